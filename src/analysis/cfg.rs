@@ -0,0 +1,374 @@
+//! Control flow graphs of KO function sections and KSM code sections.
+//!
+//! The code is split into basic blocks, which are runs of instructions that are always executed
+//! from the first to the last. A new block begins at the destination of every branch, and after
+//! every branch or instruction that ends execution, such as Ret or Eop.
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::analysis::cfg::ControlFlowGraph;
+//! use kerbalobjects::analysis::ResolvedCode;
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType};
+//! use kerbalobjects::ksm::CodeEmitter;
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut code_section = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut code_section, &mut arg_section);
+//!
+//! // if (true) { print("yes"). }
+//! emitter.push(KOSValue::Bool(true));
+//! emitter.bfa(6);
+//! emitter.push_arg_marker();
+//! emitter.push(KOSValue::StringValue("yes".into()));
+//! emitter.call("", "print()");
+//! emitter.pop();
+//! emitter.nop();
+//! emitter.eop();
+//!
+//! let code = ResolvedCode::from_code_section(&code_section, &arg_section);
+//! let cfg = ControlFlowGraph::new(&code);
+//!
+//! assert_eq!(cfg.blocks().len(), 3);
+//! assert_eq!(cfg.block(0).unwrap().successors(), &[1, 2]);
+//! assert_eq!(cfg.block(2).unwrap().predecessors(), &[0, 1]);
+//!
+//! let dominators = cfg.dominators(0);
+//! assert!(dominators.dominates(0, 2));
+//! assert!(!dominators.dominates(1, 2));
+//! # }
+//! ```
+//!
+use std::ops::Range;
+
+use crate::analysis::ResolvedCode;
+
+/// A run of instructions that are always executed in order from the first to the last
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    start: usize,
+    end: usize,
+    successors: Vec<usize>,
+    predecessors: Vec<usize>,
+}
+
+impl BasicBlock {
+    /// The index of the first instruction in this block
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The index one past the last instruction in this block
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// The range of the indexes of the instructions in this block
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The index of the last instruction in this block
+    pub fn last(&self) -> usize {
+        self.end - 1
+    }
+
+    /// The number of instructions in this block
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns true if this block contains no instructions, which never happens for blocks
+    /// created by a [ControlFlowGraph]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The indexes of the blocks that may execute directly after this one
+    pub fn successors(&self) -> &[usize] {
+        &self.successors
+    }
+
+    /// The indexes of the blocks that may execute directly before this one
+    pub fn predecessors(&self) -> &[usize] {
+        &self.predecessors
+    }
+}
+
+/// The control flow graph of a single function section or code section
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    // The index of the block each instruction belongs to
+    instr_blocks: Vec<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Splits the provided code into basic blocks, and connects them
+    pub fn new(code: &ResolvedCode) -> Self {
+        let len = code.len();
+
+        let mut leaders = vec![false; len];
+
+        if len > 0 {
+            leaders[0] = true;
+        }
+
+        for index in 0..len {
+            let opcode = code.instructions()[index].opcode();
+
+            if opcode.is_branch() || opcode.is_terminator() {
+                if index + 1 < len {
+                    leaders[index + 1] = true;
+                }
+
+                if let Some(destination) = code.branch_destination(index) {
+                    if destination < len {
+                        leaders[destination] = true;
+                    }
+                }
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut instr_blocks = vec![0; len];
+
+        for index in 0..len {
+            if leaders[index] {
+                blocks.push(BasicBlock {
+                    start: index,
+                    end: index,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                });
+            }
+
+            let block_index = blocks.len() - 1;
+            blocks[block_index].end = index + 1;
+            instr_blocks[index] = block_index;
+        }
+
+        for block_index in 0..blocks.len() {
+            let successors: Vec<usize> = code
+                .successors(blocks[block_index].last())
+                .into_iter()
+                .map(|successor| instr_blocks[successor])
+                .collect();
+
+            for &successor in successors.iter() {
+                if !blocks[successor].predecessors.contains(&block_index) {
+                    blocks[successor].predecessors.push(block_index);
+                }
+            }
+
+            blocks[block_index].successors = successors;
+        }
+
+        for block in blocks.iter_mut() {
+            block.predecessors.sort_unstable();
+        }
+
+        Self {
+            blocks,
+            instr_blocks,
+        }
+    }
+
+    /// Returns all of the basic blocks, in the order of their instructions
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Returns the basic block at the provided index, or None if it doesn't exist
+    pub fn block(&self, index: usize) -> Option<&BasicBlock> {
+        self.blocks.get(index)
+    }
+
+    /// Returns the index of the block containing the instruction at the provided index
+    pub fn block_of(&self, instr_index: usize) -> Option<usize> {
+        self.instr_blocks.get(instr_index).copied()
+    }
+
+    /// Returns the indexes of all blocks reachable from the provided block, in reverse postorder
+    pub fn reverse_postorder(&self, entry: usize) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+
+        if entry >= self.blocks.len() {
+            return postorder;
+        }
+
+        // Each entry is a block, and the index of the next successor to visit
+        let mut stack = vec![(entry, 0)];
+        visited[entry] = true;
+
+        while let Some((block, next)) = stack.pop() {
+            match self.blocks[block].successors.get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+
+        postorder.reverse();
+        postorder
+    }
+
+    /// Computes the dominators of every block reachable from the provided entry block
+    pub fn dominators(&self, entry: usize) -> Dominators {
+        let order = self.reverse_postorder(entry);
+
+        let mut order_index = vec![usize::MAX; self.blocks.len()];
+        for (position, &block) in order.iter().enumerate() {
+            order_index[block] = position;
+        }
+
+        let mut idoms: Vec<Option<usize>> = vec![None; self.blocks.len()];
+
+        if let Some(&entry) = order.first() {
+            idoms[entry] = Some(entry);
+        }
+
+        // "A Simple, Fast Dominance Algorithm" by Cooper, Harvey, and Kennedy
+        let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while order_index[a] > order_index[b] {
+                    a = idoms[a].expect("Processed block has a dominator");
+                }
+                while order_index[b] > order_index[a] {
+                    b = idoms[b].expect("Processed block has a dominator");
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &block in order.iter().skip(1) {
+                let new_idom = self.blocks[block]
+                    .predecessors
+                    .iter()
+                    .copied()
+                    .filter(|&predecessor| idoms[predecessor].is_some())
+                    .reduce(|a, b| intersect(&idoms, a, b));
+
+                if new_idom.is_some() && idoms[block] != new_idom {
+                    idoms[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        Dominators { entry, idoms }
+    }
+}
+
+/// The dominator tree of a control flow graph.
+///
+/// A block dominates another if every path from the entry block to the other block passes
+/// through it.
+#[derive(Debug, Clone)]
+pub struct Dominators {
+    entry: usize,
+    idoms: Vec<Option<usize>>,
+}
+
+impl Dominators {
+    /// Returns the entry block that these dominators were computed from
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Returns the immediate dominator of the provided block, or None if the block is the entry
+    /// block or isn't reachable from it
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        if block == self.entry {
+            return None;
+        }
+
+        self.idoms.get(block).copied().flatten()
+    }
+
+    /// Returns true if the provided block is reachable from the entry block
+    pub fn is_reachable(&self, block: usize) -> bool {
+        matches!(self.idoms.get(block), Some(Some(_)))
+    }
+
+    /// Returns true if the first block dominates the second block. Every block dominates itself.
+    pub fn dominates(&self, dominator: usize, block: usize) -> bool {
+        if !self.is_reachable(block) {
+            return false;
+        }
+
+        let mut current = block;
+
+        loop {
+            if current == dominator {
+                return true;
+            }
+
+            match self.immediate_dominator(current) {
+                Some(idom) => current = idom,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "ksm")]
+mod tests {
+    use super::*;
+    use crate::ksm::sections::{ArgumentSection, CodeSection, CodeType};
+    use crate::ksm::CodeEmitter;
+    use crate::KOSValue;
+
+    #[test]
+    fn loop_dominators() {
+        let mut arg_section = ArgumentSection::new();
+        let mut code_section = CodeSection::new(CodeType::Main);
+
+        let mut emitter = CodeEmitter::new(&mut code_section, &mut arg_section);
+
+        // 0: header
+        emitter.push(KOSValue::String("$x".into()));
+        emitter.bfa(3);
+        // 1: body
+        emitter.nop();
+        emitter.jmp(-3);
+        // 2: exit
+        emitter.eop();
+        // 3: unreachable
+        emitter.nop();
+
+        let code = ResolvedCode::from_code_section(&code_section, &arg_section);
+        let cfg = ControlFlowGraph::new(&code);
+
+        assert_eq!(cfg.blocks().len(), 4);
+        assert_eq!(cfg.block(0).unwrap().range(), 0..2);
+        assert_eq!(cfg.block(0).unwrap().predecessors(), &[1]);
+        assert_eq!(cfg.block(1).unwrap().successors(), &[0]);
+        assert_eq!(cfg.block(3).unwrap().predecessors(), &[] as &[usize]);
+        assert_eq!(cfg.block_of(3), Some(1));
+
+        assert_eq!(cfg.reverse_postorder(0), vec![0, 2, 1]);
+
+        let dominators = cfg.dominators(0);
+
+        assert_eq!(dominators.immediate_dominator(1), Some(0));
+        assert_eq!(dominators.immediate_dominator(2), Some(0));
+        assert!(!dominators.is_reachable(3));
+        assert!(!dominators.dominates(0, 3));
+        assert!(dominators.dominates(1, 1));
+    }
+}
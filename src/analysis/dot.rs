@@ -0,0 +1,498 @@
+//! Exports control flow graphs and call graphs as [Graphviz](https://graphviz.org/) DOT text.
+//!
+//! Control flow graphs are drawn with one node per basic block, which lists the block's
+//! instructions, along with source line numbers if the file has a debug section. Call graphs
+//! are drawn with one node per function, and an edge for every function that a function calls
+//! or creates a delegate of.
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::analysis::dot::{ksm_to_dot, CallGraph};
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugEntry, DebugRange, DebugSection};
+//! use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//!
+//! emitter.push_arg_marker();
+//! emitter.push(KOSValue::StringValue("Hello, world!".into()));
+//! emitter.call("", "print()");
+//! emitter.pop();
+//!
+//! let ksm_file = KSMFile::new_from_parts(
+//!     arg_section,
+//!     vec![main_code],
+//!     DebugSection::new(DebugEntry::new(1).with_range(DebugRange::new(2, 7))),
+//! );
+//!
+//! let cfg_dot = ksm_to_dot(&ksm_file);
+//! assert!(cfg_dot.contains("call \\\"\\\", \\\"print()\\\""));
+//!
+//! let call_graph = CallGraph::from_ksm_file(&ksm_file);
+//! assert!(call_graph.to_dot().contains("\"<main 0>\" -> \"print()\""));
+//! # }
+//! ```
+//!
+use std::fmt::Write;
+
+#[cfg(feature = "ko")]
+use std::collections::HashMap;
+
+use crate::analysis::cfg::ControlFlowGraph;
+use crate::analysis::ResolvedCode;
+use crate::Opcode;
+
+#[cfg(any(feature = "ko", feature = "ksm"))]
+use crate::analysis::ResolvedInstr;
+#[cfg(any(feature = "ko", feature = "ksm"))]
+use crate::KOSValue;
+
+#[cfg(feature = "ko")]
+use crate::ko::{symbols::OperandIndex, KOFile, SectionIdx};
+#[cfg(feature = "ksm")]
+use crate::ksm::{sections::CodeType, KSMFile};
+
+/// Escapes a string so that it can be placed inside of a quoted DOT string
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes the nodes and edges of a control flow graph, with every node id beginning with the prefix
+fn write_cfg_body(
+    out: &mut String,
+    prefix: &str,
+    code: &ResolvedCode,
+    cfg: &ControlFlowGraph,
+    lines: Option<&[Option<isize>]>,
+    indent: &str,
+) {
+    for (block_index, block) in cfg.blocks().iter().enumerate() {
+        let mut label = format!("block {}\\l", block_index);
+        let mut previous_line = None;
+
+        for index in block.range() {
+            let line = lines.and_then(|lines| lines.get(index).copied().flatten());
+
+            let _ = write!(
+                label,
+                "{:>4}  {}",
+                index,
+                escape(&code.instructions()[index].to_string())
+            );
+
+            if let Some(number) = line.filter(|_| line != previous_line) {
+                let _ = write!(label, "    ; line {}", number);
+            }
+
+            previous_line = line;
+            label.push_str("\\l");
+        }
+
+        let _ = writeln!(
+            out,
+            "{}\"{}b{}\" [label=\"{}\"];",
+            indent, prefix, block_index, label
+        );
+    }
+
+    for (block_index, block) in cfg.blocks().iter().enumerate() {
+        let last = &code.instructions()[block.last()];
+        let destination = code.branch_destination(block.last());
+
+        for &successor in block.successors() {
+            let is_branch = destination == Some(cfg.blocks()[successor].start());
+
+            // The edge label says which value on the stack causes the edge to be taken
+            let edge_label = match (last.opcode(), is_branch) {
+                (Opcode::Bfa, true) | (Opcode::Btr, false) => " [label=\"false\"]",
+                (Opcode::Bfa, false) | (Opcode::Btr, true) => " [label=\"true\"]",
+                _ => "",
+            };
+
+            let _ = writeln!(
+                out,
+                "{}\"{}b{}\" -> \"{}b{}\"{};",
+                indent, prefix, block_index, prefix, successor, edge_label
+            );
+        }
+    }
+}
+
+/// Converts the control flow graph of a single section's code into a DOT digraph with the
+/// provided name.
+///
+/// If provided, `lines` contains the source line number of each instruction.
+pub fn cfg_to_dot(
+    name: &str,
+    code: &ResolvedCode,
+    cfg: &ControlFlowGraph,
+    lines: Option<&[Option<isize>]>,
+) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "digraph \"{}\" {{", escape(name));
+    out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    write_cfg_body(&mut out, "", code, cfg, lines, "    ");
+
+    out.push_str("}\n");
+    out
+}
+
+/// Converts the control flow graphs of every code section in a KSM file into a single DOT
+/// digraph, with one cluster per code section, and the source line numbers from the debug
+/// section.
+#[cfg(feature = "ksm")]
+pub fn ksm_to_dot(file: &KSMFile) -> String {
+    let mut out = String::new();
+
+    out.push_str("digraph \"ksm\" {\n");
+    out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    for (section_index, (code_section, offsets)) in file
+        .code_sections()
+        .zip(file.instruction_offsets())
+        .enumerate()
+    {
+        if code_section.instructions().len() == 0 {
+            continue;
+        }
+
+        let code = ResolvedCode::from_code_section(code_section, &file.arg_section);
+        let cfg = ControlFlowGraph::new(&code);
+        let lines: Vec<Option<isize>> = offsets
+            .iter()
+            .map(|&offset| file.debug_section.line_for_offset(offset))
+            .collect();
+
+        let _ = writeln!(out, "    subgraph \"cluster_{}\" {{", section_index);
+        let _ = writeln!(
+            out,
+            "        label=\"{:?} section {}\";",
+            code_section.section_type, section_index
+        );
+
+        write_cfg_body(
+            &mut out,
+            &format!("s{}_", section_index),
+            &code,
+            &cfg,
+            Some(&lines),
+            "        ",
+        );
+
+        out.push_str("    }\n");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Converts the control flow graphs of every function section in a KO file into a single DOT
+/// digraph, with one cluster per function, using the values in the `.data` section.
+#[cfg(feature = "ko")]
+pub fn ko_to_dot(file: &KOFile) -> String {
+    let mut out = String::new();
+
+    out.push_str("digraph \"ko\" {\n");
+    out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    if let Some(data_section) = file.data_section_by_name(".data") {
+        for func_section in file.func_sections() {
+            let section_index = usize::from(func_section.section_index());
+            let name = file
+                .get_section_name_by_index(func_section.section_index())
+                .map(|name| name.as_str())
+                .unwrap_or("");
+
+            let code = ResolvedCode::from_func_section(func_section, data_section);
+            let cfg = ControlFlowGraph::new(&code);
+
+            let _ = writeln!(out, "    subgraph \"cluster_{}\" {{", section_index);
+            let _ = writeln!(out, "        label=\"{}\";", escape(name));
+
+            write_cfg_body(
+                &mut out,
+                &format!("s{}_", section_index),
+                &code,
+                &cfg,
+                None,
+                "        ",
+            );
+
+            out.push_str("    }\n");
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// How one function refers to another in a call graph
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    /// A call to a user-defined function
+    Call,
+    /// A call to a built-in kOS function, such as `print()`
+    Builtin,
+    /// A delegate of the function is created using Phdl or Pdrl, so it may be called later
+    Delegate,
+}
+
+/// An edge in a call graph
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallEdge {
+    /// The name of the function containing the call
+    pub caller: String,
+    /// The name of the function being called, or its label
+    pub callee: String,
+    /// How the callee is referred to
+    pub kind: CallKind,
+}
+
+/// The graph of which functions call which other functions
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    functions: Vec<String>,
+    edges: Vec<CallEdge>,
+}
+
+impl CallGraph {
+    /// Creates a new empty call graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a function that is defined in the program
+    pub fn add_function(&mut self, name: impl Into<String>) {
+        let name = name.into();
+
+        if !self.functions.contains(&name) {
+            self.functions.push(name);
+        }
+    }
+
+    /// Adds an edge to the graph, if it isn't already present
+    pub fn add_edge(&mut self, edge: CallEdge) {
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    /// Returns the names of all of the functions defined in the program
+    pub fn functions(&self) -> &[String] {
+        &self.functions
+    }
+
+    /// Returns all of the edges in the graph
+    pub fn edges(&self) -> &[CallEdge] {
+        &self.edges
+    }
+
+    /// Adds the edges for the instruction, if it calls or creates a delegate of a function.
+    ///
+    /// `relocated` is the name of the symbol that the first operand will be replaced with by the
+    /// linker, if there is one.
+    #[cfg(any(feature = "ko", feature = "ksm"))]
+    fn add_instr_edges(&mut self, caller: &str, instr: &ResolvedInstr, relocated: Option<&str>) {
+        let operand_name = |index: usize| -> Option<String> {
+            match instr.operand(index) {
+                Some(KOSValue::String(s) | KOSValue::StringValue(s)) if !s.is_empty() => {
+                    Some(s.clone())
+                }
+                _ => None,
+            }
+        };
+
+        let destination = relocated.map(String::from).or_else(|| operand_name(0));
+
+        let (callee, kind) = match instr.opcode() {
+            Opcode::Call => match (destination, operand_name(1)) {
+                (Some(destination), _) => (destination, CallKind::Call),
+                (None, Some(name)) if name != "<indirect>" => (name, CallKind::Builtin),
+                _ => return,
+            },
+            Opcode::Pdrl => match destination {
+                Some(destination) => (destination, CallKind::Delegate),
+                None => return,
+            },
+            Opcode::Phdl => match (relocated, instr.operand(0)) {
+                (Some(relocated), _) => (relocated.to_string(), CallKind::Delegate),
+                (None, Some(value)) => (value.to_string(), CallKind::Delegate),
+                (None, None) => return,
+            },
+            _ => return,
+        };
+
+        self.add_edge(CallEdge {
+            caller: caller.to_string(),
+            callee,
+            kind,
+        });
+    }
+
+    /// Builds the call graph of a KSM file.
+    ///
+    /// Every function in a function code section is named by the label of its first instruction.
+    /// Main and initialization code sections are named `<main N>` and `<init N>`, where N is the
+    /// index of the code section.
+    #[cfg(feature = "ksm")]
+    pub fn from_ksm_file(file: &KSMFile) -> Self {
+        let mut graph = Self::new();
+
+        for (section_index, code_section) in file.code_sections().enumerate() {
+            if code_section.instructions().len() == 0 {
+                continue;
+            }
+
+            let code = ResolvedCode::from_code_section(code_section, &file.arg_section);
+
+            let entries = match code_section.section_type {
+                CodeType::Function => code.function_entries(),
+                CodeType::Initialization | CodeType::Main => vec![0],
+            };
+
+            let mut owners: Vec<Option<usize>> = vec![None; code.len()];
+
+            for (function, &entry) in entries.iter().enumerate() {
+                let name = match code_section.section_type {
+                    CodeType::Function => (entry..code.len())
+                        .find(|&index| code.instructions()[index].opcode() != Opcode::Lbrt)
+                        .and_then(|index| code.label_of(index))
+                        .map(String::from)
+                        .unwrap_or_else(|| format!("<function {} {}>", section_index, entry)),
+                    CodeType::Initialization => format!("<init {}>", section_index),
+                    CodeType::Main => format!("<main {}>", section_index),
+                };
+
+                graph.add_function(name.clone());
+
+                owners[entry] = Some(function);
+                let mut worklist = vec![entry];
+
+                while let Some(index) = worklist.pop() {
+                    graph.add_instr_edges(&name, &code.instructions()[index], None);
+
+                    for successor in code.successors(index) {
+                        if owners[successor].is_none() {
+                            owners[successor] = Some(function);
+                            worklist.push(successor);
+                        }
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Builds the call graph of a KO file, using the values in the `.data` section.
+    ///
+    /// Every function is named by the name of its function section. Destinations that are filled
+    /// in by the linker are named by the symbol that they refer to.
+    #[cfg(feature = "ko")]
+    pub fn from_ko_file(file: &KOFile) -> Self {
+        let mut graph = Self::new();
+
+        let data_section = match file.data_section_by_name(".data") {
+            Some(data_section) => data_section,
+            None => return graph,
+        };
+
+        // The name of the symbol that the first operand of each instruction is relocated to, by
+        // section and instruction index
+        let mut relocations: HashMap<(SectionIdx, usize), &str> = HashMap::new();
+
+        for entry in file
+            .reld_sections()
+            .flat_map(|reld_section| reld_section.entries())
+            .filter(|entry| entry.operand_index == OperandIndex::One)
+        {
+            if let Some(name) = file
+                .symtab()
+                .and_then(|symtab| symtab.get(entry.symbol_index))
+                .and_then(|symbol| file.symbol_name(symbol))
+            {
+                relocations
+                    .entry((entry.section_index, usize::from(entry.instr_index)))
+                    .or_insert(name.as_str());
+            }
+        }
+
+        for func_section in file.func_sections() {
+            let section_index = func_section.section_index();
+            let name = file
+                .get_section_name_by_index(section_index)
+                .cloned()
+                .unwrap_or_default();
+
+            graph.add_function(name.clone());
+
+            let code = ResolvedCode::from_func_section(func_section, data_section);
+
+            for (index, instr) in code.instructions().iter().enumerate() {
+                let relocated = relocations.get(&(section_index, index)).copied();
+
+                graph.add_instr_edges(&name, instr, relocated);
+            }
+        }
+
+        graph
+    }
+
+    /// Converts this call graph into a DOT digraph.
+    ///
+    /// Functions that are called but not defined, such as built-in functions, are drawn with
+    /// a dashed outline, and edges for delegates are drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("digraph \"calls\" {\n");
+        out.push_str("    node [shape=box];\n");
+
+        for function in self.functions.iter() {
+            let _ = writeln!(out, "    \"{}\";", escape(function));
+        }
+
+        let mut undefined: Vec<&str> = Vec::new();
+
+        for edge in self.edges.iter() {
+            if !self.functions.contains(&edge.callee) && !undefined.contains(&&*edge.callee) {
+                undefined.push(&edge.callee);
+
+                let shape = match edge.kind {
+                    CallKind::Builtin => "ellipse",
+                    _ => "box",
+                };
+
+                let _ = writeln!(
+                    out,
+                    "    \"{}\" [shape={}, style=dashed];",
+                    escape(&edge.callee),
+                    shape
+                );
+            }
+        }
+
+        for edge in self.edges.iter() {
+            let style = match edge.kind {
+                CallKind::Delegate => " [style=dashed]",
+                _ => "",
+            };
+
+            let _ = writeln!(
+                out,
+                "    \"{}\" -> \"{}\"{};",
+                escape(&edge.caller),
+                escape(&edge.callee),
+                style
+            );
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
@@ -0,0 +1,318 @@
+//! # Code analysis
+//!
+//! A module for analyzing the instructions of KO function sections and KSM code sections.
+//!
+//! Both file formats store instructions whose operands are indexes into another section, so
+//! every analysis first resolves a section's instructions into [ResolvedCode], which holds each
+//! instruction's opcode and the values of its operands. From there, the analyses themselves
+//! don't need to care which file format the instructions came from.
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::analysis::ResolvedCode;
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType};
+//! use kerbalobjects::ksm::CodeEmitter;
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut code_section = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut code_section, &mut arg_section);
+//!
+//! emitter.push(KOSValue::Bool(true));
+//! emitter.bfa(2);
+//! emitter.nop();
+//! emitter.eop();
+//!
+//! let code = ResolvedCode::from_code_section(&code_section, &arg_section);
+//!
+//! assert_eq!(code.branch_destination(1), Some(3));
+//! assert_eq!(code.successors(1), vec![2, 3]);
+//! # }
+//! ```
+//!
+use std::collections::HashMap;
+
+use crate::{KOSValue, Opcode};
+
+#[cfg(feature = "ko")]
+use crate::ko::sections::{DataSection, FuncSection};
+#[cfg(feature = "ksm")]
+use crate::ksm::sections::{ArgumentSection, CodeSection};
+
+pub mod cfg;
+pub mod dot;
+pub mod stack;
+
+/// An instruction with its operands looked up in the data section or argument section.
+///
+/// An operand is None if it could not be found, which happens for KO file operands that are
+/// placeholders to be filled in by the linker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedInstr<'a> {
+    opcode: Opcode,
+    operands: Vec<Option<&'a KOSValue>>,
+}
+
+impl<'a> ResolvedInstr<'a> {
+    /// Creates a new resolved instruction from an opcode and the values of its operands
+    pub fn new(opcode: Opcode, operands: Vec<Option<&'a KOSValue>>) -> Self {
+        Self { opcode, operands }
+    }
+
+    /// Returns the opcode of this instruction
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// Returns the value of the operand at the provided index (starting at 0), or None if the
+    /// operand doesn't exist or couldn't be resolved
+    pub fn operand(&self, index: usize) -> Option<&'a KOSValue> {
+        self.operands.get(index).copied().flatten()
+    }
+
+    /// Returns the number of operands this instruction has
+    pub fn num_operands(&self) -> usize {
+        self.operands.len()
+    }
+}
+
+/// Formats the instruction as KASM, such as `call "", "print()"`.
+///
+/// Operands that couldn't be resolved are formatted as `?`.
+impl std::fmt::Display for ResolvedInstr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+
+        for (index, operand) in self.operands.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+
+            match operand {
+                Some(value) => write!(f, "{}{}", separator, value)?,
+                None => write!(f, "{}?", separator)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The resolved instructions of a single KO function section or KSM code section, along with
+/// the labels of the instructions.
+#[derive(Debug, Clone)]
+pub struct ResolvedCode<'a> {
+    instrs: Vec<ResolvedInstr<'a>>,
+    labels: HashMap<String, usize>,
+    instr_labels: Vec<Option<String>>,
+}
+
+impl<'a> ResolvedCode<'a> {
+    /// Creates a new ResolvedCode from a list of already resolved instructions
+    pub fn new(instrs: Vec<ResolvedInstr<'a>>) -> Self {
+        let mut labels = HashMap::new();
+        let mut instr_labels = vec![None; instrs.len()];
+        let mut next_label: Option<&str> = None;
+        let mut counter: Option<u32> = None;
+
+        // kOS gives every instruction a label. Lbrt sets the label of the instruction after it,
+        // and every other instruction's label is one more than the previous numbered label.
+        for (index, instr) in instrs.iter().enumerate() {
+            if instr.opcode() == Opcode::Lbrt {
+                if let Some(KOSValue::String(label) | KOSValue::StringValue(label)) =
+                    instr.operand(0)
+                {
+                    next_label = Some(label.as_str());
+
+                    if let Some(number) = label.strip_prefix('@').and_then(|n| n.parse().ok()) {
+                        counter = Some(number);
+                    }
+                }
+
+                continue;
+            }
+
+            let label = match next_label.take() {
+                Some(label) => Some(label.to_string()),
+                None => counter.map(|number| format!("@{:04}", number)),
+            };
+
+            if let Some(label) = label {
+                labels.insert(label.clone(), index);
+                instr_labels[index] = Some(label);
+            }
+
+            counter = counter.map(|number| number + 1);
+        }
+
+        Self {
+            instrs,
+            labels,
+            instr_labels,
+        }
+    }
+
+    /// Resolves all of the instructions of a KO function section using the provided data section
+    #[cfg(feature = "ko")]
+    pub fn from_func_section(func_section: &FuncSection, data_section: &'a DataSection) -> Self {
+        use crate::ko::Instr;
+
+        let instrs = func_section
+            .instructions()
+            .map(|instr| match *instr {
+                Instr::ZeroOp(opcode) => ResolvedInstr::new(opcode, Vec::new()),
+                Instr::OneOp(opcode, op1) => {
+                    ResolvedInstr::new(opcode, vec![data_section.get(op1)])
+                }
+                Instr::TwoOp(opcode, op1, op2) => {
+                    ResolvedInstr::new(opcode, vec![data_section.get(op1), data_section.get(op2)])
+                }
+            })
+            .collect();
+
+        Self::new(instrs)
+    }
+
+    /// Resolves all of the instructions of a KSM code section using the provided argument section
+    #[cfg(feature = "ksm")]
+    pub fn from_code_section(code_section: &CodeSection, arg_section: &'a ArgumentSection) -> Self {
+        use crate::ksm::Instr;
+
+        let instrs = code_section
+            .instructions()
+            .map(|instr| match *instr {
+                Instr::ZeroOp(opcode) => ResolvedInstr::new(opcode, Vec::new()),
+                Instr::OneOp(opcode, op1) => ResolvedInstr::new(opcode, vec![arg_section.get(op1)]),
+                Instr::TwoOp(opcode, op1, op2) => {
+                    ResolvedInstr::new(opcode, vec![arg_section.get(op1), arg_section.get(op2)])
+                }
+            })
+            .collect();
+
+        Self::new(instrs)
+    }
+
+    /// Returns the instructions in this code
+    pub fn instructions(&self) -> &[ResolvedInstr<'a>] {
+        &self.instrs
+    }
+
+    /// Returns the instruction at the provided index, or None if it doesn't exist
+    pub fn get(&self, index: usize) -> Option<&ResolvedInstr<'a>> {
+        self.instrs.get(index)
+    }
+
+    /// Returns the number of instructions in this code
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    /// Returns true if there are no instructions in this code
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+
+    /// Returns the index of the instruction with the provided label.
+    ///
+    /// Labels are either set by an Lbrt instruction, or are numbered labels like `@0012`, which
+    /// count up from the last numbered label set by an Lbrt instruction.
+    pub fn label(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).copied()
+    }
+
+    /// Returns the label of the instruction at the provided index, if it has one
+    pub fn label_of(&self, index: usize) -> Option<&str> {
+        self.instr_labels.get(index)?.as_deref()
+    }
+
+    /// Returns the indexes of the instructions that begin each function, if this code is made up
+    /// of functions.
+    ///
+    /// The first instruction begins a function, and every instruction that isn't reachable from a
+    /// previous function begins another one.
+    pub fn function_entries(&self) -> Vec<usize> {
+        let mut entries = Vec::new();
+        let mut visited = vec![false; self.instrs.len()];
+
+        for entry in 0..self.instrs.len() {
+            if visited[entry] {
+                continue;
+            }
+
+            entries.push(entry);
+            visited[entry] = true;
+
+            let mut worklist = vec![entry];
+
+            while let Some(index) = worklist.pop() {
+                for successor in self.successors(index) {
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        worklist.push(successor);
+                    }
+                }
+            }
+        }
+
+        entries
+    }
+
+    /// Returns the index of the instruction that the branch instruction at the provided index
+    /// branches to.
+    ///
+    /// Returns None if the instruction isn't a branch, or if the destination couldn't be resolved
+    /// to an instruction in this code. Destinations can be relative offsets, or labels.
+    ///
+    /// A branch to the instruction just past the end of the code is valid, because execution
+    /// continues on into the next code section, so this can return [len](Self::len).
+    pub fn branch_destination(&self, index: usize) -> Option<usize> {
+        let instr = self.instrs.get(index)?;
+
+        if !instr.opcode().is_branch() {
+            return None;
+        }
+
+        let offset = match instr.operand(0)? {
+            KOSValue::Byte(offset) => *offset as i64,
+            KOSValue::Int16(offset) => *offset as i64,
+            KOSValue::Int32(offset) | KOSValue::ScalarInt(offset) => *offset as i64,
+            KOSValue::String(label) | KOSValue::StringValue(label) => {
+                return self.label(label);
+            }
+            _ => return None,
+        };
+
+        let destination = index as i64 + offset;
+
+        if destination >= 0 && (destination as usize) <= self.instrs.len() {
+            Some(destination as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the indexes of the instructions that may execute directly after the instruction
+    /// at the provided index.
+    ///
+    /// Branch destinations that couldn't be resolved, or that are past the end of the code, are
+    /// not included.
+    pub fn successors(&self, index: usize) -> Vec<usize> {
+        let mut successors = Vec::with_capacity(2);
+
+        let opcode = match self.instrs.get(index) {
+            Some(instr) => instr.opcode(),
+            None => return successors,
+        };
+
+        if !opcode.is_terminator() && index + 1 < self.instrs.len() {
+            successors.push(index + 1);
+        }
+
+        if let Some(destination) = self.branch_destination(index) {
+            if destination < self.instrs.len() && !successors.contains(&destination) {
+                successors.push(destination);
+            }
+        }
+
+        successors
+    }
+}
@@ -0,0 +1,434 @@
+//! A static verifier that computes the depth of the stack before every instruction.
+//!
+//! The verifier follows every path through the code, applying the stack effect of each
+//! instruction, which finds:
+//!
+//! * Instructions that consume more values than are on the stack
+//! * Instructions that can be reached with different stack depths
+//! * Functions that return while leaving values other than their return value on the stack
+//! * Calls that have no argument marker on the stack
+//!
+//! Both programs and functions are called by kOS with an argument marker on the stack, followed
+//! by any arguments. Code may consume values from below the depth it started at, which are its
+//! arguments, until an Argb instruction has checked that the argument marker is on the top of
+//! the stack. After that, consuming values that aren't there is an underflow.
+//!
+//! Depths are reported relative to the depth when the code started executing, so they can be
+//! negative after arguments have been consumed.
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::analysis::stack::{verify_stack, EntryKind};
+//! use kerbalobjects::analysis::ResolvedCode;
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType};
+//! use kerbalobjects::ksm::CodeEmitter;
+//! use kerbalobjects::{KOSValue, StackError};
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut code_section = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut code_section, &mut arg_section);
+//!
+//! emitter.bscp(1, 0);
+//! emitter.argb();
+//! emitter.push(KOSValue::ScalarInt(2));
+//! emitter.add();
+//! emitter.eop();
+//!
+//! let code = ResolvedCode::from_code_section(&code_section, &arg_section);
+//! let report = verify_stack(&code, EntryKind::Program);
+//!
+//! assert_eq!(report.depth_before(3), Some(1));
+//! assert_eq!(report.diagnostics()[0].error, StackError::Underflow(kerbalobjects::Opcode::Add, 2, 1));
+//! # }
+//! ```
+//!
+use crate::analysis::ResolvedCode;
+use crate::{KOSValue, Opcode, StackError};
+
+#[cfg(feature = "ko")]
+use crate::ko::KOFile;
+#[cfg(feature = "ksm")]
+use crate::ksm::{sections::CodeType, KSMFile};
+
+/// How the code being verified begins executing
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    /// The code is the main or initialization code of a program, which only begins executing at
+    /// its first instruction. Unreachable instructions are not verified.
+    Program,
+    /// The code is made up of one or more functions. Every instruction that isn't reachable
+    /// from a previous function is treated as the start of a new function.
+    Function,
+}
+
+/// A problem found by the verifier, along with where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackDiagnostic {
+    /// The index of the section the problem was found in, if more than one section was verified.
+    ///
+    /// For KSM files this is the index of the code section, and for KO files this is the
+    /// section header index of the function section.
+    pub section: Option<usize>,
+    /// The index of the instruction within its section
+    pub instr_index: usize,
+    /// The source code line number of the instruction, if there is debug information for it
+    pub line: Option<isize>,
+    /// The problem that was found
+    pub error: StackError,
+}
+
+impl StackDiagnostic {
+    fn new(instr_index: usize, error: StackError) -> Self {
+        Self {
+            section: None,
+            instr_index,
+            line: None,
+            error,
+        }
+    }
+}
+
+/// The result of verifying a section's code
+#[derive(Debug, Clone)]
+pub struct StackReport {
+    depths: Vec<Option<isize>>,
+    diagnostics: Vec<StackDiagnostic>,
+}
+
+impl StackReport {
+    /// Returns the depth of the stack before the instruction at the provided index executes,
+    /// relative to the depth when the code started executing.
+    ///
+    /// Returns None if the instruction is never reached.
+    pub fn depth_before(&self, index: usize) -> Option<isize> {
+        self.depths.get(index).copied().flatten()
+    }
+
+    /// Returns all of the problems that were found
+    pub fn diagnostics(&self) -> &[StackDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// Consumes this report, returning all of the problems that were found
+    pub fn into_diagnostics(self) -> Vec<StackDiagnostic> {
+        self.diagnostics
+    }
+
+    /// Returns true if no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    Value,
+    Marker,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AbstractStack {
+    slots: Vec<Slot>,
+    // The number of values consumed from below where the code started, which are arguments
+    consumed: usize,
+    // Set once Argb has checked that the argument marker is directly below the values pushed
+    // by this code, after which consuming values from below is an underflow
+    marker_checked: bool,
+}
+
+impl PartialEq for AbstractStack {
+    fn eq(&self, other: &Self) -> bool {
+        self.slots == other.slots && self.consumed == other.consumed
+    }
+}
+
+impl AbstractStack {
+    fn depth(&self) -> isize {
+        self.slots.len() as isize - self.consumed as isize
+    }
+
+    fn pop(&mut self) -> Slot {
+        match self.slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.consumed += 1;
+                Slot::Value
+            }
+        }
+    }
+
+    fn peek(&self) -> Slot {
+        self.slots.last().copied().unwrap_or(Slot::Value)
+    }
+
+    fn apply(&mut self, opcode: Opcode, operand: Option<&KOSValue>) -> Result<(), StackError> {
+        if let Some(pops) = opcode.pops() {
+            if self.marker_checked && self.slots.len() < pops {
+                return Err(StackError::Underflow(opcode, pops, self.slots.len()));
+            }
+        }
+
+        match opcode {
+            Opcode::Call => {
+                // Consumes the arguments, and a delegate if there is one, down to the marker
+                loop {
+                    match self.slots.pop() {
+                        Some(Slot::Marker) => break,
+                        Some(Slot::Value) => {}
+                        None => return Err(StackError::MissingArgMarker),
+                    }
+                }
+
+                self.slots.push(Slot::Value);
+            }
+            Opcode::Push | Opcode::Pushv => {
+                self.slots.push(match operand {
+                    Some(KOSValue::ArgMarker) => Slot::Marker,
+                    _ => Slot::Value,
+                });
+            }
+            Opcode::Dup => {
+                let top = self.peek();
+                self.slots.push(top);
+            }
+            Opcode::Argb => match self.slots.last() {
+                Some(Slot::Marker) => {}
+                Some(Slot::Value) => return Err(StackError::MissingArgMarker),
+                None => self.marker_checked = true,
+            },
+            Opcode::Targ => {
+                self.peek();
+                self.slots.push(Slot::Value);
+            }
+            Opcode::Swap => {
+                let first = self.pop();
+                let second = self.pop();
+                self.slots.push(first);
+                self.slots.push(second);
+            }
+            Opcode::Ret => {
+                self.pop();
+
+                if !self.slots.is_empty() {
+                    return Err(StackError::LeftoverValues(self.slots.len()));
+                }
+            }
+            _ => {
+                for _ in 0..opcode.pops().unwrap_or(0) {
+                    self.pop();
+                }
+
+                for _ in 0..opcode.pushes() {
+                    self.slots.push(Slot::Value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Verifies the stack usage of the provided code, following every path through it.
+pub fn verify_stack(code: &ResolvedCode, kind: EntryKind) -> StackReport {
+    let len = code.len();
+
+    let mut states: Vec<Option<AbstractStack>> = vec![None; len];
+    let mut reported_join = vec![false; len];
+    let mut diagnostics = Vec::new();
+
+    let mut next_entry = if len > 0 { Some(0) } else { None };
+
+    while let Some(entry) = next_entry {
+        states[entry] = Some(AbstractStack::default());
+        let mut worklist = vec![entry];
+
+        while let Some(index) = worklist.pop() {
+            let instr = &code.instructions()[index];
+            let mut state = states[index]
+                .clone()
+                .expect("Visited instruction has a state");
+
+            if let Err(error) = state.apply(instr.opcode(), instr.operand(0)) {
+                diagnostics.push(StackDiagnostic::new(index, error));
+                continue;
+            }
+
+            if instr.opcode().is_branch() && code.branch_destination(index).is_none() {
+                diagnostics.push(StackDiagnostic::new(index, StackError::UnresolvedBranch));
+            }
+
+            for successor in code.successors(index) {
+                match &states[successor] {
+                    None => {
+                        states[successor] = Some(state.clone());
+                        worklist.push(successor);
+                    }
+                    Some(existing) if *existing != state && !reported_join[successor] => {
+                        reported_join[successor] = true;
+                        diagnostics.push(StackDiagnostic::new(
+                            successor,
+                            StackError::InconsistentDepth(existing.depth(), state.depth()),
+                        ));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        next_entry = match kind {
+            EntryKind::Program => None,
+            EntryKind::Function => (entry + 1..len).find(|&index| states[index].is_none()),
+        };
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.instr_index);
+
+    StackReport {
+        depths: states
+            .into_iter()
+            .map(|state| state.map(|state| state.depth()))
+            .collect(),
+        diagnostics,
+    }
+}
+
+/// Verifies every code section in a KSM file, tagging each problem with the line number from the
+/// debug section if there is one.
+///
+/// Function code sections are verified as functions, and all other code sections are verified
+/// as programs.
+#[cfg(feature = "ksm")]
+pub fn verify_ksm_file(file: &KSMFile) -> Vec<StackDiagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (section_index, (code_section, offsets)) in file
+        .code_sections()
+        .zip(file.instruction_offsets())
+        .enumerate()
+    {
+        let kind = match code_section.section_type {
+            CodeType::Function => EntryKind::Function,
+            CodeType::Initialization | CodeType::Main => EntryKind::Program,
+        };
+
+        let code = ResolvedCode::from_code_section(code_section, &file.arg_section);
+
+        for mut diagnostic in verify_stack(&code, kind).into_diagnostics() {
+            diagnostic.section = Some(section_index);
+            diagnostic.line = file
+                .debug_section
+                .line_for_offset(offsets[diagnostic.instr_index]);
+
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics
+}
+
+/// Verifies every function section in a KO file, using the values in the `.data` section.
+///
+/// The `_start` and `_init` functions are verified as programs, because the linker places them in
+/// the main and initialization code sections, and all other functions are verified as functions.
+#[cfg(feature = "ko")]
+pub fn verify_ko_file(file: &KOFile) -> Vec<StackDiagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let data_section = match file.data_section_by_name(".data") {
+        Some(data_section) => data_section,
+        None => return diagnostics,
+    };
+
+    for func_section in file.func_sections() {
+        let kind = match file
+            .get_section_name_by_index(func_section.section_index())
+            .map(|name| name.as_str())
+        {
+            Some("_start") | Some("_init") => EntryKind::Program,
+            _ => EntryKind::Function,
+        };
+
+        let code = ResolvedCode::from_func_section(func_section, data_section);
+
+        for mut diagnostic in verify_stack(&code, kind).into_diagnostics() {
+            diagnostic.section = Some(usize::from(func_section.section_index()));
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+#[cfg(feature = "ksm")]
+mod tests {
+    use super::*;
+    use crate::ksm::sections::{ArgumentSection, CodeSection};
+    use crate::ksm::CodeEmitter;
+
+    fn verify(kind: EntryKind, f: impl FnOnce(&mut CodeEmitter)) -> StackReport {
+        let mut arg_section = ArgumentSection::new();
+        let mut code_section = CodeSection::new(CodeType::Function);
+
+        f(&mut CodeEmitter::new(&mut code_section, &mut arg_section));
+
+        verify_stack(
+            &ResolvedCode::from_code_section(&code_section, &arg_section),
+            kind,
+        )
+    }
+
+    #[test]
+    fn call_consumes_arguments() {
+        let report = verify(EntryKind::Program, |e| {
+            e.push_arg_marker();
+            e.push(KOSValue::ScalarInt(1));
+            e.push(KOSValue::ScalarInt(2));
+            e.call("", "print()");
+            e.pop();
+            e.eop();
+        });
+
+        assert!(report.is_ok());
+        assert_eq!(report.depth_before(3), Some(3));
+        assert_eq!(report.depth_before(5), Some(0));
+    }
+
+    #[test]
+    fn inconsistent_join() {
+        let report = verify(EntryKind::Program, |e| {
+            e.push(KOSValue::Bool(true));
+            e.bfa(2);
+            e.push(KOSValue::ScalarInt(1));
+            e.eop();
+        });
+
+        assert_eq!(
+            report.diagnostics(),
+            &[StackDiagnostic::new(3, StackError::InconsistentDepth(0, 1))]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let report = verify(EntryKind::Function, |e| {
+            // The first function takes one argument, and returns it
+            e.stol("$x");
+            e.argb();
+            e.push(KOSValue::String("$x".into()));
+            e.ret(0);
+            // The second function leaves an extra value
+            e.push(KOSValue::ScalarInt(1));
+            e.push(KOSValue::ScalarInt(2));
+            e.ret(0);
+        });
+
+        assert_eq!(report.depth_before(3), Some(0));
+        assert_eq!(report.depth_before(4), Some(0));
+        assert_eq!(
+            report.diagnostics(),
+            &[StackDiagnostic::new(6, StackError::LeftoverValues(1))]
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::str::FromStr;
 
-use crate::{KOSValueParseError, OpcodeParseError};
+use crate::{KOSValueParseError, MnemonicParseError, OpcodeParseError};
 
 /// A struct to iterate over the bytes of a buffer, and keep track of the current position
 /// for better error messages
@@ -310,6 +311,26 @@ impl Hash for KOSValue {
     }
 }
 
+/// Formats the value the way it would be written as an operand in KASM.
+///
+/// Both versions of each type are formatted the same way, so `Int32(2)` and `ScalarInt(2)` are
+/// both formatted as `2`.
+impl std::fmt::Display for KOSValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KOSValue::Null => write!(f, "null"),
+            KOSValue::Bool(b) | KOSValue::BoolValue(b) => write!(f, "{}", b),
+            KOSValue::Byte(i) => write!(f, "{}", i),
+            KOSValue::Int16(i) => write!(f, "{}", i),
+            KOSValue::Int32(i) | KOSValue::ScalarInt(i) => write!(f, "{}", i),
+            KOSValue::Float(d) => write!(f, "{:?}", d),
+            KOSValue::Double(d) | KOSValue::ScalarDouble(d) => write!(f, "{:?}", d),
+            KOSValue::String(s) | KOSValue::StringValue(s) => write!(f, "{:?}", s),
+            KOSValue::ArgMarker => write!(f, "#"),
+        }
+    }
+}
+
 impl PartialEq for KOSValue {
     fn eq(&self, other: &Self) -> bool {
         let mut hasher_1 = DefaultHasher::new();
@@ -827,6 +848,168 @@ impl Opcode {
             Opcode::Bogus => 0,
         }
     }
+
+    /// Returns the number of values that this instruction consumes from the stack, or None if the
+    /// number depends on the values on the stack.
+    ///
+    /// Call instructions consume all of the arguments down to the argument marker, and so are
+    /// the only instructions that return None. Instructions that only peek at the top of the
+    /// stack, such as Argb, are described as popping the value and pushing it back.
+    pub fn pops(&self) -> Option<usize> {
+        Some(match self {
+            Opcode::Eof | Opcode::Eop | Opcode::Nop => 0,
+            Opcode::Sto | Opcode::Stol | Opcode::Stog | Opcode::Stoe | Opcode::Uns => 1,
+            Opcode::Gmb | Opcode::Gmet => 1,
+            Opcode::Smb => 2,
+            Opcode::Gidx => 2,
+            Opcode::Sidx => 3,
+            Opcode::Bfa | Opcode::Btr => 1,
+            Opcode::Jmp => 0,
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Pow => 2,
+            Opcode::Cgt | Opcode::Clt | Opcode::Cge | Opcode::Cle | Opcode::Ceq | Opcode::Cne => 2,
+            Opcode::Neg | Opcode::Bool | Opcode::Not => 1,
+            Opcode::And | Opcode::Or => 2,
+            Opcode::Call => return None,
+            Opcode::Ret => 1,
+            Opcode::Push | Opcode::Pushv => 0,
+            Opcode::Pop => 1,
+            Opcode::Dup => 1,
+            Opcode::Swap => 2,
+            Opcode::Eval => 1,
+            Opcode::Addt | Opcode::Rmvt | Opcode::Wait => 1,
+            Opcode::Bscp | Opcode::Escp => 0,
+            Opcode::Phdl => 0,
+            Opcode::Exst => 1,
+            Opcode::Argb | Opcode::Targ => 1,
+            Opcode::Tcan => 0,
+            Opcode::Prl | Opcode::Pdrl => 0,
+            Opcode::Lbrt => 0,
+            Opcode::Bogus => 0,
+        })
+    }
+
+    /// Returns the number of values that this instruction pushes onto the stack.
+    ///
+    /// Call instructions always push the return value of the function that was called.
+    pub fn pushes(&self) -> usize {
+        match self {
+            Opcode::Gmb | Opcode::Gmet | Opcode::Gidx => 1,
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Pow => 1,
+            Opcode::Cgt | Opcode::Clt | Opcode::Cge | Opcode::Cle | Opcode::Ceq | Opcode::Cne => 1,
+            Opcode::Neg | Opcode::Bool | Opcode::Not => 1,
+            Opcode::And | Opcode::Or => 1,
+            Opcode::Call => 1,
+            Opcode::Push | Opcode::Pushv => 1,
+            Opcode::Dup => 2,
+            Opcode::Swap => 2,
+            Opcode::Eval => 1,
+            Opcode::Phdl => 1,
+            Opcode::Exst => 1,
+            Opcode::Argb => 1,
+            Opcode::Targ => 2,
+            Opcode::Tcan => 1,
+            Opcode::Prl | Opcode::Pdrl => 1,
+            _ => 0,
+        }
+    }
+
+    /// Returns true if this instruction can transfer execution to an instruction other than the
+    /// next one, using a destination stored in its operand.
+    pub fn is_branch(&self) -> bool {
+        matches!(self, Opcode::Bfa | Opcode::Btr | Opcode::Jmp)
+    }
+
+    /// Returns true if this instruction only branches depending on a value on the stack
+    pub fn is_conditional_branch(&self) -> bool {
+        matches!(self, Opcode::Bfa | Opcode::Btr)
+    }
+
+    /// Returns true if this instruction returns from a function
+    pub fn is_return(&self) -> bool {
+        matches!(self, Opcode::Ret)
+    }
+
+    /// Returns true if this instruction calls a function
+    pub fn is_call(&self) -> bool {
+        matches!(self, Opcode::Call)
+    }
+
+    /// Returns true if execution never continues on to the next instruction after this one
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Opcode::Jmp | Opcode::Ret | Opcode::Eop | Opcode::Eof)
+    }
+
+    /// Returns true if the operand at the provided index (starting at 0) of this instruction
+    /// is a branch destination, which is either a label or an offset relative to this instruction
+    /// measured in instructions.
+    pub fn is_jump_offset(&self, operand_index: usize) -> bool {
+        self.is_branch() && operand_index == 0
+    }
+
+    /// Returns the mnemonic used to refer to this opcode in KASM
+    pub fn mnemonic(&self) -> &'static str {
+        (*self).into()
+    }
+
+    /// Returns a short human-readable description of what this instruction does
+    pub fn description(&self) -> &'static str {
+        match self {
+            Opcode::Bogus => "An unrecognized opcode",
+            Opcode::Eof => "Stops executing for this cycle",
+            Opcode::Eop => "Aborts the current program and returns to the interpreter",
+            Opcode::Nop => "Does nothing",
+            Opcode::Sto => "Stores the top of the stack in the variable named by the operand",
+            Opcode::Uns => "Unsets the variable named by the top of the stack",
+            Opcode::Gmb => "Gets the suffix named by the operand of the value on the stack",
+            Opcode::Smb => "Sets the suffix named by the operand of an object on the stack",
+            Opcode::Gidx => "Gets the value at an index of a collection on the stack",
+            Opcode::Sidx => "Sets the value at an index of a collection on the stack",
+            Opcode::Bfa => "Branches to the destination if the top of the stack is false",
+            Opcode::Jmp => "Unconditionally branches to the destination",
+            Opcode::Add => "Adds two values on the stack, or concatenates strings",
+            Opcode::Sub => "Subtracts two values on the stack",
+            Opcode::Mul => "Multiplies two values on the stack",
+            Opcode::Div => "Divides two values on the stack",
+            Opcode::Pow => "Raises a value on the stack to the power of another",
+            Opcode::Cgt => "Compares if a value is greater than another",
+            Opcode::Clt => "Compares if a value is less than another",
+            Opcode::Cge => "Compares if a value is greater than or equal to another",
+            Opcode::Cle => "Compares if a value is less than or equal to another",
+            Opcode::Ceq => "Compares if two values are equal",
+            Opcode::Cne => "Compares if two values are not equal",
+            Opcode::Neg => "Negates the value on the stack",
+            Opcode::Bool => "Converts the value on the stack into a boolean",
+            Opcode::Not => "Logically negates the value on the stack",
+            Opcode::And => "Performs a logical AND of two values on the stack",
+            Opcode::Or => "Performs a logical OR of two values on the stack",
+            Opcode::Call => "Calls a function, consuming its arguments and pushing the result",
+            Opcode::Ret => "Returns from a function, popping the provided number of scopes",
+            Opcode::Push => "Pushes the operand onto the stack",
+            Opcode::Pop => "Discards the top value of the stack",
+            Opcode::Dup => "Duplicates the top value of the stack",
+            Opcode::Swap => "Swaps the top two values of the stack",
+            Opcode::Eval => "Replaces the variable on the top of the stack with its value",
+            Opcode::Addt => "Adds a trigger using the function pointer on the stack",
+            Opcode::Rmvt => "Removes the triggers that call the function pointer on the stack",
+            Opcode::Wait => "Waits for the number of seconds on the stack",
+            Opcode::Gmet => "Gets the method suffix named by the operand of the value on the stack",
+            Opcode::Stol => "Stores the top of the stack in a new local variable",
+            Opcode::Stog => "Stores the top of the stack in a global variable",
+            Opcode::Bscp => "Begins a new variable scope",
+            Opcode::Escp => "Ends the provided number of variable scopes",
+            Opcode::Stoe => "Stores the top of the stack in an existing variable",
+            Opcode::Phdl => "Pushes a function delegate onto the stack",
+            Opcode::Btr => "Branches to the destination if the top of the stack is true",
+            Opcode::Exst => "Tests if the variable named by the top of the stack exists",
+            Opcode::Argb => "Asserts that the top of the stack is an argument marker",
+            Opcode::Targ => "Tests if the top of the stack is an argument marker",
+            Opcode::Tcan => "Tests if the current trigger has been cancelled",
+            Opcode::Prl => "Pushes a value that is relocated after the file is loaded",
+            Opcode::Pdrl => "Pushes a function delegate that is relocated after the file is loaded",
+            Opcode::Lbrt => "Sets the label of the next instruction",
+            Opcode::Pushv => "Pushes the value version of the operand onto the stack",
+        }
+    }
 }
 
 impl From<u8> for Opcode {
@@ -1019,6 +1202,19 @@ impl From<&str> for Opcode {
     }
 }
 
+impl FromStr for Opcode {
+    type Err = MnemonicParseError;
+
+    /// Parses an opcode from its KASM mnemonic. Unlike `From<&str>`, this returns an
+    /// error instead of Opcode::Bogus if the mnemonic is not recognized.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Opcode::from(s) {
+            Opcode::Bogus => Err(MnemonicParseError(s.to_string())),
+            opcode => Ok(opcode),
+        }
+    }
+}
+
 impl From<Opcode> for &str {
     fn from(opcode: Opcode) -> Self {
         match opcode {
@@ -1104,9 +1300,48 @@ impl FromBytes for Opcode {
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn opcode_from_str() {
+        assert_eq!("push".parse::<Opcode>(), Ok(Opcode::Push));
+        assert_eq!("lbrt".parse::<Opcode>(), Ok(Opcode::Lbrt));
+        assert_eq!(
+            "psuh".parse::<Opcode>(),
+            Err(MnemonicParseError("psuh".into()))
+        );
+        assert!("bogus".parse::<Opcode>().is_err());
+    }
+
+    #[test]
+    fn opcode_metadata() {
+        for byte in 0..=u8::MAX {
+            let opcode = Opcode::from(byte);
+
+            if opcode == Opcode::Bogus {
+                continue;
+            }
+
+            assert_eq!(opcode.mnemonic().parse::<Opcode>(), Ok(opcode));
+            assert!(!opcode.description().is_empty());
+
+            if opcode.is_branch() {
+                assert_eq!(opcode.num_operands(), 1);
+                assert!(opcode.is_jump_offset(0));
+            } else {
+                assert!(!opcode.is_jump_offset(0));
+            }
+        }
+
+        assert_eq!(Opcode::Add.pops(), Some(2));
+        assert_eq!(Opcode::Add.pushes(), 1);
+        assert_eq!(Opcode::Call.pops(), None);
+        assert!(Opcode::Jmp.is_terminator());
+        assert!(!Opcode::Bfa.is_terminator());
+    }
+
     #[test]
     fn null_to_bytes() {
         let v = KOSValue::Null;
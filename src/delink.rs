@@ -0,0 +1,269 @@
+//! # Delinking
+//!
+//! A module for turning a KSM file back into a KO file, so that the functions of a program that
+//! was already compiled, such as by kOS itself, can be linked into new programs.
+//!
+//! Every function code section becomes a local function section, named after the label of its
+//! first instruction. All of the initialization code sections are joined into one global `_init`
+//! function, and all of the main code sections are joined into one global `_start` function, in
+//! the same order that kOS would run them. The argument section becomes the `.data` section.
+//!
+//! Branches are relative, so they don't need to change. Operands that name one of the functions
+//! by its label become relocations to its symbol, so that several delinked files can be linked
+//! together even if their labels are the same. Phdl refers to its function by the absolute index
+//! of an instruction instead, so files that contain it can't be delinked.
+//!
+//! KO files don't store line numbers, so the line number of each instruction is returned
+//! separately as a [DelinkedFunction].
+//!
+//! ```
+//! use kerbalobjects::delink::delink;
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+//! use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut function_code = CodeSection::new(CodeType::Function);
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut function_code, &mut arg_section);
+//! emitter.lbrt("greet");
+//! emitter.push(KOSValue::Int16(0));
+//! emitter.ret(0);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//! emitter.push_arg_marker();
+//! emitter.call("greet", "");
+//! emitter.eop();
+//!
+//! let ksm = KSMFile::new_from_parts(arg_section, vec![function_code, main_code], DebugSection::new_empty());
+//!
+//! let delinked = delink(&ksm, "greet.ksm").expect("Could not delink file");
+//!
+//! let names: Vec<&str> = delinked.functions().iter().map(|f| f.name()).collect();
+//! assert_eq!(names, vec!["greet", "_start"]);
+//!
+//! let ko = delinked.into_ko_file();
+//! assert!(ko.get().func_section_by_symbol_name("greet").is_some());
+//! ```
+//!
+use std::collections::HashMap;
+
+use crate::errors::DelinkError;
+use crate::ko::sections::DataIdx;
+use crate::ko::symbols::{OperandIndex, SymBind};
+use crate::ko::{FuncHandle, KOFileBuilder, SectionIdx, WritableKOFile};
+use crate::ksm::sections::{ArgIndex, CodeSection, CodeType};
+use crate::ksm::KSMFile;
+use crate::{KOSValue, Opcode};
+
+/// A function section that was created from the code of a KSM file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelinkedFunction {
+    name: String,
+    section_index: SectionIdx,
+    lines: Vec<Option<isize>>,
+}
+
+impl DelinkedFunction {
+    /// The name of this function's symbol
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The index of this function's section header in the KO file
+    pub fn section_index(&self) -> SectionIdx {
+        self.section_index
+    }
+
+    /// The source line number of each of this function's instructions, if the KSM file's debug
+    /// section had one
+    pub fn lines(&self) -> &[Option<isize>] {
+        &self.lines
+    }
+}
+
+/// The result of delinking a KSM file
+#[derive(Debug)]
+pub struct Delinked {
+    ko: WritableKOFile,
+    functions: Vec<DelinkedFunction>,
+}
+
+impl Delinked {
+    /// The KO file that was created
+    pub fn ko_file(&self) -> &WritableKOFile {
+        &self.ko
+    }
+
+    /// Consumes this result, returning the KO file that was created
+    pub fn into_ko_file(self) -> WritableKOFile {
+        self.ko
+    }
+
+    /// The functions that were created, in the order of their sections
+    pub fn functions(&self) -> &[DelinkedFunction] {
+        &self.functions
+    }
+}
+
+/// Returns the label of the first instruction in the code section that isn't an Lbrt, if any
+fn function_name(file: &KSMFile, code_section: &CodeSection) -> Option<String> {
+    let code = crate::analysis::ResolvedCode::from_code_section(code_section, &file.arg_section);
+
+    (0..code.len())
+        .find(|&index| code.instructions()[index].opcode() != Opcode::Lbrt)
+        .and_then(|index| code.label_of(index))
+        .map(String::from)
+}
+
+/// Converts a KSM file into a KO file. See the [module documentation](self) for how the code is
+/// split into functions.
+///
+/// `source_name` is used as the name of the KO file's file symbol.
+pub fn delink(file: &KSMFile, source_name: impl Into<String>) -> Result<Delinked, DelinkError> {
+    let mut builder = KOFileBuilder::new();
+    builder.set_source_file(source_name);
+
+    let offsets = file.instruction_offsets();
+
+    // Every code section that has instructions, grouped by the function it becomes
+    let mut groups: Vec<(String, CodeType, Vec<usize>)> = Vec::new();
+
+    for (section_index, code_section) in file.code_sections().enumerate() {
+        if code_section.instructions().len() == 0 {
+            continue;
+        }
+
+        let name = match code_section.section_type {
+            CodeType::Function => function_name(file, code_section)
+                .unwrap_or_else(|| format!("function.{}", section_index)),
+            CodeType::Initialization => String::from("_init"),
+            CodeType::Main => String::from("_start"),
+        };
+
+        match groups.iter_mut().find(|(group_name, _, _)| {
+            *group_name == name && code_section.section_type != CodeType::Function
+        }) {
+            Some((_, _, sections)) => sections.push(section_index),
+            None => groups.push((name, code_section.section_type, vec![section_index])),
+        }
+    }
+
+    let code_sections: Vec<&CodeSection> = file.code_sections().collect();
+    let mut data_indexes: HashMap<ArgIndex, DataIdx> = HashMap::new();
+    let mut functions = Vec::with_capacity(groups.len());
+
+    // Only the entry points are global, so that the functions of several delinked files don't
+    // clash when they are linked together
+    let handles = groups
+        .iter()
+        .map(|(name, section_type, _)| {
+            let bind = match section_type {
+                CodeType::Function => SymBind::Local,
+                CodeType::Initialization | CodeType::Main => SymBind::Global,
+            };
+
+            builder.define_function(name.as_str(), bind)
+        })
+        .collect::<Result<Vec<FuncHandle>, _>>()
+        .map_err(DelinkError::BuilderError)?;
+
+    let local_functions: HashMap<&str, FuncHandle> = groups
+        .iter()
+        .zip(handles.iter().copied())
+        .filter(|((_, section_type, _), _)| *section_type == CodeType::Function)
+        .map(|((name, _, _), func)| (name.as_str(), func))
+        .collect();
+
+    for ((name, _, sections), &func) in groups.iter().zip(handles.iter()) {
+        let mut lines = Vec::new();
+
+        for &section_index in sections {
+            for (instr_index, instr) in code_sections[section_index].instructions().enumerate() {
+                if instr.opcode() == Opcode::Phdl {
+                    return Err(DelinkError::AbsoluteIndexError(section_index, instr_index));
+                }
+
+                let mut convert = |arg_index: ArgIndex| -> Result<DataIdx, DelinkError> {
+                    if let Some(&data_index) = data_indexes.get(&arg_index) {
+                        return Ok(data_index);
+                    }
+
+                    let value = file
+                        .arg_section
+                        .get(arg_index)
+                        .ok_or(DelinkError::InvalidOperandError(section_index, instr_index))?;
+
+                    let data_index = builder.add_data(value.clone());
+                    data_indexes.insert(arg_index, data_index);
+
+                    Ok(data_index)
+                };
+
+                let ko_instr = match *instr {
+                    crate::ksm::Instr::ZeroOp(opcode) => crate::ko::Instr::ZeroOp(opcode),
+                    crate::ksm::Instr::OneOp(opcode, op1) => {
+                        crate::ko::Instr::OneOp(opcode, convert(op1)?)
+                    }
+                    crate::ksm::Instr::TwoOp(opcode, op1, op2) => {
+                        crate::ko::Instr::TwoOp(opcode, convert(op1)?, convert(op2)?)
+                    }
+                };
+
+                let ko_index = builder.add_instr(func, ko_instr);
+
+                // Labels and calls by label become relocations, so that they still agree if the
+                // linker has to rename a function's label
+                if matches!(
+                    instr.opcode(),
+                    Opcode::Lbrt | Opcode::Call | Opcode::Pdrl | Opcode::Prl
+                ) {
+                    let operands = match *instr {
+                        crate::ksm::Instr::ZeroOp(_) => vec![],
+                        crate::ksm::Instr::OneOp(_, op1) => vec![(OperandIndex::One, op1)],
+                        crate::ksm::Instr::TwoOp(_, op1, op2) => {
+                            vec![(OperandIndex::One, op1), (OperandIndex::Two, op2)]
+                        }
+                    };
+
+                    for (operand_index, arg_index) in operands {
+                        let callee =
+                            file.arg_section
+                                .get(arg_index)
+                                .and_then(|value| match value {
+                                    KOSValue::String(label) | KOSValue::StringValue(label) => {
+                                        local_functions.get(label.as_str())
+                                    }
+                                    _ => None,
+                                });
+
+                        if let Some(&callee) = callee {
+                            builder.add_relocation(
+                                func,
+                                ko_index,
+                                operand_index,
+                                callee.symbol_index(),
+                            );
+                        }
+                    }
+                }
+
+                lines.push(
+                    file.debug_section
+                        .line_for_offset(offsets[section_index][instr_index]),
+                );
+            }
+        }
+
+        functions.push(DelinkedFunction {
+            name: name.clone(),
+            section_index: func.section_index(),
+            lines,
+        });
+    }
+
+    let ko = builder.finish().map_err(DelinkError::BuilderError)?;
+
+    Ok(Delinked { ko, functions })
+}
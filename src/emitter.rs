@@ -0,0 +1,235 @@
+//! The instruction emitter methods that are shared between the KO and KSM emitters.
+//!
+//! Both emitters only differ in the section types they write to, so all of the typed instruction
+//! methods are generated here. The emitter type must provide two private methods:
+//!
+//! * `intern(&mut self, value: KOSValue) -> <operand index>` which adds the value to the
+//!   operand section if it is not already present
+//! * `add_instr(&mut self, instr: Instr) -> <instruction index>` which appends the instruction
+//!   to the code section
+//!
+
+macro_rules! gen_zero_op_methods {
+    ($idx:ty, $($name:ident => $opcode:ident, $doc:literal);* $(;)?) => {
+        $(
+            #[doc = $doc]
+            pub fn $name(&mut self) -> $idx {
+                self.emit_unchecked(Opcode::$opcode, Vec::new())
+            }
+        )*
+    };
+}
+
+macro_rules! gen_identifier_methods {
+    ($idx:ty, $($name:ident => $opcode:ident, $doc:literal);* $(;)?) => {
+        $(
+            #[doc = $doc]
+            ///
+            /// The identifier is stored as a String, and should include the leading `$`
+            pub fn $name(&mut self, identifier: impl Into<String>) -> $idx {
+                self.emit_unchecked(Opcode::$opcode, vec![KOSValue::String(identifier.into())])
+            }
+        )*
+    };
+}
+
+macro_rules! gen_branch_methods {
+    ($idx:ty, $($name:ident, $label_name:ident => $opcode:ident, $doc:literal);* $(;)?) => {
+        $(
+            #[doc = $doc]
+            ///
+            /// The offset is relative to this instruction, measured in instructions.
+            pub fn $name(&mut self, offset: i32) -> $idx {
+                self.emit_unchecked(Opcode::$opcode, vec![KOSValue::Int32(offset)])
+            }
+
+            #[doc = $doc]
+            ///
+            /// The destination is the label of the instruction to branch to.
+            pub fn $label_name(&mut self, label: impl Into<String>) -> $idx {
+                self.emit_unchecked(Opcode::$opcode, vec![KOSValue::String(label.into())])
+            }
+        )*
+    };
+}
+
+macro_rules! gen_emitter_methods {
+    ($idx:ty) => {
+        /// Emits an instruction with the provided opcode and operands, interning each operand.
+        ///
+        /// Returns an error if the number of operands provided does not match the number of operands
+        /// the opcode takes, or if the opcode is Bogus.
+        pub fn emit(
+            &mut self,
+            opcode: Opcode,
+            operands: impl IntoIterator<Item = KOSValue>,
+        ) -> Result<$idx, EmitError> {
+            if opcode == Opcode::Bogus {
+                return Err(EmitError::BogusOpcodeError);
+            }
+
+            let operands: Vec<KOSValue> = operands.into_iter().collect();
+
+            if operands.len() != opcode.num_operands() {
+                return Err(EmitError::OperandCountError(
+                    opcode,
+                    opcode.num_operands(),
+                    operands.len(),
+                ));
+            }
+
+            Ok(self.emit_unchecked(opcode, operands))
+        }
+
+        fn emit_unchecked(&mut self, opcode: Opcode, operands: Vec<KOSValue>) -> $idx {
+            debug_assert_eq!(operands.len(), opcode.num_operands());
+
+            let mut operands = operands.into_iter();
+
+            let instr = match (operands.next(), operands.next()) {
+                (None, _) => Instr::ZeroOp(opcode),
+                (Some(op1), None) => Instr::OneOp(opcode, self.intern(op1)),
+                (Some(op1), Some(op2)) => {
+                    let op1 = self.intern(op1);
+                    let op2 = self.intern(op2);
+                    Instr::TwoOp(opcode, op1, op2)
+                }
+            };
+
+            self.add_instr(instr)
+        }
+
+        /// Emits a push instruction for the provided value
+        pub fn push(&mut self, value: KOSValue) -> $idx {
+            self.emit_unchecked(Opcode::Push, vec![value])
+        }
+
+        /// Emits a push of an argument marker, which must come before the arguments of a call
+        pub fn push_arg_marker(&mut self) -> $idx {
+            self.push(KOSValue::ArgMarker)
+        }
+
+        /// Emits a call instruction.
+        ///
+        /// The destination is the label of a user-defined function, and the name is the name of a
+        /// built-in function such as `print()`. Only one of these is used, so the other may be
+        /// an empty string.
+        pub fn call(&mut self, destination: impl Into<String>, name: impl Into<String>) -> $idx {
+            self.emit_unchecked(
+                Opcode::Call,
+                vec![
+                    KOSValue::String(destination.into()),
+                    KOSValue::String(name.into()),
+                ],
+            )
+        }
+
+        /// Emits a return instruction, which pops the provided number of scope levels
+        pub fn ret(&mut self, depth: i16) -> $idx {
+            self.emit_unchecked(Opcode::Ret, vec![KOSValue::Int16(depth)])
+        }
+
+        /// Emits an instruction that begins a new scope with the provided id, inside of the scope with
+        /// the parent id
+        pub fn bscp(&mut self, id: i16, parent_id: i16) -> $idx {
+            self.emit_unchecked(
+                Opcode::Bscp,
+                vec![KOSValue::Int16(id), KOSValue::Int16(parent_id)],
+            )
+        }
+
+        /// Emits an instruction that ends the provided number of scope levels
+        pub fn escp(&mut self, levels: i16) -> $idx {
+            self.emit_unchecked(Opcode::Escp, vec![KOSValue::Int16(levels)])
+        }
+
+        /// Emits an instruction that adds a new trigger
+        pub fn addt(&mut self, unique: bool, priority: i32) -> $idx {
+            self.emit_unchecked(
+                Opcode::Addt,
+                vec![KOSValue::Bool(unique), KOSValue::Int32(priority)],
+            )
+        }
+
+        /// Emits an instruction that pushes a function delegate with the provided destination
+        pub fn phdl(&mut self, destination: i32, closure: bool) -> $idx {
+            self.emit_unchecked(
+                Opcode::Phdl,
+                vec![KOSValue::Int32(destination), KOSValue::Bool(closure)],
+            )
+        }
+
+        /// Emits an instruction that pushes a value which is relocated after the file is loaded
+        pub fn prl(&mut self, label: impl Into<String>) -> $idx {
+            self.emit_unchecked(Opcode::Prl, vec![KOSValue::String(label.into())])
+        }
+
+        /// Emits an instruction that pushes a function delegate to the provided label, which is
+        /// relocated after the file is loaded
+        pub fn pdrl(&mut self, label: impl Into<String>, closure: bool) -> $idx {
+            self.emit_unchecked(
+                Opcode::Pdrl,
+                vec![KOSValue::String(label.into()), KOSValue::Bool(closure)],
+            )
+        }
+
+        /// Emits an instruction that resets the current kOS label value
+        pub fn lbrt(&mut self, label: impl Into<String>) -> $idx {
+            self.emit_unchecked(Opcode::Lbrt, vec![KOSValue::String(label.into())])
+        }
+
+        gen_identifier_methods! {
+            $idx,
+            sto => Sto, "Emits an instruction that stores the top of the stack into a variable";
+            stol => Stol, "Emits an instruction that stores the top of the stack into a local variable";
+            stog => Stog, "Emits an instruction that stores the top of the stack into a global variable";
+            stoe => Stoe, "Emits an instruction that stores the top of the stack into an existing variable";
+            gmb => Gmb, "Emits an instruction that gets a suffix of the value on the stack";
+            smb => Smb, "Emits an instruction that sets a suffix of the value on the stack";
+            gmet => Gmet, "Emits an instruction that gets a suffixed method of the value on the stack";
+        }
+
+        gen_branch_methods! {
+            $idx,
+            bfa, bfa_label => Bfa, "Emits an instruction that branches if the value on the stack is false.";
+            btr, btr_label => Btr, "Emits an instruction that branches if the value on the stack is true.";
+            jmp, jmp_label => Jmp, "Emits an instruction that unconditionally branches.";
+        }
+
+        gen_zero_op_methods! {
+            $idx,
+            eof => Eof, "Emits an instruction that stops executing for this cycle";
+            eop => Eop, "Emits an instruction that ends the program";
+            nop => Nop, "Emits an instruction that does nothing";
+            uns => Uns, "Emits an instruction that unsets the variable named on the stack";
+            gidx => Gidx, "Emits an instruction that gets an index into a value on the stack";
+            sidx => Sidx, "Emits an instruction that sets an index of a value on the stack";
+            add => Add, "Emits an instruction that adds two values on the stack";
+            sub => Sub, "Emits an instruction that subtracts two values on the stack";
+            mul => Mul, "Emits an instruction that multiplies two values on the stack";
+            div => Div, "Emits an instruction that divides two values on the stack";
+            pow => Pow, "Emits an instruction that raises a value on the stack to another value on the stack";
+            cgt => Cgt, "Emits a greater than comparison";
+            clt => Clt, "Emits a less than comparison";
+            cge => Cge, "Emits a greater than or equal comparison";
+            cle => Cle, "Emits a less than or equal comparison";
+            ceq => Ceq, "Emits an equality comparison";
+            cne => Cne, "Emits an inequality comparison";
+            neg => Neg, "Emits an instruction that negates the value on the stack";
+            bool => Bool, "Emits an instruction that converts the value on the stack to a boolean";
+            not => Not, "Emits an instruction that logically negates the value on the stack";
+            and => And, "Emits a logical AND";
+            or => Or, "Emits a logical OR";
+            pop => Pop, "Emits an instruction that discards the top value of the stack";
+            dup => Dup, "Emits an instruction that duplicates the top value of the stack";
+            swap => Swap, "Emits an instruction that swaps the top two values of the stack";
+            eval => Eval, "Emits an instruction that evaluates the top value of the stack";
+            rmvt => Rmvt, "Emits an instruction that removes a trigger";
+            wait => Wait, "Emits an instruction that waits for an amount of time";
+            exst => Exst, "Emits an instruction that checks if a variable exists";
+            argb => Argb, "Emits an instruction that asserts that the top of the stack is an argument marker";
+            targ => Targ, "Emits an instruction that tests if the top of the stack is an argument marker";
+            tcan => Tcan, "Emits an instruction that tests if the current trigger is cancelled";
+        }
+    };
+}
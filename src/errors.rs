@@ -1,4 +1,5 @@
 //! Generic errors while reading KSM or KO files
+use crate::Opcode;
 use thiserror::Error;
 
 /// An error type that describes an error while just parsing a KOSValue
@@ -22,3 +23,157 @@ pub enum OpcodeParseError {
     #[error("Invalid opcode: {0}")]
     InvalidOpcode(u8),
 }
+
+/// An error type that describes an unrecognized opcode mnemonic
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("Unknown opcode mnemonic: {0}")]
+pub struct MnemonicParseError(pub String);
+
+/// An error type that describes an error while emitting an instruction
+#[derive(Debug, Error, Copy, Clone)]
+pub enum EmitError {
+    /// Error emitting an instruction with the Bogus opcode
+    #[error("Cannot emit an instruction with the Bogus opcode")]
+    BogusOpcodeError,
+    /// Error emitting an instruction with the wrong number of operands
+    #[error("Opcode {0:?} takes {1} operands, but {2} were provided")]
+    OperandCountError(Opcode, usize, usize),
+}
+
+/// A problem found by the stack depth verifier
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq)]
+pub enum StackError {
+    /// An instruction tried to consume more values than there were on the stack
+    #[error("Stack underflow: {0:?} requires {1} values, but only {2} are on the stack")]
+    Underflow(Opcode, usize, usize),
+    /// An instruction can be reached with two different stack depths
+    #[error("Inconsistent stack depth: previously reached with depth {0}, now with depth {1}")]
+    InconsistentDepth(isize, isize),
+    /// A function returns while leaving values on the stack other than its return value
+    #[error("Function returns with {0} extra values left on the stack")]
+    LeftoverValues(usize),
+    /// A call instruction was reached with no argument marker on the stack
+    #[error("Call instruction has no argument marker on the stack")]
+    MissingArgMarker,
+    /// A branch instruction's destination couldn't be resolved
+    #[error("Branch destination could not be resolved")]
+    UnresolvedBranch,
+}
+
+/// An error type that describes an error while performing an operation on KOSValues
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum KOSValueOpError {
+    /// Error when an operation is given values of the wrong type
+    #[error("{0:?} cannot operate on {1}")]
+    TypeError(Opcode, String),
+    /// Error when dividing by zero
+    #[error("Tried to divide by zero")]
+    DivideByZero,
+    /// Error when a calculation results in infinity or NaN, which kOS doesn't allow
+    #[error("Result is not a finite number")]
+    NotFinite,
+    /// Error when the opcode isn't an operation on values
+    #[error("{0:?} is not an operation on values")]
+    NotAnOperation(Opcode),
+}
+
+/// An error encountered while linking KO files into a KSM file
+#[cfg(all(feature = "ko", feature = "ksm"))]
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// Error when more than one file defines a global symbol with the same name
+    #[error("Symbol `{0}` is defined more than once")]
+    DuplicateSymbolError(String),
+    /// Error when a file refers to a symbol that none of the files define
+    #[error("Symbol `{0}` is not defined")]
+    UndefinedSymbolError(String),
+    /// Error when none of the files define `_start`
+    #[error("No file defines `_start`")]
+    MissingEntryPointError,
+    /// Error when an instruction has an operand that isn't in its file's data section. This
+    /// stores the name of the function, and the index of the instruction.
+    #[error("Instruction {1} of function `{0}` has an operand that isn't in the data section")]
+    InvalidOperandError(String, usize),
+    /// Error when a relocation can't be applied to the symbol that it refers to, such as a branch
+    /// to a data symbol, or a branch to a function in a different code section. This stores the
+    /// name of the function, and the index of the instruction.
+    #[error("Relocation of instruction {1} of function `{0}` can't be applied to its symbol")]
+    InvalidRelocationError(String, usize),
+}
+
+/// An error encountered while loading or running a program in the [Vm](crate::vm::Vm)
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum VmError {
+    /// Error when an instruction needs more values than there are on the stack
+    #[error("Stack underflow")]
+    StackUnderflow,
+    /// Error when reading or storing a variable that doesn't exist
+    #[error("Variable `{0}` does not exist")]
+    UnknownVariable(String),
+    /// Error when calling a function that isn't a builtin, a label, or a delegate
+    #[error("Function `{0}` does not exist")]
+    UnknownFunction(String),
+    /// Error when a branch or call refers to a label that doesn't exist
+    #[error("Label `{0}` does not exist")]
+    UnknownLabel(String),
+    /// Error when an instruction is given values of the wrong type
+    #[error("{0:?} cannot operate on {1}")]
+    TypeError(Opcode, String),
+    /// Error when dividing by zero
+    #[error("Tried to divide by zero")]
+    DivideByZero,
+    /// Error when a calculation results in infinity or NaN, which kOS doesn't allow
+    #[error("Result is not a finite number")]
+    NotFinite,
+    /// Error when a function is called with too many or too few arguments
+    #[error("Function called with the wrong number of arguments")]
+    ArgumentMismatch,
+    /// Error when executing an instruction that needs parts of kOS that aren't available, such
+    /// as triggers or suffixes
+    #[error("Instruction {0:?} is not supported")]
+    Unsupported(Opcode),
+    /// Error when the instruction at the provided index has an operand that couldn't be loaded
+    #[error("Instruction {0} has an invalid operand")]
+    InvalidOperand(usize),
+    /// Error when a program runs for more than the allowed number of instructions
+    #[error("Program did not finish within {0} instructions")]
+    StepLimit(usize),
+    /// Error when loading KO files that can't be linked together
+    #[cfg(all(feature = "ko", feature = "ksm"))]
+    #[error("Error while linking KO files: {0}")]
+    LinkError(LinkError),
+    /// Error reported by a builtin function
+    #[error("{0}")]
+    Builtin(String),
+}
+
+impl From<KOSValueOpError> for VmError {
+    fn from(error: KOSValueOpError) -> Self {
+        match error {
+            KOSValueOpError::TypeError(opcode, types) => VmError::TypeError(opcode, types),
+            KOSValueOpError::DivideByZero => VmError::DivideByZero,
+            KOSValueOpError::NotFinite => VmError::NotFinite,
+            KOSValueOpError::NotAnOperation(opcode) => VmError::Unsupported(opcode),
+        }
+    }
+}
+
+/// An error encountered while delinking a KSM file into a KO file
+#[cfg(all(feature = "ko", feature = "ksm"))]
+#[derive(Debug, Error, Clone)]
+pub enum DelinkError {
+    /// Error when an instruction has an operand that isn't in the argument section. This stores
+    /// the index of the code section, and the index of the instruction.
+    #[error(
+        "Instruction {1} in code section {0} has an operand that isn't in the argument section"
+    )]
+    InvalidOperandError(usize, usize),
+    /// Error when an instruction refers to another instruction by its absolute index, like Phdl
+    /// does, which can't be kept once the code is split into functions. This stores the index of
+    /// the code section, and the index of the instruction.
+    #[error("Instruction {1} in code section {0} refers to an instruction by its absolute index")]
+    AbsoluteIndexError(usize, usize),
+    /// Error while building the KO file, such as two functions with the same name
+    #[error("{0}")]
+    BuilderError(crate::ko::errors::BuilderError),
+}
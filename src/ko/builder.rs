@@ -0,0 +1,588 @@
+//! A module containing the KOFileBuilder type, which allows for the creation of a KOFile without
+//! having to keep track of the symbol table, string tables, data section and relocation data
+//! section by hand.
+//!
+//! # Example:
+//!
+//! ```
+//! use kerbalobjects::ko::symbols::{OperandIndex, SymBind};
+//! use kerbalobjects::ko::sections::DataIdx;
+//! use kerbalobjects::ko::{Instr, KOFileBuilder};
+//! use kerbalobjects::{KOSValue, Opcode};
+//!
+//! let mut builder = KOFileBuilder::new();
+//!
+//! builder.set_source_file("test.kasm");
+//!
+//! let start = builder.define_function("_start", SymBind::Global).unwrap();
+//! let greet = builder.reference_extern("greet");
+//!
+//! let marker = builder.add_data(KOSValue::ArgMarker);
+//! let empty = builder.add_data(KOSValue::String("".into()));
+//!
+//! builder.add_instr(start, Instr::OneOp(Opcode::Push, marker));
+//! // The first operand will be replaced by the linker with the location of `greet`
+//! let call = builder.add_instr(start, Instr::TwoOp(Opcode::Call, DataIdx::PLACEHOLDER, empty));
+//! builder.add_relocation(start, call, OperandIndex::One, greet);
+//! builder.add_instr(start, Instr::ZeroOp(Opcode::Pop));
+//!
+//! let ko = builder.finish().expect("Could not build KO file");
+//!
+//! let mut file_buffer = Vec::with_capacity(2048);
+//! ko.write(&mut file_buffer);
+//! ```
+//!
+use std::collections::HashMap;
+
+use crate::ko::errors::BuilderError;
+use crate::ko::sections::{
+    DataIdx, DataSection, FuncSection, InstrIdx, ReldSection, StringIdx, StringTable, SymbolIdx,
+    SymbolTable,
+};
+use crate::ko::symbols::{KOSymbol, OperandIndex, ReldEntry, ReldKind, SymBind, SymType};
+use crate::ko::{
+    FuncEmitter, Instr, KOFile, SectionIdx, WritableKOFile, SYMSTRTAB_NAME, SYMTAB_NAME,
+};
+use crate::KOSValue;
+
+/// A handle to a function that has been defined using a [KOFileBuilder].
+///
+/// This is used to refer to the function when adding instructions or relocations to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FuncHandle {
+    symbol_index: SymbolIdx,
+    section_index: SectionIdx,
+}
+
+impl FuncHandle {
+    /// The index of this function's symbol in the symbol table
+    pub fn symbol_index(&self) -> SymbolIdx {
+        self.symbol_index
+    }
+
+    /// The index of this function's section header
+    pub fn section_index(&self) -> SectionIdx {
+        self.section_index
+    }
+}
+
+/// A higher-level way to create a KOFile, which manages all of the bookkeeping that is
+/// required to produce a valid file.
+///
+/// The builder owns the `.symtab`, `.symstrtab`, `.data`, and `.reld` sections. Function
+/// symbol sizes are computed when the file is finished, and the section headers are validated,
+/// so that [finish](Self::finish) produces a [WritableKOFile] in one step.
+///
+#[derive(Debug)]
+pub struct KOFileBuilder {
+    ko: KOFile,
+    symstrtab: StringTable,
+    symtab_index: SectionIdx,
+    symbols: Vec<KOSymbol>,
+    symbol_map: HashMap<String, SymbolIdx>,
+    // The File symbol and the name of the source file. The name is only added to the .symstrtab
+    // when the file is finished, so that renaming the file doesn't leave the old name behind.
+    file_symbol: Option<(SymbolIdx, String)>,
+    data_section: DataSection,
+    func_sections: Vec<FuncSection>,
+    reld_section: Option<ReldSection>,
+}
+
+impl KOFileBuilder {
+    /// Creates a new KOFileBuilder for an empty Kerbal Object file
+    pub fn new() -> Self {
+        let mut ko = KOFile::new();
+
+        let symtab_index = ko.new_symtab(SYMTAB_NAME).section_index();
+        let symstrtab = ko.new_strtab(SYMSTRTAB_NAME);
+        let data_section = ko.new_data_section(".data");
+
+        Self {
+            ko,
+            symstrtab,
+            symtab_index,
+            symbols: Vec::new(),
+            symbol_map: HashMap::new(),
+            file_symbol: None,
+            data_section,
+            func_sections: Vec::new(),
+            reld_section: None,
+        }
+    }
+
+    /// Sets the name of the source file that this object file was produced from.
+    ///
+    /// Only one File symbol may exist in each object file, so calling this more than once
+    /// renames the existing File symbol.
+    pub fn set_source_file(&mut self, name: impl Into<String>) -> SymbolIdx {
+        let index = match self.file_symbol {
+            Some((index, _)) => index,
+            None => self.push_symbol(KOSymbol::new(
+                StringIdx::from(0usize),
+                DataIdx::PLACEHOLDER,
+                0,
+                SymBind::Global,
+                SymType::File,
+                SectionIdx::NULL,
+            )),
+        };
+
+        self.file_symbol = Some((index, name.into()));
+        index
+    }
+
+    /// Defines a new function with the provided name and symbol binding, creating the function's
+    /// section and its symbol.
+    ///
+    /// If the function was previously referenced using [reference_extern](Self::reference_extern)
+    /// or [reference_weak](Self::reference_weak), then that symbol becomes this definition, so
+    /// that any relocations that already refer to it now refer to this function.
+    ///
+    /// This fails if a symbol with this name is already defined, or if the binding is Extern.
+    pub fn define_function(
+        &mut self,
+        name: impl Into<String>,
+        bind: SymBind,
+    ) -> Result<FuncHandle, BuilderError> {
+        let name = name.into();
+
+        if bind == SymBind::Extern {
+            return Err(BuilderError::ExternDefinitionError(name));
+        }
+
+        let existing = match self.symbol_map.get(&name) {
+            Some(&index) if !self.symbols[usize::from(index)].is_undefined() => {
+                return Err(BuilderError::DuplicateSymbolError(name));
+            }
+            existing => existing.copied(),
+        };
+
+        let func_section = self.ko.new_func_section(name.as_str());
+        let section_index = func_section.section_index();
+        self.func_sections.push(func_section);
+
+        let symbol = KOSymbol::new(
+            self.symstrtab.add_checked(name.as_str()),
+            DataIdx::PLACEHOLDER,
+            0,
+            bind,
+            SymType::Func,
+            section_index,
+        );
+
+        let symbol_index = match existing {
+            Some(index) => {
+                self.symbols[usize::from(index)] = symbol;
+                index
+            }
+            None => {
+                let index = self.push_symbol(symbol);
+                self.symbol_map.insert(name, index);
+                index
+            }
+        };
+
+        Ok(FuncHandle {
+            symbol_index,
+            section_index,
+        })
+    }
+
+    /// Defines a new value in the data section with the provided name and symbol binding,
+    /// which can be referenced from other object files if it is Global or Weak.
+    ///
+    /// This fails if a symbol with this name is already defined, if the binding is Extern, or if
+    /// the value is too large for the size of its symbol.
+    pub fn define_value(
+        &mut self,
+        name: impl Into<String>,
+        value: KOSValue,
+        bind: SymBind,
+    ) -> Result<SymbolIdx, BuilderError> {
+        self.define_data(name.into(), value, bind, SymType::NoType)
+    }
+
+    /// Defines a new global variable with the provided name, initial value, and symbol binding.
+    ///
+    /// The initial value is stored in the data section, and when the file is linked, code that
+    /// stores it in the kOS global variable `$name` is run before `_init` and `_start`.
+    /// Relocations that refer to the variable are replaced with its identifier, so that
+    /// instructions like Push and Sto use the variable itself. Global and Weak variables can be
+    /// referenced from other object files.
+    ///
+    /// This fails if a symbol with this name is already defined, if the binding is Extern, or if
+    /// the value is too large for the size of its symbol.
+    pub fn define_variable(
+        &mut self,
+        name: impl Into<String>,
+        initial: KOSValue,
+        bind: SymBind,
+    ) -> Result<SymbolIdx, BuilderError> {
+        self.define_data(name.into(), initial, bind, SymType::Object)
+    }
+
+    fn define_data(
+        &mut self,
+        name: String,
+        value: KOSValue,
+        bind: SymBind,
+        sym_type: SymType,
+    ) -> Result<SymbolIdx, BuilderError> {
+        if bind == SymBind::Extern {
+            return Err(BuilderError::ExternDefinitionError(name));
+        }
+
+        let existing = match self.symbol_map.get(&name) {
+            Some(&index) if !self.symbols[usize::from(index)].is_undefined() => {
+                return Err(BuilderError::DuplicateSymbolError(name));
+            }
+            existing => existing.copied(),
+        };
+
+        let size = u16::try_from(value.size_bytes())
+            .map_err(|_| BuilderError::SymbolSizeError(name.clone(), value.size_bytes()))?;
+        let value_idx = self.data_section.add_checked(value);
+
+        let symbol = KOSymbol::new(
+            self.symstrtab.add_checked(name.as_str()),
+            value_idx,
+            size,
+            bind,
+            sym_type,
+            self.data_section.section_index(),
+        );
+
+        Ok(match existing {
+            Some(index) => {
+                self.symbols[usize::from(index)] = symbol;
+                index
+            }
+            None => {
+                let index = self.push_symbol(symbol);
+                self.symbol_map.insert(name, index);
+                index
+            }
+        })
+    }
+
+    /// Returns the symbol index of a function that is defined in a different object file.
+    ///
+    /// If a symbol with this name already exists, either defined or referenced, its index is returned.
+    pub fn reference_extern(&mut self, name: impl Into<String>) -> SymbolIdx {
+        self.reference(name.into(), SymBind::Extern)
+    }
+
+    /// Returns the symbol index of a function that may be defined in a different object file.
+    /// If no file defines it, the linker replaces it with a null value instead of failing.
+    ///
+    /// If a symbol with this name already exists, either defined or referenced, its index is returned.
+    pub fn reference_weak(&mut self, name: impl Into<String>) -> SymbolIdx {
+        self.reference(name.into(), SymBind::Weak)
+    }
+
+    fn reference(&mut self, name: String, bind: SymBind) -> SymbolIdx {
+        if let Some(&index) = self.symbol_map.get(&name) {
+            return index;
+        }
+
+        let name_idx = self.symstrtab.add_checked(name.as_str());
+        let index = self.push_symbol(KOSymbol::new(
+            name_idx,
+            DataIdx::PLACEHOLDER,
+            0,
+            bind,
+            SymType::Func,
+            SectionIdx::NULL,
+        ));
+        self.symbol_map.insert(name, index);
+
+        index
+    }
+
+    /// Returns the index of the symbol with the provided name, or None if it hasn't been defined
+    /// or referenced
+    pub fn symbol_index(&self, name: impl AsRef<str>) -> Option<SymbolIdx> {
+        self.symbol_map.get(name.as_ref()).copied()
+    }
+
+    /// Adds a value to the data section if it isn't already present, and returns its index
+    pub fn add_data(&mut self, value: KOSValue) -> DataIdx {
+        self.data_section.add_checked(value)
+    }
+
+    /// Adds an instruction to the end of the provided function, and returns the
+    /// instruction's index into the function
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle was not created by this builder.
+    pub fn add_instr(&mut self, func: FuncHandle, instr: Instr) -> InstrIdx {
+        self.func_section_mut(func).add(instr)
+    }
+
+    /// Records that an operand of an instruction in the provided function should be replaced
+    /// by the linker with the value of the provided symbol.
+    ///
+    /// The relocation data section is created the first time this is called.
+    pub fn add_relocation(
+        &mut self,
+        func: FuncHandle,
+        instr_index: InstrIdx,
+        operand_index: OperandIndex,
+        symbol_index: SymbolIdx,
+    ) {
+        self.add_relocation_of_kind(
+            func,
+            instr_index,
+            operand_index,
+            symbol_index,
+            ReldKind::Symbol,
+            0,
+        );
+    }
+
+    /// Records that an operand of an instruction in the provided function should be replaced
+    /// by the linker, using the provided kind of relocation and addend. See [ReldKind] for what
+    /// each kind of relocation does.
+    ///
+    /// The relocation data section is created the first time this is called.
+    pub fn add_relocation_of_kind(
+        &mut self,
+        func: FuncHandle,
+        instr_index: InstrIdx,
+        operand_index: OperandIndex,
+        symbol_index: SymbolIdx,
+        kind: ReldKind,
+        addend: i32,
+    ) {
+        let ko = &mut self.ko;
+        let reld_section = self
+            .reld_section
+            .get_or_insert_with(|| ko.new_reld_section(".reld"));
+
+        reld_section.add(
+            ReldEntry::new(func.section_index, instr_index, operand_index, symbol_index)
+                .with_kind(kind)
+                .with_addend(addend),
+        );
+    }
+
+    /// Gets a reference to the function section of the provided function
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle was not created by this builder.
+    pub fn func_section(&self, func: FuncHandle) -> &FuncSection {
+        self.func_sections
+            .iter()
+            .find(|section| section.section_index() == func.section_index)
+            .expect("Function handle was not created by this KOFileBuilder")
+    }
+
+    /// Gets a mutable reference to the function section of the provided function
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle was not created by this builder.
+    pub fn func_section_mut(&mut self, func: FuncHandle) -> &mut FuncSection {
+        self.func_sections
+            .iter_mut()
+            .find(|section| section.section_index() == func.section_index)
+            .expect("Function handle was not created by this KOFileBuilder")
+    }
+
+    /// Gets a reference to the data section that this builder is adding values to
+    pub fn data_section(&self) -> &DataSection {
+        &self.data_section
+    }
+
+    /// Gets a mutable reference to the data section that this builder is adding values to
+    pub fn data_section_mut(&mut self) -> &mut DataSection {
+        &mut self.data_section
+    }
+
+    /// Returns an emitter which appends instructions to the provided function, adding their
+    /// operands to this builder's data section
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle was not created by this builder.
+    pub fn emitter(&mut self, func: FuncHandle) -> FuncEmitter<'_> {
+        let func_section = self
+            .func_sections
+            .iter_mut()
+            .find(|section| section.section_index() == func.section_index)
+            .expect("Function handle was not created by this KOFileBuilder");
+
+        FuncEmitter::new(func_section, &mut self.data_section)
+    }
+
+    /// Consumes this builder, filling in the size of every function symbol, and adding all of the
+    /// sections to the KOFile before validating it.
+    ///
+    /// This fails if a function is too large for the size of its symbol, which is stored as a
+    /// 16 bit integer.
+    pub fn finish(self) -> Result<WritableKOFile, BuilderError> {
+        let mut ko = self.ko;
+        let mut symstrtab = self.symstrtab;
+        let mut symbols = self.symbols;
+        let mut symtab = SymbolTable::with_capacity(symbols.len(), self.symtab_index);
+
+        if let Some((index, name)) = self.file_symbol {
+            symbols[usize::from(index)].name_idx = symstrtab.add_checked(name);
+        }
+
+        for mut symbol in symbols {
+            if symbol.sym_type == SymType::Func && !symbol.is_undefined() {
+                if let Some(section) = self
+                    .func_sections
+                    .iter()
+                    .find(|section| section.section_index() == symbol.sh_idx)
+                {
+                    symbol.size = u16::try_from(section.size()).map_err(|_| {
+                        let name = symstrtab.get(symbol.name_idx).cloned();
+
+                        BuilderError::SymbolSizeError(
+                            name.unwrap_or_default(),
+                            section.size() as usize,
+                        )
+                    })?;
+                }
+            }
+
+            symtab.add(symbol);
+        }
+
+        ko.add_sym_tab(symtab);
+        ko.add_str_tab(symstrtab);
+        ko.add_data_section(self.data_section);
+
+        for func_section in self.func_sections {
+            ko.add_func_section(func_section);
+        }
+
+        if let Some(reld_section) = self.reld_section {
+            ko.add_reld_section(reld_section);
+        }
+
+        ko.validate()
+            .map_err(|(_, e)| BuilderError::ValidationError(e))
+    }
+
+    fn push_symbol(&mut self, symbol: KOSymbol) -> SymbolIdx {
+        self.symbols.push(symbol);
+        SymbolIdx::from(self.symbols.len() - 1)
+    }
+}
+
+impl Default for KOFileBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ko::builder::KOFileBuilder;
+    use crate::ko::errors::BuilderError;
+    use crate::ko::sections::DataIdx;
+    use crate::ko::symbols::{OperandIndex, SymBind, SymType};
+    use crate::ko::{Instr, KOFile};
+    use crate::{BufferIterator, KOSValue, Opcode};
+
+    #[test]
+    fn function_sizes() {
+        let mut builder = KOFileBuilder::new();
+
+        let start = builder.define_function("_start", SymBind::Global).unwrap();
+        let one = builder.add_data(KOSValue::Int16(1));
+        builder.add_instr(start, Instr::OneOp(Opcode::Push, one));
+        builder.add_instr(start, Instr::ZeroOp(Opcode::Pop));
+
+        let ko = builder.finish().unwrap().get();
+
+        let symbol = ko.symbol_by_name("_start").unwrap();
+
+        assert_eq!(symbol.sym_type, SymType::Func);
+        assert_eq!(symbol.size, 6);
+        assert_eq!(ko.func_section_by_symbol_name("_start").unwrap().size(), 6);
+    }
+
+    #[test]
+    fn oversized_function() {
+        let mut builder = KOFileBuilder::new();
+
+        let start = builder.define_function("_start", SymBind::Global).unwrap();
+
+        for _ in 0..=u16::MAX {
+            builder.add_instr(start, Instr::ZeroOp(Opcode::Nop));
+        }
+
+        assert!(matches!(
+            builder.finish(),
+            Err(BuilderError::SymbolSizeError(name, 65536)) if name == "_start"
+        ));
+    }
+
+    #[test]
+    fn extern_then_define() {
+        let mut builder = KOFileBuilder::new();
+
+        builder.set_source_file("lib.kasm");
+
+        let start = builder.define_function("_start", SymBind::Global).unwrap();
+        let helper_ref = builder.reference_extern("helper");
+        let empty = builder.add_data(KOSValue::String("".into()));
+        let call = builder.add_instr(
+            start,
+            Instr::TwoOp(Opcode::Call, DataIdx::PLACEHOLDER, empty),
+        );
+        builder.add_relocation(start, call, OperandIndex::One, helper_ref);
+
+        let helper = builder.define_function("helper", SymBind::Local).unwrap();
+        builder.add_instr(helper, Instr::ZeroOp(Opcode::Nop));
+
+        assert_eq!(helper.symbol_index(), helper_ref);
+        assert!(matches!(
+            builder.define_function("helper", SymBind::Global),
+            Err(BuilderError::DuplicateSymbolError(_))
+        ));
+
+        let ko = builder.finish().unwrap();
+
+        let mut buffer = Vec::new();
+        ko.write(&mut buffer);
+
+        let read = KOFile::parse(&mut BufferIterator::new(&buffer)).unwrap();
+
+        let helper = read.symbol_by_name("helper").unwrap();
+        assert_eq!(helper.sym_bind, SymBind::Local);
+        assert_eq!(helper.size, 1);
+        assert_eq!(read.reld_sections().next().unwrap().entries().count(), 1);
+        assert_eq!(
+            read.named_symbols()
+                .filter(|(_, s)| s.sym_type == SymType::File)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn rename_source_file() {
+        let mut builder = KOFileBuilder::new();
+
+        let first = builder.set_source_file("old.kasm");
+        let second = builder.set_source_file("new.kasm");
+
+        assert_eq!(first, second);
+
+        let ko = builder.finish().unwrap().get();
+        let symstrtab = ko.symstrtab().unwrap();
+
+        assert!(symstrtab.position("old.kasm").is_none());
+        assert_eq!(
+            ko.symbol_name(ko.symtab().unwrap().get(first).unwrap()),
+            Some(&String::from("new.kasm"))
+        );
+    }
+}
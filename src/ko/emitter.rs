@@ -0,0 +1,115 @@
+//! A module containing the FuncEmitter type, which appends instructions to a function section while
+//! automatically adding their operands to a data section.
+//!
+//! # Example:
+//!
+//! ```
+//! use kerbalobjects::ko::{FuncEmitter, KOFile};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut ko = KOFile::new();
+//!
+//! let mut data_section = ko.new_data_section(".data");
+//! let mut start = ko.new_func_section("_start");
+//!
+//! let mut emitter = FuncEmitter::new(&mut start, &mut data_section);
+//!
+//! // Corresponds to the KerbalScript code:
+//! // PRINT("Hello, world!").
+//!
+//! emitter.bscp(1, 0);
+//! emitter.argb();
+//! emitter.push_arg_marker();
+//! emitter.push(KOSValue::StringValue("Hello, world!".into()));
+//! emitter.call("", "print()");
+//! emitter.pop();
+//! emitter.escp(1);
+//!
+//! assert_eq!(start.instructions().count(), 7);
+//! ```
+//!
+use crate::ko::sections::{DataIdx, DataSection, FuncSection, InstrIdx};
+use crate::ko::Instr;
+use crate::{EmitError, KOSValue, Opcode};
+
+/// Appends instructions to a function section, adding each operand to a data section
+/// if it is not already present.
+#[derive(Debug)]
+pub struct FuncEmitter<'a> {
+    func_section: &'a mut FuncSection,
+    data_section: &'a mut DataSection,
+}
+
+impl<'a> FuncEmitter<'a> {
+    /// Creates a new emitter that appends instructions to the provided function section,
+    /// and stores operands in the provided data section
+    pub fn new(func_section: &'a mut FuncSection, data_section: &'a mut DataSection) -> Self {
+        Self {
+            func_section,
+            data_section,
+        }
+    }
+
+    fn intern(&mut self, value: KOSValue) -> DataIdx {
+        self.data_section.add_checked(value)
+    }
+
+    fn add_instr(&mut self, instr: Instr) -> InstrIdx {
+        self.func_section.add(instr)
+    }
+
+    gen_emitter_methods!(InstrIdx);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ko::sections::{DataIdx, DataSection, FuncSection};
+    use crate::ko::{FuncEmitter, Instr, SectionIdx};
+    use crate::{EmitError, KOSValue, Opcode};
+
+    #[test]
+    fn interns_operands() {
+        let mut data_section = DataSection::new(SectionIdx::from(2u16));
+        let mut func_section = FuncSection::new(SectionIdx::from(3u16));
+
+        let mut emitter = FuncEmitter::new(&mut func_section, &mut data_section);
+
+        emitter.bscp(1, 0);
+        emitter.call("", "print()");
+        emitter.escp(1);
+
+        let instrs: Vec<&Instr> = func_section.instructions().collect();
+
+        assert_eq!(data_section.data().count(), 4);
+        assert_eq!(
+            *instrs[0],
+            Instr::TwoOp(Opcode::Bscp, DataIdx::from(0u32), DataIdx::from(1u32))
+        );
+        assert_eq!(
+            *instrs[1],
+            Instr::TwoOp(Opcode::Call, DataIdx::from(2u32), DataIdx::from(3u32))
+        );
+        // The 1 is shared with the bscp instruction
+        assert_eq!(*instrs[2], Instr::OneOp(Opcode::Escp, DataIdx::from(0u32)));
+    }
+
+    #[test]
+    fn emit_checks_operands() {
+        let mut data_section = DataSection::new(SectionIdx::from(2u16));
+        let mut func_section = FuncSection::new(SectionIdx::from(3u16));
+
+        let mut emitter = FuncEmitter::new(&mut func_section, &mut data_section);
+
+        assert!(emitter.emit(Opcode::Push, [KOSValue::ScalarInt(2)]).is_ok());
+        assert!(matches!(
+            emitter.emit(Opcode::Add, [KOSValue::ScalarInt(2)]),
+            Err(EmitError::OperandCountError(Opcode::Add, 0, 1))
+        ));
+        assert!(matches!(
+            emitter.emit(Opcode::Bogus, []),
+            Err(EmitError::BogusOpcodeError)
+        ));
+
+        assert_eq!(func_section.instructions().count(), 1);
+    }
+}
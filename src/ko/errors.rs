@@ -1,6 +1,7 @@
 //! Errors specifically for when parsing a KO file
 
 use crate::ko::sections::SectionKind;
+use crate::ko::FormatFeature;
 use crate::{KOSValueParseError, OpcodeParseError};
 use thiserror::Error;
 
@@ -61,9 +62,20 @@ pub enum HeaderParseError {
     /// Reached EOF before reading KO file version
     #[error("Reached end of file trying to read KerbalObject file version")]
     MissingVersionError,
-    /// Encountered an unsupported KO file version
+    /// Encountered an unsupported KO file version. This is no longer returned, because files of
+    /// several versions can now be read, see [VersionTooNewError](Self::VersionTooNewError) and
+    /// [VersionTooOldError](Self::VersionTooOldError).
+    #[deprecated(note = "use VersionTooNewError or VersionTooOldError instead")]
     #[error("Only KerbalObject files version {0} can be read with this library version, tried to read file with version {1}")]
     UnsupportedVersionError(u8, u8),
+    /// Encountered a KO file that is newer than any version this library can read. This stores the
+    /// file's version, and the newest version that can be read.
+    #[error("KerbalObject file version {0} is newer than this library can read, the newest version it can read is {1}")]
+    VersionTooNewError(u8, u8),
+    /// Encountered a KO file that is older than any version this library can read. This stores the
+    /// file's version, and the oldest version that can be read.
+    #[error("KerbalObject file version {0} is older than this library can read, the oldest version it can read is {1}")]
+    VersionTooOldError(u8, u8),
     /// Reached EOF before reading the number of section headers in the file
     #[error("Reached end of file trying to read the number of headers in the header table")]
     MissingNumHeaders,
@@ -120,6 +132,15 @@ pub enum ReldEntryParseError {
     /// Reached EOF before reading symbol table index
     #[error("Reached end of file trying to read entry symbol index")]
     MissingSymbolIndexError,
+    /// Reached EOF before reading relocation kind
+    #[error("Reached end of file trying to read entry relocation kind")]
+    MissingKindError,
+    /// Encountered invalid relocation kind while reading
+    #[error("Entry has an invalid relocation kind of {0}, expected 0, 1, 2, or 3")]
+    InvalidKindError(u8),
+    /// Reached EOF before reading addend
+    #[error("Reached end of file trying to read entry addend")]
+    MissingAddendError,
 }
 
 /// An error encountered when parsing a KOSymbol from the symbol table
@@ -138,7 +159,9 @@ pub enum SymbolParseError {
     #[error("Reached end of file trying to read symbol binding")]
     MissingSymbolBindingError,
     /// Encountered an invalid symbol binding value
-    #[error("Symbol has an invalid symbol binding value {0}, expected 0, 1, or 2")]
+    #[error(
+        "Symbol has an invalid symbol binding value {0}, expected 0, 1, 2, or 3 (since version 6)"
+    )]
     InvalidSymbolBindingError(u8),
     /// Reached EOF before reading symbol type
     #[error("Reached end of file trying to read symbol type")]
@@ -194,6 +217,20 @@ pub enum ReldSectionParseError {
     ReldEntryParseError(usize, usize, ReldEntryParseError),
 }
 
+/// An error encountered when writing a KO file as a specific version of the KO file format
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq)]
+pub enum WriteVersionError {
+    /// This library can't write the requested version. This stores the requested version, and the
+    /// oldest and newest versions that can be written.
+    #[error(
+        "KerbalObject files can only be written as versions {1} to {2}, tried to write version {0}"
+    )]
+    UnsupportedVersionError(u8, u8, u8),
+    /// The file uses a feature that the requested version doesn't have
+    #[error("KerbalObject file uses {0}, which version {1} files can't store")]
+    UnavailableFeatureError(FormatFeature, u8),
+}
+
 /// An error encountered when calling .validate() on a KOFile instance to attempt to convert it to
 /// a writable format
 #[derive(Debug, Error, Clone)]
@@ -213,3 +250,47 @@ pub enum ValidationError {
     #[error("Error validating KOFile: An inserted section header at index {0} of kind {1:?} has an invalid name index of {2}")]
     InvalidSectionHeaderNameIndexError(u16, SectionKind, usize),
 }
+
+/// An error encountered while building a KO file using a [KOFileBuilder](crate::ko::KOFileBuilder)
+#[derive(Debug, Error, Clone)]
+pub enum BuilderError {
+    /// Error when a symbol is defined more than once
+    #[error("Error building KOFile: Symbol `{0}` is already defined")]
+    DuplicateSymbolError(String),
+    /// Error when attempting to define a symbol with the Extern binding
+    #[error("Error building KOFile: Symbol `{0}` cannot be defined with an Extern binding")]
+    ExternDefinitionError(String),
+    /// Error when a symbol's value or function is larger than the 65535 bytes that a symbol's
+    /// size can store
+    #[error("Error building KOFile: Symbol `{0}` is {1} bytes, which is larger than 65535 bytes")]
+    SymbolSizeError(String, usize),
+    /// Error while validating the final KO file
+    #[error("Error building KOFile: {0}")]
+    ValidationError(ValidationError),
+}
+
+/// An error encountered while transforming the symbols of a KO file
+#[derive(Debug, Error, Clone)]
+pub enum TransformError {
+    /// Error when there is no symbol with the provided name
+    #[error("Error transforming KOFile: Symbol `{0}` does not exist")]
+    MissingSymbolError(String),
+    /// Error when renaming a symbol to a name that another symbol already has
+    #[error("Error transforming KOFile: Symbol `{0}` already exists")]
+    DuplicateSymbolError(String),
+    /// Error when changing the binding of a symbol that isn't defined in the file
+    #[error("Error transforming KOFile: Symbol `{0}` is not defined in this file")]
+    UndefinedSymbolError(String),
+}
+
+/// An error encountered while getting a section of a KO file by its section header index
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum SectionAccessError {
+    /// Error when there is no section at the provided section header index
+    #[error("KOFile has no section at section header index {0}")]
+    MissingSectionError(u16),
+    /// Error when the section at the provided section header index is a different kind of
+    /// section than the one requested
+    #[error("Expected section at section header index {0} to be of kind {1:?}, found {2:?}")]
+    SectionKindMismatchError(u16, SectionKind, SectionKind),
+}
@@ -92,26 +92,82 @@
 //! ```
 //!
 
-use std::slice::Iter;
+use std::collections::HashMap;
+use std::slice::{Iter, IterMut};
 
 use crate::{BufferIterator, FromBytes, ToBytes, WritableBuffer};
 
 use self::sections::{DataSection, FuncSection, SectionHeader, StringTable, SymbolTable};
-use self::sections::{ReldSection, SectionKind};
+use self::sections::{ReldSection, Section, SectionKind, SectionMut};
+
+pub mod builder;
+pub use builder::*;
+
+pub mod emitter;
+pub use emitter::*;
 
 pub mod errors;
 pub mod instructions;
 pub mod sections;
 pub mod symbols;
+pub mod transform;
 
-use crate::ko::errors::{HeaderParseError, KOParseError, ValidationError};
-use crate::ko::sections::StringIdx;
+use crate::ko::errors::{
+    HeaderParseError, KOParseError, SectionAccessError, ValidationError, WriteVersionError,
+};
+use crate::ko::sections::{StringIdx, SymbolIdx};
+use crate::ko::symbols::{KOSymbol, ReldKind, SymBind, SymType};
 pub use instructions::Instr;
 
-const FILE_VERSION: u8 = 4;
+/// The version of the KO file format that this library writes by default
+pub const FILE_VERSION: u8 = 6;
+/// The oldest version of the KO file format that this library can read or write. Files of older
+/// versions are upgraded to [FILE_VERSION] when they are read.
+pub const MIN_FILE_VERSION: u8 = 4;
 /// `k` 1 `o` `f`
 const MAGIC_NUMBER: u32 = 0x666f016b;
 
+/// The name of the symbol table that the linker reads symbols from
+const SYMTAB_NAME: &str = ".symtab";
+/// The name of the string table which stores the names of the symbols in the .symtab
+const SYMSTRTAB_NAME: &str = ".symstrtab";
+
+/// A feature of the KO file format that isn't available in every version of the format that this
+/// library can read and write.
+///
+/// Writing a file that uses a feature as a version that doesn't have it fails. See
+/// [WritableKOFile::write_version].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormatFeature {
+    /// Relocation kinds other than [ReldKind::Symbol], and relocation addends
+    RelocationKinds,
+    /// Symbols with the [Weak](symbols::SymBind::Weak) binding
+    WeakSymbols,
+}
+
+impl FormatFeature {
+    /// Every feature, in the order that they were added to the format
+    pub const ALL: [FormatFeature; 2] =
+        [FormatFeature::RelocationKinds, FormatFeature::WeakSymbols];
+
+    /// The first version of the KO file format that has this feature
+    pub const fn min_version(&self) -> u8 {
+        match self {
+            FormatFeature::RelocationKinds => 5,
+            FormatFeature::WeakSymbols => 6,
+        }
+    }
+}
+
+impl std::fmt::Display for FormatFeature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatFeature::RelocationKinds => write!(f, "relocation kinds"),
+            FormatFeature::WeakSymbols => write!(f, "weak symbols"),
+        }
+    }
+}
+
 /// A wrapper type that represents an index into a section header table of a KO file.
 ///
 /// This type implements From<u16> and u16 implements From<SectionIdx>, but this is provided
@@ -163,6 +219,13 @@ pub struct KOFile {
     data_sections: Vec<DataSection>,
     func_sections: Vec<FuncSection>,
     reld_sections: Vec<ReldSection>,
+    /// The kind and position in its list of every section, by the index of its section header.
+    /// This doesn't include the .shstrtab, which is stored on its own.
+    section_positions: HashMap<SectionIdx, (SectionKind, usize)>,
+    /// The section header index of the .symtab, if this file has one
+    symtab_index: Option<SectionIdx>,
+    /// The section header index of the .symstrtab, if this file has one
+    symstrtab_index: Option<SectionIdx>,
 }
 
 impl KOFile {
@@ -184,6 +247,9 @@ impl KOFile {
             data_sections: Vec::with_capacity(1),
             func_sections: Vec::with_capacity(1),
             reld_sections: Vec::with_capacity(1),
+            section_positions: HashMap::with_capacity(6),
+            symtab_index: None,
+            symstrtab_index: None,
         };
 
         // Add the null entry
@@ -242,6 +308,108 @@ impl KOFile {
         self.section_headers.get(usize::from(index))
     }
 
+    /// Gets the section at the provided index into the section header table, whatever kind of
+    /// section it is, or None if there is no section at that index. This includes the
+    /// .shstrtab.
+    pub fn get_section(&self, index: SectionIdx) -> Option<Section<'_>> {
+        if index == self.shstrtab.section_index() {
+            return Some(Section::StrTab(&self.shstrtab));
+        }
+
+        let &(kind, position) = self.section_positions.get(&index)?;
+
+        match kind {
+            SectionKind::StrTab => self.str_tabs.get(position).map(Section::StrTab),
+            SectionKind::SymTab => self.sym_tabs.get(position).map(Section::SymTab),
+            SectionKind::Data => self.data_sections.get(position).map(Section::Data),
+            SectionKind::Func => self.func_sections.get(position).map(Section::Func),
+            SectionKind::Reld => self.reld_sections.get(position).map(Section::Reld),
+            SectionKind::Null | SectionKind::Debug => None,
+        }
+    }
+
+    /// Gets a mutable reference to the section at the provided index into the section header
+    /// table, whatever kind of section it is, or None if there is no section at that index. This
+    /// includes the .shstrtab.
+    pub fn get_section_mut(&mut self, index: SectionIdx) -> Option<SectionMut<'_>> {
+        if index == self.shstrtab.section_index() {
+            return Some(SectionMut::StrTab(&mut self.shstrtab));
+        }
+
+        let &(kind, position) = self.section_positions.get(&index)?;
+
+        match kind {
+            SectionKind::StrTab => self.str_tabs.get_mut(position).map(SectionMut::StrTab),
+            SectionKind::SymTab => self.sym_tabs.get_mut(position).map(SectionMut::SymTab),
+            SectionKind::Data => self.data_sections.get_mut(position).map(SectionMut::Data),
+            SectionKind::Func => self.func_sections.get_mut(position).map(SectionMut::Func),
+            SectionKind::Reld => self.reld_sections.get_mut(position).map(SectionMut::Reld),
+            SectionKind::Null | SectionKind::Debug => None,
+        }
+    }
+
+    /// Returns an iterator over every section in this Kerbal Object file, including the
+    /// .shstrtab, in the order of their section headers
+    pub fn sections(&self) -> impl Iterator<Item = Section<'_>> {
+        (0..self.section_headers.len() as u16)
+            .map(SectionIdx::from)
+            .filter_map(|index| self.get_section(index))
+    }
+
+    /// Records where every section is stored, after the lists of sections have been filled in
+    fn index_sections(&mut self) {
+        let positions = std::iter::empty()
+            .chain(self.str_tabs.iter().enumerate().map(|(position, section)| {
+                (section.section_index(), (SectionKind::StrTab, position))
+            }))
+            .chain(self.sym_tabs.iter().enumerate().map(|(position, section)| {
+                (section.section_index(), (SectionKind::SymTab, position))
+            }))
+            .chain(
+                self.data_sections
+                    .iter()
+                    .enumerate()
+                    .map(|(position, section)| {
+                        (section.section_index(), (SectionKind::Data, position))
+                    }),
+            )
+            .chain(
+                self.func_sections
+                    .iter()
+                    .enumerate()
+                    .map(|(position, section)| {
+                        (section.section_index(), (SectionKind::Func, position))
+                    }),
+            )
+            .chain(
+                self.reld_sections
+                    .iter()
+                    .enumerate()
+                    .map(|(position, section)| {
+                        (section.section_index(), (SectionKind::Reld, position))
+                    }),
+            );
+
+        self.section_positions = positions.collect();
+
+        self.symtab_index = self
+            .sym_tabs
+            .iter()
+            .map(SymbolTable::section_index)
+            .find(|&index| self.is_section_named(index, SYMTAB_NAME));
+        self.symstrtab_index = self
+            .str_tabs
+            .iter()
+            .map(StringTable::section_index)
+            .find(|&index| self.is_section_named(index, SYMSTRTAB_NAME));
+    }
+
+    // Whether or not the section header at the provided index has the provided name
+    fn is_section_named(&self, index: SectionIdx, name: &str) -> bool {
+        self.get_section_name_by_index(index)
+            .is_some_and(|section_name| section_name == name)
+    }
+
     /// Gets the name of the section referred to by the provided section header,
     /// or None if no section with that header is found in this KO file
     pub fn get_header_name(&self, header: &SectionHeader) -> Option<&String> {
@@ -250,54 +418,96 @@ impl KOFile {
 
     /// Adds a new string table to this Kerbal Object file
     pub fn add_str_tab(&mut self, str_tab: StringTable) {
+        if self.symstrtab_index.is_none()
+            && self.is_section_named(str_tab.section_index(), SYMSTRTAB_NAME)
+        {
+            self.symstrtab_index = Some(str_tab.section_index());
+        }
+
+        self.section_positions.insert(
+            str_tab.section_index(),
+            (SectionKind::StrTab, self.str_tabs.len()),
+        );
         self.str_tabs.push(str_tab);
     }
 
     /// Adds a new symbol table to this Kerbal Object file
     pub fn add_sym_tab(&mut self, sym_tab: SymbolTable) {
+        if self.symtab_index.is_none()
+            && self.is_section_named(sym_tab.section_index(), SYMTAB_NAME)
+        {
+            self.symtab_index = Some(sym_tab.section_index());
+        }
+
+        self.section_positions.insert(
+            sym_tab.section_index(),
+            (SectionKind::SymTab, self.sym_tabs.len()),
+        );
         self.sym_tabs.push(sym_tab);
     }
 
     /// Adds a new data section to this Kerbal Object file
     pub fn add_data_section(&mut self, data_section: DataSection) {
+        self.section_positions.insert(
+            data_section.section_index(),
+            (SectionKind::Data, self.data_sections.len()),
+        );
         self.data_sections.push(data_section);
     }
 
     /// Adds a new function section to this Kerbal Object file
     pub fn add_func_section(&mut self, func_section: FuncSection) {
+        self.section_positions.insert(
+            func_section.section_index(),
+            (SectionKind::Func, self.func_sections.len()),
+        );
         self.func_sections.push(func_section);
     }
 
     /// Adds a new relocation data section to this Kerbal Object file
     pub fn add_reld_section(&mut self, reld_section: ReldSection) {
+        self.section_positions.insert(
+            reld_section.section_index(),
+            (SectionKind::Reld, self.reld_sections.len()),
+        );
         self.reld_sections.push(reld_section);
     }
 
     /// Returns an iterator over all of the string tables in this Kerbal Object file
-    pub fn str_tabs(&self) -> Iter<StringTable> {
+    pub fn str_tabs(&self) -> Iter<'_, StringTable> {
         self.str_tabs.iter()
     }
 
     /// Returns an iterator over all of the symbol tables in this Kerbal Object file
-    pub fn sym_tabs(&self) -> Iter<SymbolTable> {
+    pub fn sym_tabs(&self) -> Iter<'_, SymbolTable> {
         self.sym_tabs.iter()
     }
 
     /// Returns an iterator over all of the data sections in this Kerbal Object file
-    pub fn data_sections(&self) -> Iter<DataSection> {
+    pub fn data_sections(&self) -> Iter<'_, DataSection> {
         self.data_sections.iter()
     }
 
     /// Returns an iterator over all of the function sections in this Kerbal Object file
-    pub fn func_sections(&self) -> Iter<FuncSection> {
+    pub fn func_sections(&self) -> Iter<'_, FuncSection> {
         self.func_sections.iter()
     }
 
+    /// Returns a mutable iterator over all of the function sections in this Kerbal Object file
+    pub fn func_sections_mut(&mut self) -> IterMut<'_, FuncSection> {
+        self.func_sections.iter_mut()
+    }
+
     /// Returns an iterator over all of the relocation data sections in this Kerbal Object file
-    pub fn reld_sections(&self) -> Iter<ReldSection> {
+    pub fn reld_sections(&self) -> Iter<'_, ReldSection> {
         self.reld_sections.iter()
     }
 
+    /// Returns a mutable iterator over all of the relocation data sections in this Kerbal Object file
+    pub fn reld_sections_mut(&mut self) -> IterMut<'_, ReldSection> {
+        self.reld_sections.iter_mut()
+    }
+
     /// Adds a new section header of the provided name and section kind to this Kerbal
     /// Object file, and returns the index into the section header table of this new header
     pub fn new_section_header(&mut self, name: impl Into<String>, kind: SectionKind) -> SectionIdx {
@@ -326,14 +536,121 @@ impl KOFile {
         None
     }
 
+    /// Returns the symbol table of this Kerbal Object file (.symtab), or None if it doesn't exist
+    pub fn symtab(&self) -> Option<&SymbolTable> {
+        self.sym_tab_at(self.symtab_index?).ok()
+    }
+
+    /// Returns the string table that stores the names of all symbols in this file's
+    /// symbol table (.symstrtab), or None if it doesn't exist
+    pub fn symstrtab(&self) -> Option<&StringTable> {
+        self.str_tab_at(self.symstrtab_index?).ok()
+    }
+
+    /// Returns the name of the provided symbol from this file's .symstrtab, or None if
+    /// either the .symstrtab doesn't exist, or the symbol's name index is invalid
+    pub fn symbol_name(&self, symbol: &KOSymbol) -> Option<&String> {
+        self.symstrtab()?.get(symbol.name_idx)
+    }
+
+    /// Returns the index into this file's .symtab of the symbol with the provided name, or None
+    /// if no such symbol exists.
+    ///
+    /// This doesn't scan the symbol table, both the string table and the symbol table keep
+    /// an index of their contents, so this is a pair of hash lookups.
+    pub fn symbol_index_by_name(&self, name: impl AsRef<str>) -> Option<SymbolIdx> {
+        let name = name.as_ref();
+        let symstrtab = self.symstrtab()?;
+        let name_idx = symstrtab.position(name)?;
+
+        // Guard against two different strings that happen to hash the same
+        if symstrtab.get(name_idx)? != name {
+            return None;
+        }
+
+        self.symtab()?.position_by_name(name_idx)
+    }
+
+    /// Returns the symbol in this file's .symtab with the provided name, or None if
+    /// no such symbol exists
+    pub fn symbol_by_name(&self, name: impl AsRef<str>) -> Option<&KOSymbol> {
+        let index = self.symbol_index_by_name(name)?;
+        self.symtab()?.get(index)
+    }
+
+    /// Returns an iterator over every symbol in this file's .symtab, paired with the symbol's
+    /// name. Symbols whose name can't be found in the .symstrtab are paired with an empty name.
+    ///
+    /// The iterator is empty if either the .symtab or .symstrtab doesn't exist.
+    pub fn named_symbols(&self) -> impl Iterator<Item = (&str, &KOSymbol)> {
+        let symstrtab = self.symstrtab();
+
+        self.symtab()
+            .filter(|_| symstrtab.is_some())
+            .into_iter()
+            .flat_map(|symtab| symtab.symbols())
+            .map(move |symbol| {
+                let name = symstrtab
+                    .and_then(|strtab| strtab.get(symbol.name_idx))
+                    .map(|s| s.as_str())
+                    .unwrap_or("");
+
+                (name, symbol)
+            })
+    }
+
+    /// Gets a reference to the FuncSection at the provided section header index,
+    /// or None if there is no function section at that index
+    pub fn func_section_by_index(&self, index: SectionIdx) -> Option<&FuncSection> {
+        self.func_section_at(index).ok()
+    }
+
+    /// Gets a reference to the FuncSection that contains the code of the function symbol with
+    /// the provided name, or None if there is no function symbol by that name, or if the symbol
+    /// doesn't refer to a function section in this file (which is the case for Extern symbols)
+    pub fn func_section_by_symbol_name(&self, name: impl AsRef<str>) -> Option<&FuncSection> {
+        let symbol = self.symbol_by_name(name)?;
+
+        if symbol.sym_type != SymType::Func {
+            return None;
+        }
+
+        self.func_section_by_index(symbol.sh_idx)
+    }
+
     /// The Kerbal Object file header
     pub fn header(&self) -> KOHeader {
         self.header
     }
 
+    /// Returns true if this file uses the provided feature of the KO file format
+    pub fn uses_feature(&self, feature: FormatFeature) -> bool {
+        match feature {
+            FormatFeature::RelocationKinds => self
+                .reld_sections
+                .iter()
+                .flat_map(|reld_section| reld_section.entries())
+                .any(|entry| entry.kind != ReldKind::Symbol || entry.addend != 0),
+            FormatFeature::WeakSymbols => self
+                .sym_tabs
+                .iter()
+                .flat_map(|symtab| symtab.symbols())
+                .any(|symbol| symbol.sym_bind == SymBind::Weak),
+        }
+    }
+
+    /// Returns the oldest version of the KO file format that this file can be written as
+    pub fn min_version(&self) -> u8 {
+        FormatFeature::ALL
+            .iter()
+            .filter(|&&feature| self.uses_feature(feature))
+            .map(FormatFeature::min_version)
+            .fold(MIN_FILE_VERSION, u8::max)
+    }
+
     /// Returns an iterator over all section headers in the Kerbal Object file's section
     /// header table
-    pub fn section_headers(&self) -> Iter<SectionHeader> {
+    pub fn section_headers(&self) -> Iter<'_, SectionHeader> {
         self.section_headers.iter()
     }
 
@@ -480,6 +797,7 @@ impl KOFile {
     ///
     /// A WritableKOFile can be turned back into a KOFile
     ///
+    #[allow(clippy::result_large_err)]
     pub fn validate(mut self) -> Result<WritableKOFile, (Self, ValidationError)> {
         self.header = KOHeader::new(
             self.section_headers.len() as u16,
@@ -496,6 +814,7 @@ impl KOFile {
     /// Parses an entire KOFile from a byte buffer
     pub fn parse(source: &mut BufferIterator) -> Result<Self, KOParseError> {
         let header = KOHeader::parse(source).map_err(KOParseError::HeaderError)?;
+        let version = header.version;
         let mut section_headers = Vec::with_capacity(header.num_headers as usize);
         let mut str_tabs;
         let mut sym_tabs;
@@ -593,7 +912,7 @@ impl KOFile {
                 }
                 SectionKind::SymTab => {
                     sym_tabs.push(
-                        SymbolTable::parse(source, header.size, section_idx)
+                        SymbolTable::parse_version(source, header.size, section_idx, version)
                             .map_err(KOParseError::SymbolTableParseError)?,
                     );
                 }
@@ -611,7 +930,7 @@ impl KOFile {
                 }
                 SectionKind::Reld => {
                     reld_sections.push(
-                        ReldSection::parse(source, header.size, section_idx)
+                        ReldSection::parse_version(source, header.size, section_idx, version)
                             .map_err(KOParseError::ReldSectionParseError)?,
                     );
                 }
@@ -622,7 +941,17 @@ impl KOFile {
             }
         }
 
-        Ok(Self {
+        // Older files are upgraded to the current version, which can change the sizes of sections
+        for reld_section in reld_sections.iter() {
+            section_headers[usize::from(reld_section.section_index())].size = reld_section.size();
+        }
+
+        let header = KOHeader {
+            version: FILE_VERSION,
+            ..header
+        };
+
+        let mut kofile = Self {
             header,
             shstrtab,
             section_headers,
@@ -631,7 +960,14 @@ impl KOFile {
             data_sections,
             func_sections,
             reld_sections,
-        })
+            section_positions: HashMap::new(),
+            symtab_index: None,
+            symstrtab_index: None,
+        };
+
+        kofile.index_sections();
+
+        Ok(kofile)
     }
 
     // There are extra macro-generated functions at the end of this file that are implemented
@@ -650,67 +986,72 @@ impl Default for KOFile {
 pub struct WritableKOFile(KOFile);
 
 impl WritableKOFile {
-    /// Writes the binary representation of this KO file to the provided buffer
+    /// Writes the binary representation of this KO file to the provided buffer, as the current
+    /// version of the KO file format
     pub fn write(&self, buf: &mut impl WritableBuffer) {
-        let ko = &self.0;
-        // Write the file header
-        ko.header.write(buf);
+        self.write_unchecked(buf, FILE_VERSION);
+    }
 
-        // First write all of the section headers in the section header table
-        for header in &ko.section_headers {
-            header.write(buf);
+    /// Writes the binary representation of this KO file to the provided buffer, as the provided
+    /// version of the KO file format, so that it can be read by older tools.
+    ///
+    /// This fails without writing anything if this library can't write the version, or if the
+    /// file uses a [FormatFeature] that the version doesn't have.
+    pub fn write_version(
+        &self,
+        buf: &mut impl WritableBuffer,
+        version: u8,
+    ) -> Result<(), WriteVersionError> {
+        if !(MIN_FILE_VERSION..=FILE_VERSION).contains(&version) {
+            return Err(WriteVersionError::UnsupportedVersionError(
+                version,
+                MIN_FILE_VERSION,
+                FILE_VERSION,
+            ));
         }
 
-        // Write out all of the sections in order
-        for section_index in (0..ko.section_header_count()).map(|i| SectionIdx::from(i as u16)) {
-            if self.0.shstrtab.section_index() == section_index {
-                self.0.shstrtab.write(buf);
-                continue;
-            }
+        if let Some(feature) = FormatFeature::ALL
+            .into_iter()
+            .find(|feature| feature.min_version() > version && self.0.uses_feature(*feature))
+        {
+            return Err(WriteVersionError::UnavailableFeatureError(feature, version));
+        }
 
-            if let Some(section) = ko
-                .str_tabs
-                .iter()
-                .find(|s| s.section_index() == section_index)
-            {
-                section.write(buf);
-                continue;
-            }
+        self.write_unchecked(buf, version);
 
-            if let Some(section) = ko
-                .sym_tabs
-                .iter()
-                .find(|s| s.section_index() == section_index)
-            {
-                section.write(buf);
-                continue;
-            }
-
-            if let Some(section) = ko
-                .data_sections
-                .iter()
-                .find(|s| s.section_index() == section_index)
-            {
-                section.write(buf);
-                continue;
-            }
+        Ok(())
+    }
 
-            if let Some(section) = ko
-                .func_sections
-                .iter()
-                .find(|s| s.section_index() == section_index)
-            {
-                section.write(buf);
-                continue;
+    fn write_unchecked(&self, buf: &mut impl WritableBuffer, version: u8) {
+        let ko = &self.0;
+        // Write the file header
+        KOHeader {
+            version,
+            ..ko.header
+        }
+        .write(buf);
+
+        // First write all of the section headers in the section header table. The size of
+        // relocation data sections depends on the version.
+        for (index, header) in ko.section_headers.iter().enumerate() {
+            match ko.get_section(SectionIdx::from(index as u16)) {
+                Some(Section::Reld(reld_section)) => SectionHeader {
+                    size: reld_section.size_for_version(version),
+                    ..*header
+                }
+                .write(buf),
+                _ => header.write(buf),
             }
+        }
 
-            if let Some(section) = ko
-                .reld_sections
-                .iter()
-                .find(|s| s.section_index() == section_index)
-            {
-                section.write(buf);
-                continue;
+        // Write out all of the sections in order
+        for section in ko.sections() {
+            match section {
+                Section::StrTab(section) => section.write(buf),
+                Section::SymTab(section) => section.write(buf),
+                Section::Data(section) => section.write(buf),
+                Section::Func(section) => section.write(buf),
+                Section::Reld(section) => section.write_version(buf, version),
             }
         }
     }
@@ -781,10 +1122,14 @@ impl KOHeader {
             return Err(HeaderParseError::InvalidMagicError(magic, MAGIC_NUMBER));
         }
 
-        if version != FILE_VERSION {
-            return Err(HeaderParseError::UnsupportedVersionError(
+        if version > FILE_VERSION {
+            return Err(HeaderParseError::VersionTooNewError(version, FILE_VERSION));
+        }
+
+        if version < MIN_FILE_VERSION {
+            return Err(HeaderParseError::VersionTooOldError(
                 version,
-                FILE_VERSION,
+                MIN_FILE_VERSION,
             ));
         }
 
@@ -834,6 +1179,39 @@ macro_rules! gen_get_by_name {
     };
 }
 
+macro_rules! gen_get_by_index {
+    ($(#[$ref_attr:meta])* => $func_name: ident, $(#[$mut_attr:meta])* => $mut_func_name: ident, $variant: ident, $section_type: ty) => {
+        $(#[$ref_attr])*
+        pub fn $func_name(&self, index: SectionIdx) -> Result<&$section_type, SectionAccessError> {
+            match self.get_section(index) {
+                Some(Section::$variant(section)) => Ok(section),
+                Some(section) => Err(SectionAccessError::SectionKindMismatchError(
+                    u16::from(index),
+                    SectionKind::$variant,
+                    section.kind(),
+                )),
+                None => Err(SectionAccessError::MissingSectionError(u16::from(index))),
+            }
+        }
+
+        $(#[$mut_attr])*
+        pub fn $mut_func_name(
+            &mut self,
+            index: SectionIdx,
+        ) -> Result<&mut $section_type, SectionAccessError> {
+            match self.get_section_mut(index) {
+                Some(SectionMut::$variant(section)) => Ok(section),
+                Some(section) => Err(SectionAccessError::SectionKindMismatchError(
+                    u16::from(index),
+                    SectionKind::$variant,
+                    section.kind(),
+                )),
+                None => Err(SectionAccessError::MissingSectionError(u16::from(index))),
+            }
+        }
+    };
+}
+
 macro_rules! gen_new_section {
     ($(#[$attr:meta])* => $func_name: ident, $section_type: ty, $section_kind: expr) => {
         $(#[$attr])*
@@ -961,4 +1339,69 @@ impl KOFile {
         reld_sections,
         ReldSection
     );
+
+    gen_get_by_index! {
+        /// Gets a reference to the StringTable at the provided section header index. Fails if there is
+        /// no section at that index, or if it is a different kind of section.
+        =>
+        str_tab_at,
+        /// Gets a mutable reference to the StringTable at the provided section header index. Fails if
+        /// there is no section at that index, or if it is a different kind of section.
+        =>
+        str_tab_at_mut,
+        StrTab,
+        StringTable
+    }
+
+    gen_get_by_index! {
+        /// Gets a reference to the SymbolTable at the provided section header index. Fails if there is
+        /// no section at that index, or if it is a different kind of section.
+        =>
+        sym_tab_at,
+        /// Gets a mutable reference to the SymbolTable at the provided section header index. Fails if
+        /// there is no section at that index, or if it is a different kind of section.
+        =>
+        sym_tab_at_mut,
+        SymTab,
+        SymbolTable
+    }
+
+    gen_get_by_index! {
+        /// Gets a reference to the DataSection at the provided section header index. Fails if there is
+        /// no section at that index, or if it is a different kind of section.
+        =>
+        data_section_at,
+        /// Gets a mutable reference to the DataSection at the provided section header index. Fails if
+        /// there is no section at that index, or if it is a different kind of section.
+        =>
+        data_section_at_mut,
+        Data,
+        DataSection
+    }
+
+    gen_get_by_index! {
+        /// Gets a reference to the FuncSection at the provided section header index. Fails if there is
+        /// no section at that index, or if it is a different kind of section.
+        =>
+        func_section_at,
+        /// Gets a mutable reference to the FuncSection at the provided section header index. Fails if
+        /// there is no section at that index, or if it is a different kind of section.
+        =>
+        func_section_at_mut,
+        Func,
+        FuncSection
+    }
+
+    gen_get_by_index! {
+        /// Gets a reference to the ReldSection at the provided section header index. Fails if there is
+        /// no section at that index, or if it is a different kind of section.
+        =>
+        reld_section_at,
+        /// Gets a mutable reference to the ReldSection at the provided section header index. Fails if
+        /// there is no section at that index, or if it is a different kind of section.
+        =>
+        reld_section_at_mut,
+        Reld,
+        ReldSection
+    }
 }
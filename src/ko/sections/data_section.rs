@@ -156,7 +156,7 @@ impl DataSection {
     }
 
     /// Returns an iterator over all KOSValues in this data section
-    pub fn data(&self) -> Iter<KOSValue> {
+    pub fn data(&self) -> Iter<'_, KOSValue> {
         self.data.iter()
     }
 
@@ -184,7 +184,7 @@ impl DataSection {
             let kos_value = KOSValue::from_bytes(source).map_err(|e| {
                 DataSectionParseError::KOSValueParseError(source.current_index(), e)
             })?;
-            bytes_read += kos_value.size_bytes() as usize;
+            bytes_read += kos_value.size_bytes();
 
             let mut hasher = DefaultHasher::new();
             kos_value.hash(&mut hasher);
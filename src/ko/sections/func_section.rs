@@ -87,7 +87,7 @@ impl FuncSection {
     pub fn add(&mut self, instr: Instr) -> InstrIdx {
         let index = self.instructions.len();
 
-        self.size += instr.size_bytes() as u32;
+        self.size += instr.size_bytes();
         self.instructions.push(instr);
 
         InstrIdx::from(index)
@@ -104,7 +104,7 @@ impl FuncSection {
     }
 
     /// Returns an iterator over all of the instructions in this section
-    pub fn instructions(&self) -> Iter<Instr> {
+    pub fn instructions(&self) -> Iter<'_, Instr> {
         self.instructions.iter()
     }
 
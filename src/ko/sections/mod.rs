@@ -8,6 +8,7 @@ mod string_table;
 mod symbol_table;
 
 use crate::ko::errors::SectionHeaderParseError;
+use crate::ko::SectionIdx;
 pub use data_section::*;
 pub use func_section::*;
 pub use reld_section::*;
@@ -66,6 +67,88 @@ impl From<SectionKind> for u8 {
     }
 }
 
+/// A reference to a section of a KO file of any kind.
+///
+/// See [KOFile::get_section](crate::ko::KOFile::get_section)
+#[derive(Debug, Clone, Copy)]
+pub enum Section<'a> {
+    /// A string table
+    StrTab(&'a StringTable),
+    /// A symbol table
+    SymTab(&'a SymbolTable),
+    /// A data section
+    Data(&'a DataSection),
+    /// A function section
+    Func(&'a FuncSection),
+    /// A relocation data section
+    Reld(&'a ReldSection),
+}
+
+impl Section<'_> {
+    /// The kind of this section
+    pub fn kind(&self) -> SectionKind {
+        match self {
+            Self::StrTab(_) => SectionKind::StrTab,
+            Self::SymTab(_) => SectionKind::SymTab,
+            Self::Data(_) => SectionKind::Data,
+            Self::Func(_) => SectionKind::Func,
+            Self::Reld(_) => SectionKind::Reld,
+        }
+    }
+
+    /// The index of this section's section header
+    pub fn section_index(&self) -> SectionIdx {
+        match self {
+            Self::StrTab(section) => section.section_index(),
+            Self::SymTab(section) => section.section_index(),
+            Self::Data(section) => section.section_index(),
+            Self::Func(section) => section.section_index(),
+            Self::Reld(section) => section.section_index(),
+        }
+    }
+
+    /// The size of this section in bytes
+    pub fn size(&self) -> u32 {
+        match self {
+            Self::StrTab(section) => section.size(),
+            Self::SymTab(section) => section.size(),
+            Self::Data(section) => section.size(),
+            Self::Func(section) => section.size(),
+            Self::Reld(section) => section.size(),
+        }
+    }
+}
+
+/// A mutable reference to a section of a KO file of any kind.
+///
+/// See [KOFile::get_section_mut](crate::ko::KOFile::get_section_mut)
+#[derive(Debug)]
+pub enum SectionMut<'a> {
+    /// A string table
+    StrTab(&'a mut StringTable),
+    /// A symbol table
+    SymTab(&'a mut SymbolTable),
+    /// A data section
+    Data(&'a mut DataSection),
+    /// A function section
+    Func(&'a mut FuncSection),
+    /// A relocation data section
+    Reld(&'a mut ReldSection),
+}
+
+impl SectionMut<'_> {
+    /// The kind of this section
+    pub fn kind(&self) -> SectionKind {
+        match self {
+            Self::StrTab(_) => SectionKind::StrTab,
+            Self::SymTab(_) => SectionKind::SymTab,
+            Self::Data(_) => SectionKind::Data,
+            Self::Func(_) => SectionKind::Func,
+            Self::Reld(_) => SectionKind::Reld,
+        }
+    }
+}
+
 /// A Kerbal Object file section header entry
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SectionHeader {
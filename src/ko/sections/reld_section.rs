@@ -1,7 +1,7 @@
 //! A module describing a relocation data section in a Kerbal Object file
 use crate::ko::errors::ReldSectionParseError;
 use crate::ko::symbols::ReldEntry;
-use crate::ko::SectionIdx;
+use crate::ko::{SectionIdx, FILE_VERSION};
 use crate::{BufferIterator, WritableBuffer};
 use std::slice::Iter;
 
@@ -99,7 +99,7 @@ impl ReldSection {
     }
 
     /// Returns an iterator over all relocation data entries in this section
-    pub fn entries(&self) -> Iter<ReldEntry> {
+    pub fn entries(&self) -> Iter<'_, ReldEntry> {
         self.entries.iter()
     }
 
@@ -108,34 +108,57 @@ impl ReldSection {
         self.size
     }
 
+    /// The size of this relocation data section in bytes, when it is written as the provided KO
+    /// file version
+    pub fn size_for_version(&self, version: u8) -> u32 {
+        self.entries
+            .iter()
+            .map(|entry| entry.size_bytes_for_version(version))
+            .sum()
+    }
+
     /// The index of this section's section header
     pub fn section_index(&self) -> SectionIdx {
         self.section_index
     }
 
-    /// Parses a relocation data section from the provided byte buffer
+    /// Parses a relocation data section from the provided byte buffer, in the format of the
+    /// current KO file version
     pub fn parse(
         source: &mut BufferIterator,
         size: u32,
         section_index: SectionIdx,
     ) -> Result<Self, ReldSectionParseError> {
-        let mut bytes_read = 0;
-        let mut entries = Vec::new();
+        Self::parse_version(source, size, section_index, FILE_VERSION)
+    }
 
-        while bytes_read < size {
-            let entry = ReldEntry::parse(source).map_err(|e| {
-                ReldSectionParseError::ReldEntryParseError(entries.len(), source.current_index(), e)
+    /// Parses a relocation data section from the provided byte buffer, in the format of the
+    /// provided KO file version.
+    ///
+    /// Entries from older versions are upgraded, so the size of the parsed section is the size
+    /// that it will be written with, which may be different from the provided size.
+    pub fn parse_version(
+        source: &mut BufferIterator,
+        size: u32,
+        section_index: SectionIdx,
+        version: u8,
+    ) -> Result<Self, ReldSectionParseError> {
+        let start = source.current_index();
+        let mut section = Self::new(section_index);
+
+        while ((source.current_index() - start) as u32) < size {
+            let entry = ReldEntry::parse_version(source, version).map_err(|e| {
+                ReldSectionParseError::ReldEntryParseError(
+                    section.entries.len(),
+                    source.current_index(),
+                    e,
+                )
             })?;
-            bytes_read += entry.size_bytes();
 
-            entries.push(entry);
+            section.add(entry);
         }
 
-        Ok(Self {
-            entries,
-            size,
-            section_index,
-        })
+        Ok(section)
     }
 
     /// Converts this relocation data section to its binary representation and appends it to the provided buffer
@@ -144,4 +167,12 @@ impl ReldSection {
             reld_entry.write(buf);
         }
     }
+
+    /// Converts this relocation data section to its binary representation in the format of the
+    /// provided KO file version, and appends it to the provided buffer
+    pub fn write_version(&self, buf: &mut impl WritableBuffer, version: u8) {
+        for reld_entry in self.entries.iter() {
+            reld_entry.write_version(buf, version);
+        }
+    }
 }
@@ -3,7 +3,7 @@
 use crate::ko::errors::SymbolTableParseError;
 use crate::ko::sections::StringIdx;
 use crate::ko::symbols::KOSymbol;
-use crate::ko::SectionIdx;
+use crate::ko::{SectionIdx, FILE_VERSION};
 use crate::{BufferIterator, WritableBuffer};
 use std::collections::HashMap;
 use std::slice::Iter;
@@ -137,13 +137,29 @@ impl SymbolTable {
         }
     }
 
+    /// Replaces the symbol at the provided index, returning the old symbol, or None if there is
+    /// no symbol at that index
+    pub fn set(&mut self, index: SymbolIdx, symbol: KOSymbol) -> Option<KOSymbol> {
+        let position = usize::from(index);
+        let old = *self.symbols.get(position)?;
+
+        if self.name_map.get(&old.name_idx) == Some(&position) {
+            self.name_map.remove(&old.name_idx);
+        }
+
+        self.name_map.insert(symbol.name_idx, position);
+        self.symbols[position] = symbol;
+
+        Some(old)
+    }
+
     /// The size of this symbol table section in bytes
     pub fn size(&self) -> u32 {
         self.size
     }
 
     /// Returns an iterator over all symbols in this symbol table
-    pub fn symbols(&self) -> Iter<KOSymbol> {
+    pub fn symbols(&self) -> Iter<'_, KOSymbol> {
         self.symbols.iter()
     }
 
@@ -152,11 +168,23 @@ impl SymbolTable {
         self.section_index
     }
 
-    /// Parses a symbol table from the provided byte buffer
+    /// Parses a symbol table from the provided byte buffer, in the format of the current KO file
+    /// version
     pub fn parse(
         source: &mut BufferIterator,
         size: u32,
         section_index: SectionIdx,
+    ) -> Result<Self, SymbolTableParseError> {
+        Self::parse_version(source, size, section_index, FILE_VERSION)
+    }
+
+    /// Parses a symbol table from the provided byte buffer, in the format of the provided KO file
+    /// version
+    pub fn parse_version(
+        source: &mut BufferIterator,
+        size: u32,
+        section_index: SectionIdx,
+        version: u8,
     ) -> Result<Self, SymbolTableParseError> {
         let num_symbols = size / KOSymbol::size_bytes();
         let mut num_read_symbols = 0;
@@ -164,7 +192,7 @@ impl SymbolTable {
         let mut sym_tab = SymbolTable::with_capacity(num_symbols as usize, section_index);
 
         while num_read_symbols * KOSymbol::size_bytes() < size {
-            let symbol = KOSymbol::parse(source).map_err(|e| {
+            let symbol = KOSymbol::parse_version(source, version).map_err(|e| {
                 SymbolTableParseError::SymbolParseError(
                     num_read_symbols as usize,
                     source.current_index(),
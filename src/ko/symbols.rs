@@ -3,7 +3,7 @@ use crate::{BufferIterator, FromBytes, ToBytes, WritableBuffer};
 
 use crate::ko::errors::{ReldEntryParseError, SymbolParseError};
 use crate::ko::sections::{DataIdx, InstrIdx, StringIdx, SymbolIdx};
-use crate::ko::SectionIdx;
+use crate::ko::{FormatFeature, SectionIdx, FILE_VERSION};
 
 /// Represents the "binding" of a symbol in the symbol table.
 ///
@@ -18,8 +18,16 @@ pub enum SymBind {
     /// This symbol is visible to the linker from other object files.
     Global = 1,
     /// This symbol doesn't exist in the current object file, it is external.
-    /// This should be matched with a corresponding symbol of the same name marked as Global
+    /// This should be matched with a corresponding symbol of the same name marked as Global or Weak
     Extern = 2,
+    /// This symbol is visible to the linker from other object files like a Global symbol, but a
+    /// Global symbol with the same name overrides it.
+    ///
+    /// A Weak symbol with a null section index isn't defined in the current object file, and is
+    /// replaced with a null value if no other file defines it, instead of being an error.
+    ///
+    /// This binding was added in version 6 of the format.
+    Weak = 3,
 }
 
 impl TryFrom<u8> for SymBind {
@@ -30,6 +38,7 @@ impl TryFrom<u8> for SymBind {
             0 => Ok(Self::Local),
             1 => Ok(Self::Global),
             2 => Ok(Self::Extern),
+            3 => Ok(Self::Weak),
             _ => Err(()),
         }
     }
@@ -41,6 +50,7 @@ impl From<SymBind> for u8 {
             SymBind::Local => 0,
             SymBind::Global => 1,
             SymBind::Extern => 2,
+            SymBind::Weak => 3,
         }
     }
 }
@@ -51,7 +61,8 @@ impl From<SymBind> for u8 {
 pub enum SymType {
     /// No type, used for any type of data, such as KOSValues in a data section.
     NoType = 0,
-    /// An object. Specified for forward-compatibility, currently unused.
+    /// A global variable, whose initial value is the value in a data section that the symbol
+    /// refers to.
     Object = 1,
     /// A function defined elsewhere in the file.
     Func = 2,
@@ -123,6 +134,55 @@ impl From<OperandIndex> for u8 {
     }
 }
 
+/// The kind of a relocation, which describes how the symbol that it refers to becomes the value
+/// of the operand.
+///
+/// Relocation kinds other than [ReldKind::Symbol] were added in version 5 of the format. Entries
+/// read from older files are always [ReldKind::Symbol] with an addend of 0.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr(u8)]
+pub enum ReldKind {
+    /// The operand is replaced with the value that a data symbol refers to, or the name of a
+    /// function symbol's function.
+    Symbol = 0,
+    /// The operand is replaced with the relative branch offset from the instruction to the
+    /// instruction of the symbol's function at the index given by the addend. Both functions must
+    /// end up in the same code section.
+    Branch = 1,
+    /// The operand is replaced with the label of the symbol's function, which is what Pdrl needs
+    /// to create a delegate. The symbol must be a function, and the instruction must be a Pdrl.
+    Delegate = 2,
+    /// The operand is replaced with the value that a data symbol refers to plus the addend. The
+    /// value must be a number.
+    SymbolAddend = 3,
+}
+
+impl TryFrom<u8> for ReldKind {
+    type Error = ();
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0 => Ok(Self::Symbol),
+            1 => Ok(Self::Branch),
+            2 => Ok(Self::Delegate),
+            3 => Ok(Self::SymbolAddend),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ReldKind> for u8 {
+    fn from(kind: ReldKind) -> u8 {
+        match kind {
+            ReldKind::Symbol => 0,
+            ReldKind::Branch => 1,
+            ReldKind::Delegate => 2,
+            ReldKind::SymbolAddend => 3,
+        }
+    }
+}
+
 /// Represents a symbol in a symbol table in a Kerbal Object file
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct KOSymbol {
@@ -168,8 +228,26 @@ impl KOSymbol {
         Self::SYMBOL_SIZE
     }
 
-    /// Parses a KOSymbol from the provided buffer
+    /// Returns true if this symbol refers to something that isn't defined in its object file,
+    /// which is an Extern symbol, or a Weak symbol with a null section index
+    pub fn is_undefined(&self) -> bool {
+        match self.sym_bind {
+            SymBind::Extern => true,
+            SymBind::Weak => self.sh_idx == SectionIdx::NULL,
+            SymBind::Local | SymBind::Global => false,
+        }
+    }
+
+    /// Parses a KOSymbol from the provided buffer, in the format of the current KO file version
     pub fn parse(source: &mut BufferIterator) -> Result<Self, SymbolParseError> {
+        Self::parse_version(source, FILE_VERSION)
+    }
+
+    /// Parses a KOSymbol from the provided buffer, in the format of the provided KO file version
+    pub fn parse_version(
+        source: &mut BufferIterator,
+        version: u8,
+    ) -> Result<Self, SymbolParseError> {
         let name_idx = StringIdx::from(
             u32::from_bytes(source).map_err(|_| SymbolParseError::MissingNameIndexError)?,
         );
@@ -180,7 +258,11 @@ impl KOSymbol {
         let raw_sym_bind =
             u8::from_bytes(source).map_err(|_| SymbolParseError::MissingSymbolBindingError)?;
         let sym_bind = SymBind::try_from(raw_sym_bind)
-            .map_err(|_| SymbolParseError::InvalidSymbolBindingError(raw_sym_bind))?;
+            .ok()
+            .filter(|&sym_bind| {
+                sym_bind != SymBind::Weak || version >= FormatFeature::WeakSymbols.min_version()
+            })
+            .ok_or(SymbolParseError::InvalidSymbolBindingError(raw_sym_bind))?;
         let raw_sym_type =
             u8::from_bytes(source).map_err(|_| SymbolParseError::MissingSymbolTypeError)?;
         let sym_type = SymType::try_from(raw_sym_type)
@@ -221,16 +303,23 @@ pub struct ReldEntry {
     pub operand_index: OperandIndex,
     /// The index into the symbol table which should replace this operand
     pub symbol_index: SymbolIdx,
+    /// How the symbol becomes the value of the operand
+    pub kind: ReldKind,
+    /// A number that is added to the symbol, whose meaning depends on the kind of relocation
+    pub addend: i32,
 }
 
 impl ReldEntry {
-    /// The size of a reld entry in bytes
-    const RELD_ENTRY_SIZE: u32 = (std::mem::size_of::<u16>()
+    /// The size of a version 4 reld entry in bytes
+    const V4_RELD_ENTRY_SIZE: u32 = (std::mem::size_of::<u16>()
         + std::mem::size_of::<u32>()
         + std::mem::size_of::<u8>()
         + std::mem::size_of::<u32>()) as u32;
+    /// The size of a reld entry in bytes, which also stores the kind and addend
+    const RELD_ENTRY_SIZE: u32 =
+        Self::V4_RELD_ENTRY_SIZE + (std::mem::size_of::<u8>() + std::mem::size_of::<i32>()) as u32;
 
-    /// Creates a new relocation data entry
+    /// Creates a new relocation data entry that replaces the operand with the symbol
     pub const fn new(
         section_index: SectionIdx,
         instr_index: InstrIdx,
@@ -242,16 +331,50 @@ impl ReldEntry {
             instr_index,
             operand_index,
             symbol_index,
+            kind: ReldKind::Symbol,
+            addend: 0,
         }
     }
 
+    /// Returns this entry with the provided kind of relocation
+    pub const fn with_kind(mut self, kind: ReldKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Returns this entry with the provided addend
+    pub const fn with_addend(mut self, addend: i32) -> Self {
+        self.addend = addend;
+        self
+    }
+
     /// The size of this relocation data entry in bytes
     pub const fn size_bytes(&self) -> u32 {
         Self::RELD_ENTRY_SIZE
     }
 
-    /// Parses a ReldEntry from the provided byte buffer
+    /// The size of this relocation data entry in bytes, when it is written as the provided KO file
+    /// version
+    pub const fn size_bytes_for_version(&self, version: u8) -> u32 {
+        if version < FormatFeature::RelocationKinds.min_version() {
+            Self::V4_RELD_ENTRY_SIZE
+        } else {
+            Self::RELD_ENTRY_SIZE
+        }
+    }
+
+    /// Parses a ReldEntry from the provided byte buffer, in the format of the current KO file
+    /// version
     pub fn parse(source: &mut BufferIterator) -> Result<Self, ReldEntryParseError> {
+        Self::parse_version(source, FILE_VERSION)
+    }
+
+    /// Parses a ReldEntry from the provided byte buffer, in the format of the provided KO file
+    /// version
+    pub fn parse_version(
+        source: &mut BufferIterator,
+        version: u8,
+    ) -> Result<Self, ReldEntryParseError> {
         let section_index = SectionIdx::from(
             u16::from_bytes(source).map_err(|_| ReldEntryParseError::MissingSectionIndexError)?,
         );
@@ -267,27 +390,47 @@ impl ReldEntry {
             u32::from_bytes(source).map_err(|_| ReldEntryParseError::MissingSymbolIndexError)?,
         );
 
-        Ok(Self {
-            section_index,
-            instr_index,
-            operand_index,
-            symbol_index,
-        })
+        let entry = Self::new(section_index, instr_index, operand_index, symbol_index);
+
+        // Older files only have one kind of relocation
+        if version < FormatFeature::RelocationKinds.min_version() {
+            return Ok(entry);
+        }
+
+        let raw_kind = u8::from_bytes(source).map_err(|_| ReldEntryParseError::MissingKindError)?;
+        let kind = ReldKind::try_from(raw_kind)
+            .map_err(|_| ReldEntryParseError::InvalidKindError(raw_kind))?;
+        let addend =
+            i32::from_bytes(source).map_err(|_| ReldEntryParseError::MissingAddendError)?;
+
+        Ok(entry.with_kind(kind).with_addend(addend))
     }
 
     /// Converts this ReldEntry to its binary representation and appends it to the provided buffer
     pub fn write(&self, buf: &mut impl WritableBuffer) {
+        self.write_version(buf, FILE_VERSION);
+    }
+
+    /// Converts this ReldEntry to its binary representation in the format of the provided KO file
+    /// version, and appends it to the provided buffer. The kind and addend aren't written for
+    /// versions that don't have them.
+    pub fn write_version(&self, buf: &mut impl WritableBuffer, version: u8) {
         u16::from(self.section_index).to_bytes(buf);
         u32::from(self.instr_index).to_bytes(buf);
         u8::from(self.operand_index).to_bytes(buf);
         u32::from(self.symbol_index).to_bytes(buf);
+
+        if version >= FormatFeature::RelocationKinds.min_version() {
+            u8::from(self.kind).to_bytes(buf);
+            self.addend.to_bytes(buf);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ko::sections::{DataIdx, StringIdx};
-    use crate::ko::symbols::{KOSymbol, SymBind, SymType};
+    use crate::ko::sections::{DataIdx, InstrIdx, StringIdx, SymbolIdx};
+    use crate::ko::symbols::{KOSymbol, OperandIndex, ReldEntry, ReldKind, SymBind, SymType};
     use crate::ko::SectionIdx;
     use crate::BufferIterator;
 
@@ -312,4 +455,61 @@ mod tests {
 
         assert_eq!(symbol, read);
     }
+
+    #[test]
+    fn weak_symbols_need_version_6() {
+        let mut buffer = Vec::new();
+
+        let symbol = KOSymbol::new(
+            StringIdx::from(1u32),
+            DataIdx::PLACEHOLDER,
+            0,
+            SymBind::Weak,
+            SymType::Func,
+            SectionIdx::NULL,
+        );
+
+        symbol.write(&mut buffer);
+
+        assert!(symbol.is_undefined());
+        assert!(KOSymbol::parse_version(&mut BufferIterator::new(&buffer), 6).is_ok());
+        assert!(KOSymbol::parse_version(&mut BufferIterator::new(&buffer), 5).is_err());
+    }
+
+    #[test]
+    fn read_write_reld_entry() {
+        let mut buffer = Vec::new();
+
+        let entry = ReldEntry::new(
+            SectionIdx::from(7u16),
+            InstrIdx::from(2u32),
+            OperandIndex::One,
+            SymbolIdx::from(1u32),
+        )
+        .with_kind(ReldKind::Branch)
+        .with_addend(-3);
+
+        entry.write(&mut buffer);
+
+        assert_eq!(buffer.len() as u32, entry.size_bytes());
+
+        let mut iter = BufferIterator::new(&buffer);
+
+        assert_eq!(ReldEntry::parse_version(&mut iter, 5).unwrap(), entry);
+    }
+
+    #[test]
+    fn read_version_4_reld_entry() {
+        let buffer = [
+            0x07, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00,
+        ];
+        let mut iter = BufferIterator::new(&buffer);
+
+        let entry = ReldEntry::parse_version(&mut iter, 4).unwrap();
+
+        assert_eq!(entry.kind, ReldKind::Symbol);
+        assert_eq!(entry.addend, 0);
+        assert_eq!(u32::from(entry.symbol_index), 1);
+        assert!(iter.peek().is_none());
+    }
 }
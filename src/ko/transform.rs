@@ -0,0 +1,481 @@
+//! # Transformations
+//!
+//! A module containing operations that rewrite the symbols and string tables of an existing
+//! KOFile, while keeping every reference to them, such as the symbol index of each relocation
+//! data entry, up to date.
+//!
+//! These work on the .symtab of a file, which is the symbol table that the linker reads, and on
+//! the string tables that store the names of its symbols and sections.
+//!
+//! ```
+//! use kerbalobjects::ko::symbols::SymBind;
+//! use kerbalobjects::ko::transform::CanonicalizeOptions;
+//! use kerbalobjects::ko::KOFileBuilder;
+//!
+//! let mut builder = KOFileBuilder::new();
+//! builder.define_function("_start", SymBind::Global).unwrap();
+//! builder.define_function("helper", SymBind::Local).unwrap();
+//!
+//! let mut ko = builder.finish().unwrap().get();
+//!
+//! ko.canonicalize_symbols(&CanonicalizeOptions::default());
+//!
+//! let names: Vec<&str> = ko.named_symbols().map(|(name, _)| name).collect();
+//!
+//! assert_eq!(names, vec!["helper", "_start"]);
+//! ```
+//!
+use std::collections::HashMap;
+
+use crate::ko::errors::TransformError;
+use crate::ko::sections::{ReldSection, StringTable, SymbolIdx, SymbolTable};
+use crate::ko::symbols::{KOSymbol, ReldEntry, SymBind, SymType};
+use crate::ko::KOFile;
+
+/// Options that change what [KOFile::canonicalize_symbols] removes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CanonicalizeOptions {
+    /// Remove Local symbols that are exactly the same as an earlier Local symbol, and refer to
+    /// the earlier one instead
+    pub drop_duplicate_locals: bool,
+    /// Remove Local NoType and Section symbols that no relocation data entry refers to. Function,
+    /// variable, and File symbols describe the file itself, so they are always kept.
+    pub drop_unreferenced_locals: bool,
+}
+
+impl KOFile {
+    /// Sorts the symbols of this file's .symtab by their binding, in the order Local, Global,
+    /// Extern, then Weak, so that Local symbols come first like in ELF files. Symbols with the
+    /// same binding are sorted by name, and symbols with the same binding and name keep their
+    /// order.
+    ///
+    /// Every relocation data entry is updated to refer to the new index of its symbol. The
+    /// returned map has the new index of every symbol that was kept, by its old index.
+    ///
+    /// Symbols are ordered the same way no matter what order they were added in, so files that
+    /// define the same things are written the same way.
+    pub fn canonicalize_symbols(
+        &mut self,
+        options: &CanonicalizeOptions,
+    ) -> HashMap<SymbolIdx, SymbolIdx> {
+        let (position, symstrtab) = match (self.symtab_position(), self.symstrtab()) {
+            (Some(position), Some(symstrtab)) => (position, symstrtab),
+            _ => return HashMap::new(),
+        };
+
+        let symbols: Vec<&KOSymbol> = self.sym_tabs[position].symbols().collect();
+        let referenced = self.referenced_symbols(symbols.len());
+
+        // The index of the symbol that each symbol is replaced with, which is itself unless it
+        // is a duplicate
+        let mut replacements: Vec<usize> = (0..symbols.len()).collect();
+        let mut kept: Vec<usize> = Vec::with_capacity(symbols.len());
+
+        for (index, symbol) in symbols.iter().enumerate() {
+            if symbol.sym_bind == SymBind::Local {
+                if options.drop_duplicate_locals {
+                    if let Some(&original) = kept.iter().find(|&&other| symbols[other] == *symbol) {
+                        replacements[index] = original;
+                        continue;
+                    }
+                }
+
+                let removable = matches!(symbol.sym_type, SymType::NoType | SymType::Section);
+
+                if options.drop_unreferenced_locals && removable && !referenced[index] {
+                    continue;
+                }
+            }
+
+            kept.push(index);
+        }
+
+        let name = |index: usize| {
+            symstrtab
+                .get(symbols[index].name_idx)
+                .map(String::as_str)
+                .unwrap_or_default()
+        };
+
+        kept.sort_by(|&a, &b| {
+            let bind = |index: usize| u8::from(symbols[index].sym_bind);
+
+            bind(a).cmp(&bind(b)).then_with(|| name(a).cmp(name(b)))
+        });
+
+        self.replace_symbols(position, &kept, &replacements)
+    }
+
+    /// Rebuilds the .shstrtab and .symstrtab of this file so that they only contain the names of
+    /// the section headers and of the symbols in the .symtab, with each name stored once. Every
+    /// section header and symbol is updated to refer to the new index of its name.
+    ///
+    /// Strings are indexed by their position in the table and not by their byte offset, so
+    /// strings can't share their suffixes with each other like they can in ELF files.
+    ///
+    /// Returns the number of bytes that the string tables got smaller by.
+    pub fn compact_string_tables(&mut self) -> u32 {
+        let before =
+            self.shstrtab.size() + self.str_tabs.iter().map(StringTable::size).sum::<u32>();
+
+        let mut shstrtab =
+            StringTable::with_capacity(self.section_headers.len(), self.shstrtab.section_index());
+
+        for header in self.section_headers.iter_mut() {
+            let name = self
+                .shstrtab
+                .get(header.name_idx)
+                .cloned()
+                .unwrap_or_default();
+
+            header.name_idx = shstrtab.add_checked(name);
+        }
+
+        self.shstrtab = shstrtab;
+
+        if let (Some(symtab), Some(symstrtab)) = (self.symtab_position(), self.symstrtab_position())
+        {
+            let old_symtab = &self.sym_tabs[symtab];
+            let old_symstrtab = &self.str_tabs[symstrtab];
+
+            let mut new_symtab =
+                SymbolTable::with_capacity(old_symtab.symbols().len(), old_symtab.section_index());
+            let mut new_symstrtab = StringTable::with_capacity(
+                old_symtab.symbols().len(),
+                old_symstrtab.section_index(),
+            );
+
+            for symbol in old_symtab.symbols() {
+                let name = old_symstrtab
+                    .get(symbol.name_idx)
+                    .cloned()
+                    .unwrap_or_default();
+
+                new_symtab.add(KOSymbol {
+                    name_idx: new_symstrtab.add_checked(name),
+                    ..*symbol
+                });
+            }
+
+            self.sym_tabs[symtab] = new_symtab;
+            self.str_tabs[symstrtab] = new_symstrtab;
+        }
+
+        let after = self.shstrtab.size() + self.str_tabs.iter().map(StringTable::size).sum::<u32>();
+
+        before.saturating_sub(after)
+    }
+
+    /// Renames the symbol with the provided name. Relocations refer to symbols by their index,
+    /// so they refer to the renamed symbol afterwards.
+    ///
+    /// This fails if there is no symbol with the old name, or if there is already a symbol with
+    /// the new name.
+    pub fn rename_symbol(
+        &mut self,
+        name: impl AsRef<str>,
+        new_name: impl Into<String>,
+    ) -> Result<(), TransformError> {
+        let new_name = new_name.into();
+        let (position, index, symbol) = self.find_symbol(name.as_ref())?;
+
+        if self.symbol_index_by_name(&new_name).is_some() {
+            return Err(TransformError::DuplicateSymbolError(new_name));
+        }
+
+        let symstrtab = self
+            .symstrtab_position()
+            .ok_or_else(|| TransformError::MissingSymbolError(name.as_ref().to_string()))?;
+        let name_idx = self.str_tabs[symstrtab].add_checked(new_name);
+
+        self.sym_tabs[position].set(index, KOSymbol { name_idx, ..symbol });
+
+        Ok(())
+    }
+
+    /// Makes the symbol with the provided name Local, so that other files can't link against
+    /// it, like hiding the internals of a library.
+    ///
+    /// This fails if there is no symbol with that name, or if the symbol isn't defined in this
+    /// file.
+    pub fn localize_symbol(&mut self, name: impl AsRef<str>) -> Result<(), TransformError> {
+        self.set_symbol_binding(name.as_ref(), SymBind::Local)
+    }
+
+    /// Makes the symbol with the provided name Global, so that other files can link against it.
+    ///
+    /// This fails if there is no symbol with that name, or if the symbol isn't defined in this
+    /// file.
+    pub fn globalize_symbol(&mut self, name: impl AsRef<str>) -> Result<(), TransformError> {
+        self.set_symbol_binding(name.as_ref(), SymBind::Global)
+    }
+
+    /// Removes every Local symbol from this file's .symtab, except for those that relocation data
+    /// entries refer to, which the linker still needs, and variables, which the linker
+    /// initializes using their symbols. Relocation data entries are updated to refer to the new
+    /// indexes of their symbols.
+    ///
+    /// Returns the number of symbols that were removed.
+    pub fn strip_local_symbols(&mut self) -> usize {
+        self.remove_symbols(|symbol, referenced| {
+            symbol.sym_bind == SymBind::Local && symbol.sym_type != SymType::Object && !referenced
+        })
+    }
+
+    /// Removes the File symbol from this file's .symtab, so that the name of the source file
+    /// doesn't end up in the file. Relocation data entries are updated to refer to the new
+    /// indexes of their symbols.
+    ///
+    /// Returns true if there was a File symbol to remove.
+    pub fn strip_file_symbol(&mut self) -> bool {
+        self.remove_symbols(|symbol, _| symbol.sym_type == SymType::File) > 0
+    }
+
+    fn set_symbol_binding(&mut self, name: &str, sym_bind: SymBind) -> Result<(), TransformError> {
+        let (position, index, symbol) = self.find_symbol(name)?;
+
+        if symbol.is_undefined() || symbol.sym_type == SymType::File {
+            return Err(TransformError::UndefinedSymbolError(name.to_string()));
+        }
+
+        self.sym_tabs[position].set(index, KOSymbol { sym_bind, ..symbol });
+
+        Ok(())
+    }
+
+    // Returns the position of the .symtab, and the index of the symbol with the provided name
+    fn find_symbol(&self, name: &str) -> Result<(usize, SymbolIdx, KOSymbol), TransformError> {
+        let missing = || TransformError::MissingSymbolError(name.to_string());
+
+        let position = self.symtab_position().ok_or_else(missing)?;
+        let index = self.symbol_index_by_name(name).ok_or_else(missing)?;
+        let symbol = *self.sym_tabs[position].get(index).ok_or_else(missing)?;
+
+        Ok((position, index, symbol))
+    }
+
+    // Removes every symbol that the provided function returns true for, which is also passed
+    // whether or not a relocation data entry refers to the symbol. Symbols that relocations
+    // refer to should not be removed.
+    fn remove_symbols(&mut self, remove: impl Fn(&KOSymbol, bool) -> bool) -> usize {
+        let position = match self.symtab_position() {
+            Some(position) => position,
+            None => return 0,
+        };
+
+        let symbols: Vec<&KOSymbol> = self.sym_tabs[position].symbols().collect();
+        let referenced = self.referenced_symbols(symbols.len());
+
+        let kept: Vec<usize> = (0..symbols.len())
+            .filter(|&index| !remove(symbols[index], referenced[index]))
+            .collect();
+        let replacements: Vec<usize> = (0..symbols.len()).collect();
+        let removed = symbols.len() - kept.len();
+
+        self.replace_symbols(position, &kept, &replacements);
+
+        removed
+    }
+
+    // The position of the .symtab in the list of symbol tables
+    fn symtab_position(&self) -> Option<usize> {
+        let &(_, position) = self.section_positions.get(&self.symtab_index?)?;
+
+        Some(position)
+    }
+
+    // The position of the .symstrtab in the list of string tables
+    fn symstrtab_position(&self) -> Option<usize> {
+        let &(_, position) = self.section_positions.get(&self.symstrtab_index?)?;
+
+        Some(position)
+    }
+
+    // Whether or not a relocation data entry refers to each symbol of the .symtab
+    fn referenced_symbols(&self, num_symbols: usize) -> Vec<bool> {
+        let mut referenced = vec![false; num_symbols];
+
+        for entry in self.reld_sections.iter().flat_map(ReldSection::entries) {
+            if let Some(referenced) = referenced.get_mut(usize::from(entry.symbol_index)) {
+                *referenced = true;
+            }
+        }
+
+        referenced
+    }
+
+    // Replaces the symbol table at the provided position with the kept symbols, in the order
+    // they are listed. Relocations that refer to a symbol refer to the new index of the symbol in
+    // `replacements` at the symbol's index, which is usually the symbol itself.
+    fn replace_symbols(
+        &mut self,
+        position: usize,
+        kept: &[usize],
+        replacements: &[usize],
+    ) -> HashMap<SymbolIdx, SymbolIdx> {
+        let symtab = &self.sym_tabs[position];
+        let symbols: Vec<&KOSymbol> = symtab.symbols().collect();
+
+        let mut new_symtab = SymbolTable::with_capacity(kept.len(), symtab.section_index());
+        let mut new_indexes: HashMap<SymbolIdx, SymbolIdx> = HashMap::new();
+
+        for &index in kept.iter() {
+            let new_index = new_symtab.add(*symbols[index]);
+            new_indexes.insert(SymbolIdx::from(index), new_index);
+        }
+
+        for (index, &replacement) in replacements.iter().enumerate() {
+            if replacement != index {
+                let new_index = new_indexes[&SymbolIdx::from(replacement)];
+                new_indexes.insert(SymbolIdx::from(index), new_index);
+            }
+        }
+
+        self.sym_tabs[position] = new_symtab;
+
+        for reld_section in self.reld_sections.iter_mut() {
+            let mut new_reld = ReldSection::new(reld_section.section_index());
+
+            for entry in reld_section.entries() {
+                new_reld.add(ReldEntry {
+                    symbol_index: new_indexes
+                        .get(&entry.symbol_index)
+                        .copied()
+                        .unwrap_or(entry.symbol_index),
+                    ..*entry
+                });
+            }
+
+            *reld_section = new_reld;
+        }
+
+        new_indexes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ko::sections::SymbolIdx;
+    use crate::ko::symbols::{OperandIndex, SymBind};
+    use crate::ko::transform::CanonicalizeOptions;
+    use crate::ko::KOFileBuilder;
+    use crate::KOSValue;
+
+    #[test]
+    fn canonicalize_rewrites_relocations() {
+        let mut builder = KOFileBuilder::new();
+        let start = builder.define_function("_start", SymBind::Global).unwrap();
+        let greet = builder.reference_extern("greet");
+        let unused = builder
+            .define_value("unused", KOSValue::Int16(1), SymBind::Local)
+            .unwrap();
+        let helper = builder.define_function("helper", SymBind::Local).unwrap();
+
+        let mut emitter = builder.emitter(start);
+        let first = emitter.call("", "");
+        let second = emitter.call("", "");
+        emitter.eop();
+
+        builder.add_relocation(start, first, OperandIndex::One, greet);
+        builder.add_relocation(start, second, OperandIndex::One, helper.symbol_index());
+
+        let mut ko = builder.finish().unwrap().get();
+
+        let options = CanonicalizeOptions {
+            drop_duplicate_locals: true,
+            drop_unreferenced_locals: true,
+        };
+        let new_indexes = ko.canonicalize_symbols(&options);
+
+        let names: Vec<&str> = ko.named_symbols().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["helper", "_start", "greet"]);
+
+        assert!(!new_indexes.contains_key(&unused));
+        assert_eq!(new_indexes[&greet], SymbolIdx::from(2usize));
+
+        let symbols: Vec<SymbolIdx> = ko
+            .reld_sections()
+            .flat_map(|reld_section| reld_section.entries())
+            .map(|entry| entry.symbol_index)
+            .collect();
+
+        assert_eq!(
+            symbols,
+            vec![SymbolIdx::from(2usize), SymbolIdx::from(0usize)]
+        );
+    }
+
+    #[test]
+    fn compact_removes_unused_names() {
+        let mut builder = KOFileBuilder::new();
+        builder.define_function("_start", SymBind::Global).unwrap();
+        builder
+            .define_value("unused", KOSValue::Int16(1), SymBind::Local)
+            .unwrap();
+
+        let mut ko = builder.finish().unwrap().get();
+
+        let options = CanonicalizeOptions {
+            drop_unreferenced_locals: true,
+            ..Default::default()
+        };
+        ko.canonicalize_symbols(&options);
+
+        let size = ko.symstrtab().unwrap().size();
+        let removed = ko.compact_string_tables();
+
+        assert_eq!(removed, "unused".len() as u32 + 1);
+        assert_eq!(ko.symstrtab().unwrap().size(), size - removed);
+        assert!(ko.symstrtab().unwrap().position("unused").is_none());
+
+        let names: Vec<&str> = ko.named_symbols().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["_start"]);
+        assert!(ko.func_section_by_name("_start").is_some());
+    }
+
+    #[test]
+    fn symbol_transformations() {
+        let mut builder = KOFileBuilder::new();
+        builder.set_source_file("lib.kasm");
+        let start = builder.define_function("_start", SymBind::Global).unwrap();
+        builder
+            .define_function("internal", SymBind::Global)
+            .unwrap();
+        builder.define_function("helper", SymBind::Local).unwrap();
+        let greet = builder.reference_extern("greet");
+
+        let call = builder.emitter(start).call("", "");
+        builder.add_relocation(start, call, OperandIndex::One, greet);
+
+        let mut ko = builder.finish().unwrap().get();
+
+        ko.rename_symbol("greet", "hello").unwrap();
+        ko.localize_symbol("internal").unwrap();
+        ko.globalize_symbol("helper").unwrap();
+
+        assert!(ko.rename_symbol("missing", "other").is_err());
+        assert!(ko.rename_symbol("hello", "_start").is_err());
+        assert!(ko.localize_symbol("hello").is_err());
+
+        assert_eq!(
+            ko.symbol_by_name("internal").unwrap().sym_bind,
+            SymBind::Local
+        );
+        assert_eq!(
+            ko.symbol_by_name("helper").unwrap().sym_bind,
+            SymBind::Global
+        );
+
+        assert!(ko.strip_file_symbol());
+        assert!(!ko.strip_file_symbol());
+        assert_eq!(ko.strip_local_symbols(), 1);
+
+        let names: Vec<&str> = ko.named_symbols().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["_start", "helper", "hello"]);
+
+        let entry = ko.reld_sections().next().unwrap().entries().next().unwrap();
+        let symbol = ko.symtab().unwrap().get(entry.symbol_index).unwrap();
+
+        assert_eq!(ko.symbol_name(symbol).unwrap(), "hello");
+    }
+}
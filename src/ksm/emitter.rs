@@ -0,0 +1,66 @@
+//! A module containing the CodeEmitter type, which appends instructions to a code section while
+//! automatically adding their operands to an argument section.
+//!
+//! # Example:
+//!
+//! ```
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType};
+//! use kerbalobjects::ksm::CodeEmitter;
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//!
+//! // Corresponds to the KerbalScript code:
+//! // PRINT("Hello, world!").
+//!
+//! emitter.push(KOSValue::String("@0001".into()));
+//! emitter.bscp(1, 0);
+//! emitter.argb();
+//! emitter.push_arg_marker();
+//! emitter.push(KOSValue::StringValue("Hello, world!".into()));
+//! emitter.call("", "print()");
+//! emitter.pop();
+//! emitter.escp(1);
+//!
+//! assert_eq!(main_code.instructions().count(), 8);
+//! ```
+//!
+use crate::ksm::sections::{ArgIndex, ArgumentSection, CodeSection};
+use crate::ksm::Instr;
+use crate::{EmitError, KOSValue, Opcode};
+
+/// Appends instructions to a code section, adding each operand to an argument section
+/// if it is not already present.
+///
+/// Each method returns the index of the emitted instruction within the code section.
+#[derive(Debug)]
+pub struct CodeEmitter<'a> {
+    code_section: &'a mut CodeSection,
+    arg_section: &'a mut ArgumentSection,
+}
+
+impl<'a> CodeEmitter<'a> {
+    /// Creates a new emitter that appends instructions to the provided code section,
+    /// and stores operands in the provided argument section
+    pub fn new(code_section: &'a mut CodeSection, arg_section: &'a mut ArgumentSection) -> Self {
+        Self {
+            code_section,
+            arg_section,
+        }
+    }
+
+    fn intern(&mut self, value: KOSValue) -> ArgIndex {
+        self.arg_section.add_checked(value)
+    }
+
+    fn add_instr(&mut self, instr: Instr) -> usize {
+        let index = self.code_section.instructions().len();
+        self.code_section.add(instr);
+        index
+    }
+
+    gen_emitter_methods!(usize);
+}
@@ -74,6 +74,9 @@ use crate::{ksm::sections::CodeSection, FromBytes, ToBytes};
 pub mod builder;
 pub use builder::*;
 
+pub mod emitter;
+pub use emitter::*;
+
 pub mod errors;
 pub mod sections;
 
@@ -150,12 +153,12 @@ impl KSMFile {
     }
 
     /// Returns an iterator over all of the code sections in this file
-    pub fn code_sections(&self) -> Iter<CodeSection> {
+    pub fn code_sections(&self) -> Iter<'_, CodeSection> {
         self.code_sections.iter()
     }
 
     /// Returns a mutable iterator over all of the code sections in this file
-    pub fn code_sections_mut(&mut self) -> IterMut<CodeSection> {
+    pub fn code_sections_mut(&mut self) -> IterMut<'_, CodeSection> {
         self.code_sections.iter_mut()
     }
 
@@ -164,6 +167,33 @@ impl KSMFile {
         self.code_sections.push(code_section);
     }
 
+    /// Returns the byte offset of every instruction in each code section, in the same order as
+    /// [code_sections](Self::code_sections).
+    ///
+    /// Offsets are measured from the first byte after the argument section, which is how the
+    /// debug section refers to instructions.
+    pub fn instruction_offsets(&self) -> Vec<Vec<usize>> {
+        let index_bytes = self.arg_section.num_index_bytes();
+        let mut offset = 0;
+
+        self.code_sections
+            .iter()
+            .map(|code_section| {
+                // The %F/I/M that goes before the section
+                offset += 2;
+
+                code_section
+                    .instructions()
+                    .map(|instr| {
+                        let instr_offset = offset;
+                        offset += instr.size_bytes(index_bytes);
+                        instr_offset
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
     /// Parses an entire KSMFile from a byte buffer
     pub fn parse(source: &mut BufferIterator) -> Result<Self, KSMParseError> {
         let source_len = source.len();
@@ -166,8 +166,9 @@ impl ArgumentSection {
 
     /// Returns the NumArgIndexBytes that this argument section currently requires.
     ///
-    /// This represents the current size range of this argument section, because this is the number
-    /// of bytes that are required to reference an item within the argument section.
+    /// This is the number of bytes that are required to reference the last value in this
+    /// argument section, which has the largest index. This means that the size of the last
+    /// value doesn't matter.
     pub fn num_index_bytes(&self) -> IntSize {
         self.num_index_bytes
     }
@@ -213,8 +214,8 @@ impl ArgumentSection {
         self.value_index_map.insert(arg_index, index);
         self.size_bytes += size;
 
-        // This may be a little slow, but it saves us from having any state that a user has to deal with
-        self.recalculate_index_bytes();
+        // Values are only ever added at the end, so this is always the largest index
+        self.recalculate_index_bytes(arg_index);
 
         arg_index
     }
@@ -231,18 +232,31 @@ impl ArgumentSection {
     }
 
     /// Returns an iterator over all of the KOSValues that are stored in this section.
-    pub fn arguments(&self) -> Iter<KOSValue> {
+    pub fn arguments(&self) -> Iter<'_, KOSValue> {
         self.arguments.iter()
     }
 
+    /// Returns an iterator over all of the KOSValues that are stored in this section, along with
+    /// the ArgIndex of each one.
+    pub fn indexed_arguments(&self) -> impl Iterator<Item = (ArgIndex, &KOSValue)> {
+        self.arguments
+            .iter()
+            .scan(Self::BEGIN_SIZE, |offset, value| {
+                let index = ArgIndex(*offset);
+                *offset += value.size_bytes();
+                Some((index, value))
+            })
+    }
+
     /// Returns the size in bytes that this section would take up in total in the final binary file.
     pub fn size_bytes(&self) -> usize {
         self.size_bytes
     }
 
-    // Recalculates the number of bytes required to reference any value within this section.
-    fn recalculate_index_bytes(&mut self) {
-        self.num_index_bytes = fewest_bytes_to_hold(self.size_bytes as u32);
+    // Recalculates the number of bytes required to reference any value within this section,
+    // which only depends on the largest index.
+    fn recalculate_index_bytes(&mut self, largest_index: ArgIndex) {
+        self.num_index_bytes = fewest_bytes_to_hold(largest_index.0 as u32);
     }
 
     /// Attempts to parse an argument section from the current buffer iterator.
@@ -289,6 +303,10 @@ impl ArgumentSection {
             }
         }
 
+        // Other tools may have used more bytes than needed, and the code sections will be read
+        // using what the file says
+        arg_section.num_index_bytes = num_index_bytes;
+
         Ok(arg_section)
     }
 
@@ -100,7 +100,7 @@ impl DebugEntry {
     }
 
     /// Returns an iterator over each debug range in this entry
-    pub fn ranges(&self) -> Iter<DebugRange> {
+    pub fn ranges(&self) -> Iter<'_, DebugRange> {
         self.ranges.iter()
     }
 
@@ -238,10 +238,23 @@ impl DebugSection {
     }
 
     /// Returns an iterator over all of the debug entries contained within this section
-    pub fn debug_entries(&self) -> Iter<DebugEntry> {
+    pub fn debug_entries(&self) -> Iter<'_, DebugEntry> {
         self.debug_entries.iter()
     }
 
+    /// Returns the line number of the first debug entry with a range containing the provided
+    /// byte offset into the code sections, or None if no entry covers it
+    pub fn line_for_offset(&self, offset: usize) -> Option<isize> {
+        self.debug_entries
+            .iter()
+            .find(|entry| {
+                entry
+                    .ranges()
+                    .any(|range| range.start <= offset && offset <= range.end)
+            })
+            .map(|entry| entry.line_number)
+    }
+
     /// Specified in the debug section's header, the size, in bytes, of a debug range
     /// in all debug entries. This corresponds to the argument section's NumArgIndexBytes.
     /// This is needed to know how many bytes are required to represent an index into this KSM file's
@@ -154,9 +154,24 @@
 mod common;
 pub use common::*;
 
+mod operations;
+
+#[allow(unused_macros)]
+#[macro_use]
+mod emitter;
+
 pub mod errors;
 pub use errors::*;
 
+pub mod analysis;
+pub mod optimize;
+pub mod vm;
+
+#[cfg(all(feature = "ko", feature = "ksm"))]
+pub mod delink;
+#[cfg(all(feature = "ko", feature = "ksm"))]
+pub mod link;
+
 #[cfg(feature = "ko")]
 pub mod ko;
 #[cfg(feature = "ksm")]
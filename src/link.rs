@@ -0,0 +1,963 @@
+//! # Linking
+//!
+//! A module for linking KO files together into a KSM file that kOS can run.
+//!
+//! Every function section becomes code in the KSM file. `_start` becomes the main code, `_init`
+//! becomes the initialization code if it exists, and every other function is placed in the
+//! function code, after an Lbrt instruction that labels it with its name so that it can be called.
+//! Relocated operands are replaced with the name of the function they refer to, or with the value
+//! of the data symbol they refer to, looking up external symbols in the other files. Relocations
+//! of other kinds become branch offsets, delegate labels, or values plus an addend, as described
+//! by [ReldKind]. Local functions whose names are used by more than one file are labelled
+//! `name.N`, where `N` is the index of the file that defines them.
+//!
+//! [Object](SymType::Object) symbols are global variables. Code that pushes the initial value of
+//! each variable and stores it with Stog is placed at the start of the initialization code,
+//! before `_init`, and relocated operands that refer to a variable are replaced with its
+//! identifier, `$name`. Local variables whose names are used by more than one file are named
+//! `name.N` in the same way as functions.
+//!
+//! Global symbols override [Weak](SymBind::Weak) symbols with the same name, so that libraries can
+//! provide defaults that other files replace. Weak references to symbols that no file defines
+//! become null instead of being an error.
+//!
+//! With [LinkOptions::gc_sections], functions that can't be reached from `_start` or `_init` are
+//! left out, similar to `--gc-sections`. A function is reachable if it is called, if a delegate
+//! to it is pushed, or if any relocation of a reachable function refers to it. Data values
+//! that no remaining function uses are always left out, and the [GcReport] lists everything
+//! that was removed.
+//!
+//! Every link also produces a [LinkMap], which lists where each function was placed and how big
+//! it is, which data values were merged into each argument, and which functions were discarded.
+//! Its byte offsets can be used to find the function that a kOS runtime error happened in.
+//!
+//! ```
+//! use kerbalobjects::ko::symbols::{OperandIndex, SymBind};
+//! use kerbalobjects::ko::KOFileBuilder;
+//! use kerbalobjects::link::{link, LinkOptions};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut builder = KOFileBuilder::new();
+//! builder.set_source_file("main.kasm");
+//!
+//! let start = builder.define_function("_start", SymBind::Global).unwrap();
+//! let used = builder.define_function("used", SymBind::Local).unwrap();
+//! let unused = builder.define_function("unused", SymBind::Local).unwrap();
+//!
+//! let mut emitter = builder.emitter(start);
+//! emitter.push_arg_marker();
+//! let call = emitter.call("", "");
+//! emitter.eop();
+//! builder.add_relocation(start, call, OperandIndex::One, used.symbol_index());
+//!
+//! builder.emitter(used).ret(0);
+//!
+//! let mut emitter = builder.emitter(unused);
+//! emitter.push(KOSValue::StringValue("never pushed".into()));
+//! emitter.ret(0);
+//!
+//! let ko = builder.finish().unwrap().get();
+//!
+//! let options = LinkOptions { gc_sections: true };
+//! let linked = link(&[ko], &options).expect("Could not link");
+//!
+//! let removed: Vec<&str> = linked
+//!     .gc_report()
+//!     .removed_functions()
+//!     .iter()
+//!     .map(|function| function.name())
+//!     .collect();
+//!
+//! assert_eq!(removed, vec!["unused"]);
+//! assert!(linked.gc_report().removed_data() > 0);
+//!
+//! let mapped: Vec<&str> = linked
+//!     .map()
+//!     .functions()
+//!     .iter()
+//!     .map(|function| function.name())
+//!     .collect();
+//!
+//! assert_eq!(mapped, vec!["used", "_start"]);
+//! ```
+//!
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::errors::LinkError;
+use crate::ko::sections::{DataIdx, FuncSection};
+use crate::ko::symbols::{KOSymbol, ReldEntry, ReldKind, SymBind, SymType};
+use crate::ko::{KOFile, SectionIdx};
+use crate::ksm::sections::{ArgIndex, ArgumentSection, CodeSection, CodeType, DebugSection};
+use crate::ksm::KSMFile;
+use crate::{KOSValue, Opcode};
+
+/// Options that change how files are linked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkOptions {
+    /// Leave out functions that can't be reached from `_start` or `_init`
+    pub gc_sections: bool,
+}
+
+/// A function that was left out of the linked file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedFunction {
+    name: String,
+    source_file: Option<String>,
+    size: u32,
+}
+
+impl RemovedFunction {
+    /// The name of the function's symbol
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the file symbol of the KO file that defined the function, if it had one
+    pub fn source_file(&self) -> Option<&str> {
+        self.source_file.as_deref()
+    }
+
+    /// The size of the function's section in bytes
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+/// What was left out of a linked file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    removed_functions: Vec<RemovedFunction>,
+    removed_data: usize,
+}
+
+impl GcReport {
+    /// The functions that were left out because they couldn't be reached, in the order of the
+    /// files and sections that defined them
+    pub fn removed_functions(&self) -> &[RemovedFunction] {
+        &self.removed_functions
+    }
+
+    /// The number of values in all of the data sections of the files that no remaining function
+    /// uses
+    pub fn removed_data(&self) -> usize {
+        self.removed_data
+    }
+}
+
+/// Where a function ended up in a linked file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedFunction {
+    name: String,
+    source_file: Option<String>,
+    code_type: CodeType,
+    offset: usize,
+    size: usize,
+}
+
+impl MappedFunction {
+    /// The name of the function's symbol
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the file symbol of the KO file that defined the function, if it had one
+    pub fn source_file(&self) -> Option<&str> {
+        self.source_file.as_deref()
+    }
+
+    /// The type of the code section the function was placed in
+    pub fn code_type(&self) -> CodeType {
+        self.code_type
+    }
+
+    /// The byte offset of the function's first instruction, measured the same way as
+    /// [KSMFile::instruction_offsets]. For functions in the function code, this is the offset of
+    /// the Lbrt instruction that labels it.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The size of the function's instructions in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns true if the provided byte offset is one of this function's instructions
+    pub fn contains(&self, offset: usize) -> bool {
+        (self.offset..self.offset + self.size).contains(&offset)
+    }
+}
+
+/// A value from the data section of one of the files that were linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSource {
+    file_index: usize,
+    source_file: Option<String>,
+    section_index: SectionIdx,
+    data_index: DataIdx,
+}
+
+impl DataSource {
+    /// The index of the file in the slice of files that were linked
+    pub fn file_index(&self) -> usize {
+        self.file_index
+    }
+
+    /// The name of the file symbol of the file, if it had one
+    pub fn source_file(&self) -> Option<&str> {
+        self.source_file.as_deref()
+    }
+
+    /// The index of the header of the data section that the value is in
+    pub fn section_index(&self) -> SectionIdx {
+        self.section_index
+    }
+
+    /// The index of the value in its data section
+    pub fn data_index(&self) -> DataIdx {
+        self.data_index
+    }
+}
+
+/// A value in the argument section of a linked file
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedArgument {
+    index: ArgIndex,
+    value: KOSValue,
+    sources: Vec<DataSource>,
+}
+
+impl MappedArgument {
+    /// The index of the value in the argument section
+    pub fn index(&self) -> ArgIndex {
+        self.index
+    }
+
+    /// The value
+    pub fn value(&self) -> &KOSValue {
+        &self.value
+    }
+
+    /// The data section values that were merged into this value. This is empty for values that
+    /// were created by the linker, like function labels.
+    pub fn sources(&self) -> &[DataSource] {
+        &self.sources
+    }
+}
+
+/// A description of where everything ended up in a linked file, like a linker map file.
+///
+/// This implements Display, which writes the map in a human readable format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkMap {
+    functions: Vec<MappedFunction>,
+    arguments: Vec<MappedArgument>,
+    discarded: Vec<RemovedFunction>,
+}
+
+impl LinkMap {
+    /// Every function in the linked file, in the order that they were placed
+    pub fn functions(&self) -> &[MappedFunction] {
+        &self.functions
+    }
+
+    /// Every value in the argument section of the linked file, in order
+    pub fn arguments(&self) -> &[MappedArgument] {
+        &self.arguments
+    }
+
+    /// The function sections that were left out of the linked file
+    pub fn discarded(&self) -> &[RemovedFunction] {
+        &self.discarded
+    }
+
+    /// Finds the function that contains the instruction at the provided byte offset, such as an
+    /// offset from a kOS runtime error
+    pub fn function_at(&self, offset: usize) -> Option<&MappedFunction> {
+        self.functions
+            .iter()
+            .find(|function| function.contains(offset))
+    }
+}
+
+impl std::fmt::Display for LinkMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = |source_file: Option<&str>| source_file.unwrap_or("<unknown>").to_string();
+
+        writeln!(f, "Functions:")?;
+
+        for function in &self.functions {
+            writeln!(
+                f,
+                "  {:#06x} {:>6} {:<14} {} ({})",
+                function.offset,
+                function.size,
+                format!("{:?}", function.code_type),
+                function.name,
+                source(function.source_file())
+            )?;
+        }
+
+        writeln!(f, "Arguments:")?;
+
+        for argument in &self.arguments {
+            write!(
+                f,
+                "  {:#06x} {}",
+                usize::from(argument.index),
+                argument.value
+            )?;
+
+            for (i, data_source) in argument.sources.iter().enumerate() {
+                let separator = if i == 0 { " <-" } else { "," };
+
+                write!(
+                    f,
+                    "{} {}[{}]",
+                    separator,
+                    source(data_source.source_file()),
+                    usize::from(data_source.data_index)
+                )?;
+            }
+
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Discarded:")?;
+
+        for function in &self.discarded {
+            writeln!(
+                f,
+                "  {:>6} {} ({})",
+                function.size,
+                function.name,
+                source(function.source_file())
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The result of linking KO files
+#[derive(Debug)]
+pub struct Linked {
+    ksm: KSMFile,
+    gc_report: GcReport,
+    map: LinkMap,
+}
+
+impl Linked {
+    /// The KSM file that was created
+    pub fn ksm_file(&self) -> &KSMFile {
+        &self.ksm
+    }
+
+    /// Consumes this result, returning the KSM file that was created
+    pub fn into_ksm_file(self) -> KSMFile {
+        self.ksm
+    }
+
+    /// What was left out of the KSM file
+    pub fn gc_report(&self) -> &GcReport {
+        &self.gc_report
+    }
+
+    /// Where everything ended up in the KSM file
+    pub fn map(&self) -> &LinkMap {
+        &self.map
+    }
+}
+
+/// An operand after its symbol has been resolved
+#[derive(Debug, Clone)]
+enum LinkOperand {
+    /// A value, and the file, data section, and index in the data section it came from
+    Value(KOSValue, Option<(usize, SectionIdx, DataIdx)>),
+    /// A reference to a function, by its index in the list of functions
+    Function(usize),
+    /// A branch to an instruction of a function, by the function's index and the index of the
+    /// instruction within it
+    Branch(usize, i32),
+}
+
+/// A function section of one of the files being linked
+#[derive(Debug)]
+struct Function<'a> {
+    file_index: usize,
+    name: String,
+    label: String,
+    section: &'a FuncSection,
+    instrs: Vec<(Opcode, Vec<LinkOperand>)>,
+}
+
+/// Adds a value to the argument section, keeping track of the data section value it came from
+fn add_argument(
+    arg_section: &mut ArgumentSection,
+    arg_sources: &mut HashMap<ArgIndex, Vec<(usize, SectionIdx, DataIdx)>>,
+    used_data: &mut HashSet<(usize, SectionIdx, DataIdx)>,
+    value: KOSValue,
+    origin: Option<(usize, SectionIdx, DataIdx)>,
+) -> ArgIndex {
+    let arg_index = arg_section.add_checked(value);
+
+    if let Some(origin) = origin {
+        used_data.insert(origin);
+
+        let sources = arg_sources.entry(arg_index).or_default();
+
+        if !sources.contains(&origin) {
+            sources.push(origin);
+        }
+    }
+
+    arg_index
+}
+
+/// Applies a relocation of an operand of an instruction to what its symbol resolved to, or
+/// returns None if the kind of relocation can't be applied to it
+fn relocate(opcode: Opcode, entry: &ReldEntry, operand: LinkOperand) -> Option<LinkOperand> {
+    match (entry.kind, operand) {
+        (ReldKind::Symbol, operand) => Some(operand),
+        // Only Pdrl refers to its function by label, Phdl needs an instruction index that isn't
+        // known until the program is loaded
+        (ReldKind::Delegate, operand @ LinkOperand::Function(_)) if opcode == Opcode::Pdrl => {
+            Some(operand)
+        }
+        (ReldKind::Branch, LinkOperand::Function(target)) => {
+            Some(LinkOperand::Branch(target, entry.addend))
+        }
+        (ReldKind::SymbolAddend, LinkOperand::Value(value, origin)) if value.is_number() => value
+            .binary_op(Opcode::Add, &KOSValue::ScalarInt(entry.addend))
+            .ok()
+            .map(|value| LinkOperand::Value(value, origin)),
+        _ => None,
+    }
+}
+
+/// Returns the relative offset of a branch from the instruction at `position` to the instruction
+/// `addend` instructions after `target`
+fn branch_offset(position: usize, target: usize, addend: i32) -> i32 {
+    (target as i64 + addend as i64 - position as i64) as i32
+}
+
+/// Finds every symbol that other files can link against, by name, along with the index of the
+/// file that defines it. Global symbols override Weak ones, and the first of several Weak symbols
+/// with the same name is used.
+pub(crate) fn linkable_symbols(
+    files: &[KOFile],
+) -> Result<HashMap<&str, (usize, &KOSymbol)>, LinkError> {
+    let mut globals: HashMap<&str, (usize, &KOSymbol)> = HashMap::new();
+
+    for (file_index, file) in files.iter().enumerate() {
+        for (name, symbol) in file.named_symbols() {
+            if symbol.is_undefined()
+                || !matches!(
+                    symbol.sym_type,
+                    SymType::NoType | SymType::Object | SymType::Func
+                )
+            {
+                continue;
+            }
+
+            match symbol.sym_bind {
+                SymBind::Global => match globals.get(name) {
+                    Some((_, existing)) if existing.sym_bind == SymBind::Global => {
+                        return Err(LinkError::DuplicateSymbolError(name.to_string()));
+                    }
+                    _ => {
+                        globals.insert(name, (file_index, symbol));
+                    }
+                },
+                SymBind::Weak => {
+                    globals.entry(name).or_insert((file_index, symbol));
+                }
+                SymBind::Local | SymBind::Extern => {}
+            }
+        }
+    }
+
+    Ok(globals)
+}
+
+/// Finds every variable that is initialized, as the file that defines it, its name, its symbol,
+/// and the name it is given in the linked file. Weak variables that were overridden aren't, and
+/// Local variables whose names are used by more than one file are told apart by their file.
+fn linked_variables<'a>(
+    files: &'a [KOFile],
+    globals: &HashMap<&str, (usize, &KOSymbol)>,
+) -> Vec<(usize, &'a str, &'a KOSymbol, String)> {
+    let mut variables: Vec<(usize, &str, &KOSymbol, String)> = Vec::new();
+
+    for (file_index, file) in files.iter().enumerate() {
+        for (name, symbol) in file.named_symbols() {
+            if symbol.sym_type != SymType::Object || symbol.is_undefined() {
+                continue;
+            }
+
+            let winner = globals
+                .get(name)
+                .is_some_and(|&(_, winner)| std::ptr::eq(winner, symbol));
+
+            if symbol.sym_bind == SymBind::Local || winner {
+                variables.push((file_index, name, symbol, name.to_string()));
+            }
+        }
+    }
+
+    for index in 0..variables.len() {
+        let (file_index, name, symbol, _) = variables[index];
+        let shared = variables.iter().filter(|v| v.1 == name).count() > 1;
+
+        if shared && symbol.sym_bind == SymBind::Local {
+            variables[index].3 = format!("{}.{}", name, file_index);
+        }
+    }
+
+    variables
+}
+
+/// Returns the name of the file symbol of a KO file, if it has one
+pub(crate) fn source_file(file: &KOFile) -> Option<String> {
+    file.named_symbols()
+        .find(|(_, symbol)| symbol.sym_type == SymType::File)
+        .map(|(name, _)| name.to_string())
+}
+
+/// Links KO files together into a KSM file. See the [module documentation](self) for how the
+/// code is laid out.
+pub fn link(files: &[KOFile], options: &LinkOptions) -> Result<Linked, LinkError> {
+    let globals = linkable_symbols(files)?;
+
+    let mut functions: Vec<Function> = Vec::new();
+    let mut function_indexes: HashMap<(usize, SectionIdx), usize> = HashMap::new();
+
+    for (file_index, file) in files.iter().enumerate() {
+        for section in file.func_sections() {
+            let section_index = section.section_index();
+
+            let name = file
+                .named_symbols()
+                .find(|(_, symbol)| {
+                    symbol.sym_type == SymType::Func
+                        && !symbol.is_undefined()
+                        && symbol.sh_idx == section_index
+                })
+                .map(|(name, _)| name)
+                .or_else(|| {
+                    file.get_section_name_by_index(section_index)
+                        .map(String::as_str)
+                })
+                .unwrap_or_default()
+                .to_string();
+
+            function_indexes.insert((file_index, section_index), functions.len());
+            functions.push(Function {
+                file_index,
+                label: name.clone(),
+                name,
+                section,
+                instrs: Vec::new(),
+            });
+        }
+    }
+
+    // Functions that share a name with another are told apart by their file, unless they are
+    // the global one
+    for index in 0..functions.len() {
+        let function = &functions[index];
+        let shared = functions.iter().filter(|f| f.name == function.name).count() > 1;
+        let global = globals
+            .get(function.name.as_str())
+            .is_some_and(|&(file_index, _)| file_index == function.file_index);
+
+        if shared && !global {
+            functions[index].label = format!("{}.{}", function.name, function.file_index);
+        }
+    }
+
+    // The value in a data section that a symbol refers to
+    let data_value = |file_index: usize, symbol: &KOSymbol| {
+        files[file_index]
+            .data_section_at(symbol.sh_idx)
+            .ok()
+            .and_then(|data_section| data_section.get(symbol.value_idx))
+    };
+
+    let variables = linked_variables(files, &globals);
+
+    let variable_names: HashMap<(usize, &str), String> = variables
+        .iter()
+        .map(|(file_index, name, _, label)| ((*file_index, *name), format!("${}", label)))
+        .collect();
+
+    // What a symbol refers to, once external symbols are looked up
+    let resolve = |file_index: usize, symbol: &KOSymbol| -> Result<LinkOperand, LinkError> {
+        let file = &files[file_index];
+        let name = file.symbol_name(symbol).cloned().unwrap_or_default();
+
+        let (file_index, symbol) = match symbol.sym_bind {
+            SymBind::Extern | SymBind::Weak => match globals.get(name.as_str()) {
+                Some(&definition) => definition,
+                // Weak references to symbols that no file defines become null
+                None if symbol.sym_bind == SymBind::Weak => {
+                    return Ok(LinkOperand::Value(KOSValue::Null, None));
+                }
+                None => return Err(LinkError::UndefinedSymbolError(name)),
+            },
+            SymBind::Local | SymBind::Global => (file_index, symbol),
+        };
+
+        if symbol.sym_type == SymType::Func {
+            return function_indexes
+                .get(&(file_index, symbol.sh_idx))
+                .map(|&index| LinkOperand::Function(index))
+                .ok_or(LinkError::UndefinedSymbolError(name));
+        }
+
+        if symbol.sym_type == SymType::Object {
+            return variable_names
+                .get(&(file_index, name.as_str()))
+                .map(|variable| LinkOperand::Value(KOSValue::String(variable.clone()), None))
+                .ok_or(LinkError::UndefinedSymbolError(name));
+        }
+
+        data_value(file_index, symbol)
+            .map(|value| {
+                LinkOperand::Value(
+                    value.clone(),
+                    Some((file_index, symbol.sh_idx, symbol.value_idx)),
+                )
+            })
+            .ok_or(LinkError::UndefinedSymbolError(name))
+    };
+
+    for function in functions.iter_mut() {
+        let file = &files[function.file_index];
+        let data_section = file.data_section_by_name(".data");
+        let section_index = function.section.section_index();
+
+        let relocations: HashMap<(usize, usize), (&KOSymbol, &ReldEntry)> = file
+            .reld_sections()
+            .flat_map(|reld_section| reld_section.entries())
+            .filter(|entry| entry.section_index == section_index)
+            .filter_map(|entry| {
+                let symbol = file.symtab()?.get(entry.symbol_index)?;
+                let operand: u8 = entry.operand_index.into();
+
+                Some((
+                    (entry.instr_index.into(), operand as usize - 1),
+                    (symbol, entry),
+                ))
+            })
+            .collect();
+
+        for (instr_index, instr) in function.section.instructions().enumerate() {
+            let (opcode, raw_operands) = match *instr {
+                crate::ko::Instr::ZeroOp(opcode) => (opcode, vec![]),
+                crate::ko::Instr::OneOp(opcode, op1) => (opcode, vec![op1]),
+                crate::ko::Instr::TwoOp(opcode, op1, op2) => (opcode, vec![op1, op2]),
+            };
+
+            let mut operands = Vec::with_capacity(raw_operands.len());
+
+            for (operand_index, raw) in raw_operands.into_iter().enumerate() {
+                let invalid =
+                    || LinkError::InvalidRelocationError(function.name.clone(), instr_index);
+
+                let operand = match relocations.get(&(instr_index, operand_index)) {
+                    Some((symbol, entry)) => {
+                        let operand = resolve(function.file_index, symbol)?;
+
+                        relocate(opcode, entry, operand).ok_or_else(invalid)?
+                    }
+                    None => data_section
+                        .and_then(|data_section| {
+                            let origin = (function.file_index, data_section.section_index(), raw);
+
+                            Some(LinkOperand::Value(
+                                data_section.get(raw)?.clone(),
+                                Some(origin),
+                            ))
+                        })
+                        .ok_or_else(|| {
+                            LinkError::InvalidOperandError(function.name.clone(), instr_index)
+                        })?,
+                };
+
+                operands.push(operand);
+            }
+
+            function.instrs.push((opcode, operands));
+        }
+    }
+
+    let entry = |name: &str| match globals.get(name) {
+        Some(&(file_index, symbol)) if symbol.sym_type == SymType::Func => {
+            Ok(function_indexes.get(&(file_index, symbol.sh_idx)).copied())
+        }
+        // Without a Global or Weak symbol, a Local function can only be used if no other file
+        // has one with the same name
+        _ => {
+            let mut candidates = functions
+                .iter()
+                .enumerate()
+                .filter(|(_, function)| function.name == name)
+                .map(|(index, _)| index);
+
+            match (candidates.next(), candidates.next()) {
+                (Some(_), Some(_)) => Err(LinkError::DuplicateSymbolError(name.to_string())),
+                (index, _) => Ok(index),
+            }
+        }
+    };
+
+    let start = entry("_start")?.ok_or(LinkError::MissingEntryPointError)?;
+    let init = entry("_init")?;
+
+    let labels: HashMap<&str, usize> = functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| (function.label.as_str(), index))
+        .collect();
+
+    // Finds every function that can be reached from the entry points
+    let mut reachable = vec![!options.gc_sections; functions.len()];
+    let mut worklist: Vec<usize> = std::iter::once(start).chain(init).collect();
+
+    while let Some(index) = worklist.pop() {
+        if std::mem::replace(&mut reachable[index], true) {
+            continue;
+        }
+
+        for (opcode, operands) in functions[index].instrs.iter() {
+            for operand in operands {
+                let callee = match operand {
+                    LinkOperand::Function(callee) | LinkOperand::Branch(callee, _) => Some(*callee),
+                    // Calls and delegates can also refer to functions by their label
+                    LinkOperand::Value(KOSValue::String(label), _)
+                    | LinkOperand::Value(KOSValue::StringValue(label), _)
+                        if matches!(opcode, Opcode::Call | Opcode::Pdrl | Opcode::Prl) =>
+                    {
+                        labels.get(label.as_str()).copied()
+                    }
+                    _ => None,
+                };
+
+                if let Some(callee) = callee.filter(|&callee| !reachable[callee]) {
+                    worklist.push(callee);
+                }
+            }
+        }
+    }
+
+    let mut gc_report = GcReport::default();
+
+    for (index, function) in functions.iter().enumerate() {
+        if !reachable[index] {
+            gc_report.removed_functions.push(RemovedFunction {
+                name: function.name.clone(),
+                source_file: source_file(&files[function.file_index]),
+                size: function.section.size(),
+            });
+        }
+    }
+
+    let mut arg_section = ArgumentSection::new();
+    let mut function_code = CodeSection::new(CodeType::Function);
+    let mut init_code = CodeSection::new(CodeType::Initialization);
+    let mut main_code = CodeSection::new(CodeType::Main);
+    let mut used_data: HashSet<(usize, SectionIdx, DataIdx)> = HashSet::new();
+    let mut arg_sources: HashMap<ArgIndex, Vec<(usize, SectionIdx, DataIdx)>> = HashMap::new();
+    // The function, code section, and range of instructions of each placed function
+    let mut placed: Vec<(usize, usize, Range<usize>)> = Vec::new();
+
+    let order: Vec<usize> = (0..functions.len())
+        .filter(|&index| reachable[index] && index != start && Some(index) != init)
+        .chain(init)
+        .chain(std::iter::once(start))
+        .collect();
+
+    let slot_of = |index: usize| {
+        if index == start {
+            2
+        } else if Some(index) == init {
+            1
+        } else {
+            0
+        }
+    };
+
+    // The code section of each function, and the index of its first instruction after its label,
+    // which branches are relative to
+    let mut positions: HashMap<usize, (usize, usize)> = HashMap::new();
+    let mut lengths = [0, variables.len() * 2, 0];
+
+    for &index in order.iter() {
+        let slot = slot_of(index);
+
+        if slot == 0 {
+            lengths[slot] += 1;
+        }
+
+        positions.insert(index, (slot, lengths[slot]));
+        lengths[slot] += functions[index].instrs.len();
+    }
+
+    for &(file_index, name, symbol, ref label) in variables.iter() {
+        let value = data_value(file_index, symbol)
+            .cloned()
+            .ok_or_else(|| LinkError::UndefinedSymbolError(name.to_string()))?;
+
+        let value = add_argument(
+            &mut arg_section,
+            &mut arg_sources,
+            &mut used_data,
+            value,
+            Some((file_index, symbol.sh_idx, symbol.value_idx)),
+        );
+        let variable = arg_section.add_checked(KOSValue::String(format!("${}", label)));
+
+        init_code.add(crate::ksm::Instr::OneOp(Opcode::Push, value));
+        init_code.add(crate::ksm::Instr::OneOp(Opcode::Stog, variable));
+    }
+
+    for index in order {
+        let function = &functions[index];
+        let (slot, position) = positions[&index];
+
+        let code_section = match slot {
+            0 => &mut function_code,
+            1 => &mut init_code,
+            _ => &mut main_code,
+        };
+
+        let first = code_section.instructions().len();
+
+        if slot == 0 {
+            let label = arg_section.add_checked(KOSValue::String(function.label.clone()));
+            code_section.add(crate::ksm::Instr::OneOp(Opcode::Lbrt, label));
+        }
+
+        for (instr_index, (opcode, operands)) in function.instrs.iter().enumerate() {
+            let mut arg_indexes = Vec::with_capacity(operands.len());
+
+            for operand in operands {
+                let (value, origin) = match operand {
+                    LinkOperand::Value(value, origin) => (value.clone(), *origin),
+                    LinkOperand::Function(callee) => {
+                        (KOSValue::String(functions[*callee].label.clone()), None)
+                    }
+                    LinkOperand::Branch(target, addend) => match positions.get(target) {
+                        Some(&(target_slot, target_position)) if target_slot == slot => {
+                            let offset =
+                                branch_offset(position + instr_index, target_position, *addend);
+
+                            (KOSValue::Int32(offset), None)
+                        }
+                        _ => {
+                            return Err(LinkError::InvalidRelocationError(
+                                function.name.clone(),
+                                instr_index,
+                            ))
+                        }
+                    },
+                };
+
+                arg_indexes.push(add_argument(
+                    &mut arg_section,
+                    &mut arg_sources,
+                    &mut used_data,
+                    value,
+                    origin,
+                ));
+            }
+
+            code_section.add(match arg_indexes[..] {
+                [] => crate::ksm::Instr::ZeroOp(*opcode),
+                [op1] => crate::ksm::Instr::OneOp(*opcode, op1),
+                [op1, op2, ..] => crate::ksm::Instr::TwoOp(*opcode, op1, op2),
+            });
+        }
+
+        placed.push((index, slot, first..code_section.instructions().len()));
+    }
+
+    let total_data: usize = files
+        .iter()
+        .flat_map(|file| file.data_sections())
+        .map(|data_section| data_section.data().count())
+        .sum();
+
+    gc_report.removed_data = total_data.saturating_sub(used_data.len());
+
+    let ksm = KSMFile::new_from_parts(
+        arg_section,
+        vec![function_code, init_code, main_code],
+        DebugSection::new_empty(),
+    );
+
+    let source_files: Vec<Option<String>> = files.iter().map(source_file).collect();
+    let index_bytes = ksm.arg_section.num_index_bytes();
+    let mut offset = 0;
+
+    // The offset of every instruction in each code section like KSMFile::instruction_offsets,
+    // followed by the offset of the end of the section, so that empty functions have an offset
+    let bounds: Vec<Vec<usize>> = ksm
+        .code_sections()
+        .map(|code_section| {
+            offset += 2;
+
+            std::iter::once(offset)
+                .chain(code_section.instructions().map(|instr| {
+                    offset += instr.size_bytes(index_bytes);
+                    offset
+                }))
+                .collect()
+        })
+        .collect();
+
+    let map = LinkMap {
+        functions: placed
+            .into_iter()
+            .map(|(index, slot, instrs)| {
+                let function = &functions[index];
+
+                MappedFunction {
+                    name: function.name.clone(),
+                    source_file: source_files[function.file_index].clone(),
+                    code_type: [CodeType::Function, CodeType::Initialization, CodeType::Main][slot],
+                    offset: bounds[slot][instrs.start],
+                    size: bounds[slot][instrs.end] - bounds[slot][instrs.start],
+                }
+            })
+            .collect(),
+        arguments: ksm
+            .arg_section
+            .indexed_arguments()
+            .map(|(index, value)| MappedArgument {
+                index,
+                value: value.clone(),
+                sources: arg_sources
+                    .remove(&index)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(file_index, section_index, data_index)| DataSource {
+                        file_index,
+                        source_file: source_files[file_index].clone(),
+                        section_index,
+                        data_index,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        discarded: gc_report.removed_functions.clone(),
+    };
+
+    Ok(Linked {
+        ksm,
+        gc_report,
+        map,
+    })
+}
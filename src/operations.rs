@@ -0,0 +1,350 @@
+//! The operations that the kOS CPU performs on values, such as arithmetic and comparisons
+use crate::errors::KOSValueOpError;
+use crate::{KOSValue, Opcode};
+
+/// A number, after kOS has converted it to one of the two kinds of scalars it computes with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Int(i32),
+    Double(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Double(d) => d,
+        }
+    }
+}
+
+/// Returns the name kOS would use for the type of the provided value
+pub(crate) fn type_name(value: &KOSValue) -> &'static str {
+    match value {
+        KOSValue::Null => "Null",
+        KOSValue::Bool(_) | KOSValue::BoolValue(_) => "Boolean",
+        KOSValue::Byte(_) | KOSValue::Int16(_) | KOSValue::Int32(_) | KOSValue::ScalarInt(_) => {
+            "Scalar"
+        }
+        KOSValue::Float(_) | KOSValue::Double(_) | KOSValue::ScalarDouble(_) => "Scalar",
+        KOSValue::String(_) | KOSValue::StringValue(_) => "String",
+        KOSValue::ArgMarker => "ArgMarker",
+    }
+}
+
+fn to_number(value: &KOSValue) -> Option<Number> {
+    match *value {
+        KOSValue::Byte(b) => Some(Number::Int(b as i32)),
+        KOSValue::Int16(i) => Some(Number::Int(i as i32)),
+        KOSValue::Int32(i) | KOSValue::ScalarInt(i) => Some(Number::Int(i)),
+        KOSValue::Float(f) => Some(Number::Double(f as f64)),
+        KOSValue::Double(d) | KOSValue::ScalarDouble(d) => Some(Number::Double(d)),
+        _ => None,
+    }
+}
+
+fn to_str(value: &KOSValue) -> Option<&str> {
+    match value {
+        KOSValue::String(s) | KOSValue::StringValue(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn double(value: f64) -> Result<KOSValue, KOSValueOpError> {
+    if value.is_finite() {
+        Ok(KOSValue::ScalarDouble(value))
+    } else {
+        Err(KOSValueOpError::NotFinite)
+    }
+}
+
+fn type_error(opcode: Opcode, left: &KOSValue, right: &KOSValue) -> KOSValueOpError {
+    KOSValueOpError::TypeError(
+        opcode,
+        format!("{} and {}", type_name(left), type_name(right)),
+    )
+}
+
+/// Converts a value into a boolean the way kOS does: numbers are true if they aren't zero, and
+/// strings are true if they aren't empty
+fn truthiness(opcode: Opcode, value: &KOSValue) -> Result<bool, KOSValueOpError> {
+    match value {
+        KOSValue::Bool(b) | KOSValue::BoolValue(b) => Ok(*b),
+        KOSValue::String(s) | KOSValue::StringValue(s) => Ok(!s.is_empty()),
+        _ => match to_number(value) {
+            Some(number) => Ok(number.as_f64() != 0.0),
+            None => Err(KOSValueOpError::TypeError(
+                opcode,
+                type_name(value).to_string(),
+            )),
+        },
+    }
+}
+
+/// Converts a value into the string that kOS would print for it
+fn to_kos_string(value: &KOSValue) -> String {
+    match value {
+        KOSValue::Null => String::new(),
+        KOSValue::Bool(b) | KOSValue::BoolValue(b) => {
+            String::from(if *b { "True" } else { "False" })
+        }
+        KOSValue::String(s) | KOSValue::StringValue(s) => s.clone(),
+        KOSValue::ArgMarker => String::from("ArgMarker"),
+        _ => match to_number(value) {
+            Some(Number::Int(i)) => i.to_string(),
+            Some(Number::Double(d)) => d.to_string(),
+            None => unreachable!(),
+        },
+    }
+}
+
+/// Performs an operation that takes two values, with `left` being the value that was pushed
+/// first
+fn binary(opcode: Opcode, left: &KOSValue, right: &KOSValue) -> Result<KOSValue, KOSValueOpError> {
+    match opcode {
+        Opcode::Ceq => return Ok(KOSValue::BoolValue(equals(left, right))),
+        Opcode::Cne => return Ok(KOSValue::BoolValue(!equals(left, right))),
+        Opcode::And => {
+            let result = truthiness(opcode, left)? && truthiness(opcode, right)?;
+            return Ok(KOSValue::BoolValue(result));
+        }
+        Opcode::Or => {
+            let result = truthiness(opcode, left)? || truthiness(opcode, right)?;
+            return Ok(KOSValue::BoolValue(result));
+        }
+        _ => {}
+    }
+
+    // Adding anything to a string concatenates them
+    if opcode == Opcode::Add && (to_str(left).is_some() || to_str(right).is_some()) {
+        return Ok(KOSValue::StringValue(format!(
+            "{}{}",
+            to_kos_string(left),
+            to_kos_string(right)
+        )));
+    }
+
+    let (l, r) = match (to_number(left), to_number(right)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => return Err(type_error(opcode, left, right)),
+    };
+
+    match opcode {
+        Opcode::Add | Opcode::Sub | Opcode::Mul => {
+            if let (Number::Int(a), Number::Int(b)) = (l, r) {
+                let result = match opcode {
+                    Opcode::Add => a.checked_add(b),
+                    Opcode::Sub => a.checked_sub(b),
+                    _ => a.checked_mul(b),
+                };
+
+                // Integers that overflow become doubles instead of wrapping
+                if let Some(result) = result {
+                    return Ok(KOSValue::ScalarInt(result));
+                }
+            }
+
+            let (a, b) = (l.as_f64(), r.as_f64());
+
+            double(match opcode {
+                Opcode::Add => a + b,
+                Opcode::Sub => a - b,
+                _ => a * b,
+            })
+        }
+        Opcode::Div => {
+            if r.as_f64() == 0.0 {
+                return Err(KOSValueOpError::DivideByZero);
+            }
+
+            if let (Number::Int(a), Number::Int(b)) = (l, r) {
+                if let (Some(0), Some(result)) = (a.checked_rem(b), a.checked_div(b)) {
+                    return Ok(KOSValue::ScalarInt(result));
+                }
+            }
+
+            double(l.as_f64() / r.as_f64())
+        }
+        Opcode::Pow => double(l.as_f64().powf(r.as_f64())),
+        Opcode::Cgt => Ok(KOSValue::BoolValue(l.as_f64() > r.as_f64())),
+        Opcode::Clt => Ok(KOSValue::BoolValue(l.as_f64() < r.as_f64())),
+        Opcode::Cge => Ok(KOSValue::BoolValue(l.as_f64() >= r.as_f64())),
+        Opcode::Cle => Ok(KOSValue::BoolValue(l.as_f64() <= r.as_f64())),
+        _ => Err(KOSValueOpError::NotAnOperation(opcode)),
+    }
+}
+
+/// Performs an operation that takes one value
+fn unary(opcode: Opcode, value: &KOSValue) -> Result<KOSValue, KOSValueOpError> {
+    match opcode {
+        Opcode::Neg => match to_number(value) {
+            Some(Number::Int(i)) => match i.checked_neg() {
+                Some(result) => Ok(KOSValue::ScalarInt(result)),
+                None => double(-(i as f64)),
+            },
+            Some(Number::Double(d)) => double(-d),
+            None => Err(KOSValueOpError::TypeError(
+                opcode,
+                type_name(value).to_string(),
+            )),
+        },
+        Opcode::Bool => Ok(KOSValue::BoolValue(truthiness(opcode, value)?)),
+        Opcode::Not => Ok(KOSValue::BoolValue(!truthiness(opcode, value)?)),
+        _ => Err(KOSValueOpError::NotAnOperation(opcode)),
+    }
+}
+
+/// Compares two values for equality the way kOS does. Numbers are compared by value no matter
+/// how they are stored, and strings are compared without case.
+fn equals(left: &KOSValue, right: &KOSValue) -> bool {
+    if let (Some(l), Some(r)) = (to_number(left), to_number(right)) {
+        return match (l, r) {
+            (Number::Int(a), Number::Int(b)) => a == b,
+            _ => l.as_f64() == r.as_f64(),
+        };
+    }
+
+    if let (Some(l), Some(r)) = (to_str(left), to_str(right)) {
+        return l.to_lowercase() == r.to_lowercase();
+    }
+
+    match (left, right) {
+        (
+            KOSValue::Bool(l) | KOSValue::BoolValue(l),
+            KOSValue::Bool(r) | KOSValue::BoolValue(r),
+        ) => l == r,
+        (KOSValue::Null, KOSValue::Null) => true,
+        (KOSValue::ArgMarker, KOSValue::ArgMarker) => true,
+        _ => false,
+    }
+}
+
+impl KOSValue {
+    /// Returns true if this value is a String that names a variable, like `$x`, which kOS reads
+    /// the variable's value from when it is used
+    pub fn is_identifier(&self) -> bool {
+        matches!(self, KOSValue::String(s) if s.starts_with('$'))
+    }
+
+    /// Returns true if this value is a number, no matter how it is stored
+    pub fn is_number(&self) -> bool {
+        to_number(self).is_some()
+    }
+
+    /// Converts this value into a boolean the way the Bool and Not instructions do. Numbers are
+    /// true if they aren't zero, and strings are true if they aren't empty.
+    pub fn to_bool(&self) -> Result<bool, KOSValueOpError> {
+        truthiness(Opcode::Bool, self)
+    }
+
+    /// Returns the string that kOS would print for this value. Unlike [Display](std::fmt::Display),
+    /// strings aren't quoted and booleans are `True` or `False`.
+    pub fn to_kos_string(&self) -> String {
+        to_kos_string(self)
+    }
+
+    /// Compares two values for equality the way the Ceq instruction does. Numbers are compared by
+    /// value no matter how they are stored, and strings are compared without case.
+    ///
+    /// This is different from `==`, which compares how the values are stored.
+    pub fn kos_equals(&self, other: &KOSValue) -> bool {
+        equals(self, other)
+    }
+
+    /// Performs the operation of an instruction that consumes two values, with this value being
+    /// the one that was pushed first. The instruction is one of Add, Sub, Mul, Div, Pow, Cgt,
+    /// Clt, Cge, Cle, Ceq, Cne, And, or Or.
+    ///
+    /// Integers stay integers unless they overflow or are divided unevenly, in which case they
+    /// become doubles, and adding anything to a string concatenates them. Results are always the
+    /// "Value" types that kOS creates at runtime.
+    ///
+    /// ```
+    /// use kerbalobjects::{KOSValue, Opcode};
+    ///
+    /// let sum = KOSValue::Int16(2).binary_op(Opcode::Add, &KOSValue::ScalarInt(3));
+    /// assert_eq!(sum, Ok(KOSValue::ScalarInt(5)));
+    ///
+    /// let quotient = KOSValue::ScalarInt(3).binary_op(Opcode::Div, &KOSValue::ScalarInt(2));
+    /// assert_eq!(quotient, Ok(KOSValue::ScalarDouble(1.5)));
+    ///
+    /// let text = KOSValue::StringValue("x = ".into()).binary_op(Opcode::Add, &KOSValue::Bool(true));
+    /// assert_eq!(text, Ok(KOSValue::StringValue("x = True".into())));
+    /// ```
+    pub fn binary_op(&self, opcode: Opcode, rhs: &KOSValue) -> Result<KOSValue, KOSValueOpError> {
+        binary(opcode, self, rhs)
+    }
+
+    /// Performs the operation of an instruction that consumes one value, which is one of Neg,
+    /// Bool, or Not
+    pub fn unary_op(&self, opcode: Opcode) -> Result<KOSValue, KOSValueOpError> {
+        unary(opcode, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_arithmetic() {
+        let two = KOSValue::Int16(2);
+        let three = KOSValue::ScalarInt(3);
+
+        assert_eq!(
+            two.binary_op(Opcode::Add, &three),
+            Ok(KOSValue::ScalarInt(5))
+        );
+        assert_eq!(
+            two.binary_op(Opcode::Sub, &three),
+            Ok(KOSValue::ScalarInt(-1))
+        );
+        assert_eq!(
+            three.binary_op(Opcode::Div, &two),
+            Ok(KOSValue::ScalarDouble(1.5))
+        );
+        assert_eq!(
+            KOSValue::ScalarInt(i32::MAX).binary_op(Opcode::Add, &KOSValue::Byte(1)),
+            Ok(KOSValue::ScalarDouble(i32::MAX as f64 + 1.0))
+        );
+        assert_eq!(
+            two.binary_op(Opcode::Div, &KOSValue::Double(0.0)),
+            Err(KOSValueOpError::DivideByZero)
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let hello = KOSValue::StringValue("Hello ".into());
+
+        assert_eq!(
+            hello.binary_op(Opcode::Add, &KOSValue::ScalarDouble(2.5)),
+            Ok(KOSValue::StringValue("Hello 2.5".into()))
+        );
+        assert!(KOSValue::kos_equals(
+            &KOSValue::String("ABC".into()),
+            &KOSValue::StringValue("abc".into())
+        ));
+        assert!(hello.binary_op(Opcode::Sub, &KOSValue::Byte(1)).is_err());
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(
+            KOSValue::Byte(1).binary_op(Opcode::Clt, &KOSValue::Float(1.5)),
+            Ok(KOSValue::BoolValue(true))
+        );
+        assert!(KOSValue::kos_equals(
+            &KOSValue::Int32(2),
+            &KOSValue::ScalarDouble(2.0)
+        ));
+        assert!(!KOSValue::kos_equals(
+            &KOSValue::Int32(2),
+            &KOSValue::Bool(true)
+        ));
+        assert_eq!(
+            KOSValue::ScalarInt(0).unary_op(Opcode::Not),
+            Ok(KOSValue::BoolValue(true))
+        );
+    }
+}
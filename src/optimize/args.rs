@@ -0,0 +1,236 @@
+//! Optimizations of the argument section of a KSM file.
+//!
+//! Instructions refer to their operands by their byte offsets into the argument section, and all
+//! of them use the same number of bytes to do so, which is decided by the largest index. Values
+//! that no instruction uses anymore, or long strings early in the section, can make every
+//! operand in the file larger.
+//!
+use std::collections::HashMap;
+
+use crate::ksm::sections::{ArgIndex, ArgumentSection};
+use crate::ksm::{Instr, KSMFile};
+use crate::optimize::{rewrite_ksm, OptimizeReport};
+use crate::KOSValue;
+
+/// Returns true if every operand of every instruction in the file refers to a value in the
+/// argument section
+fn all_operands_valid(file: &KSMFile) -> bool {
+    file.code_sections()
+        .flat_map(|code_section| code_section.instructions())
+        .all(|instr| match *instr {
+            Instr::ZeroOp(_) => true,
+            Instr::OneOp(_, op1) => file.arg_section.get(op1).is_some(),
+            Instr::TwoOp(_, op1, op2) => {
+                file.arg_section.get(op1).is_some() && file.arg_section.get(op2).is_some()
+            }
+        })
+}
+
+/// Rebuilds the argument section of a KSM file so that it only contains the values that
+/// instructions use, and rewrites every instruction's operands to match.
+///
+/// Values are stored in the order they are first used, and values that were stored more than
+/// once are merged. Debug ranges are updated if the instructions change size.
+///
+/// If any instruction has an operand that doesn't refer to a value in the argument section, the
+/// file is left unchanged.
+///
+/// The report counts the removed values as `removed-argument` rewrites.
+///
+/// ```
+/// use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+/// use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+/// use kerbalobjects::optimize::args::compact_arguments;
+/// use kerbalobjects::KOSValue;
+///
+/// let mut arg_section = ArgumentSection::new();
+/// let mut main_code = CodeSection::new(CodeType::Main);
+///
+/// arg_section.add(KOSValue::StringValue("never used".into()));
+///
+/// let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+/// emitter.push(KOSValue::Int16(1));
+/// emitter.eop();
+///
+/// let mut file = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+///
+/// let report = compact_arguments(&mut file);
+///
+/// assert_eq!(report.rewrite_count("removed-argument"), 1);
+/// assert_eq!(file.arg_section.arguments().count(), 1);
+/// ```
+pub fn compact_arguments(file: &mut KSMFile) -> OptimizeReport {
+    if !all_operands_valid(file) {
+        let size = super::ksm_size(file);
+
+        return OptimizeReport {
+            bytes_before: size,
+            bytes_after: size,
+            rewrites: Vec::new(),
+        };
+    }
+
+    let arguments_before = file.arg_section.arguments().count();
+
+    let mut report = rewrite_ksm(file, Some(ArgumentSection::new()), |_| {});
+
+    let removed = arguments_before.saturating_sub(file.arg_section.arguments().count());
+
+    if removed > 0 {
+        report.add_rewrites("removed-argument", removed);
+    }
+
+    report
+}
+
+/// Reorders the argument section of a KSM file to make instruction operands as small as
+/// possible, and rewrites every instruction's operands to match.
+///
+/// The number of bytes every operand takes up is decided by the largest index into the argument
+/// section, which is the index of the last value. Values are sorted from smallest to largest, so
+/// that long strings come last, and values of the same size are sorted so that the most used
+/// come first. Unused values are removed first, like [compact_arguments] does.
+///
+/// If any instruction has an operand that doesn't refer to a value in the argument section, the
+/// file is left unchanged.
+///
+/// The report counts values that were moved as `reordered-argument` rewrites.
+///
+/// ```
+/// use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+/// use kerbalobjects::ksm::{CodeEmitter, IntSize, KSMFile};
+/// use kerbalobjects::optimize::args::order_arguments;
+/// use kerbalobjects::KOSValue;
+///
+/// let mut arg_section = ArgumentSection::new();
+/// let mut main_code = CodeSection::new(CodeType::Main);
+///
+/// let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+/// emitter.push(KOSValue::StringValue("a".repeat(200)));
+/// emitter.push(KOSValue::StringValue("b".repeat(200)));
+/// emitter.push(KOSValue::Int16(1));
+/// emitter.eop();
+///
+/// assert_eq!(arg_section.num_index_bytes(), IntSize::Two);
+///
+/// let mut file = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+///
+/// let report = order_arguments(&mut file);
+///
+/// assert_eq!(file.arg_section.num_index_bytes(), IntSize::One);
+/// assert!(report.bytes_saved() > 0);
+/// ```
+pub fn order_arguments(file: &mut KSMFile) -> OptimizeReport {
+    use std::cmp::Reverse;
+
+    let mut report = compact_arguments(file);
+
+    if !all_operands_valid(file) {
+        return report;
+    }
+
+    // The number of uses of each value, by its current index
+    let mut uses: HashMap<ArgIndex, usize> = HashMap::new();
+
+    for instr in file
+        .code_sections()
+        .flat_map(|code_section| code_section.instructions())
+    {
+        match *instr {
+            Instr::ZeroOp(_) => {}
+            Instr::OneOp(_, op1) => *uses.entry(op1).or_default() += 1,
+            Instr::TwoOp(_, op1, op2) => {
+                *uses.entry(op1).or_default() += 1;
+                *uses.entry(op2).or_default() += 1;
+            }
+        }
+    }
+
+    // After compacting, every value is used, and in the order of first use
+    let mut values: Vec<(usize, ArgIndex, &KOSValue)> = file
+        .arg_section
+        .indexed_arguments()
+        .enumerate()
+        .map(|(position, (index, value))| (position, index, value))
+        .collect();
+
+    values.sort_by_key(|&(position, index, value)| {
+        (
+            value.size_bytes(),
+            Reverse(uses.get(&index).copied().unwrap_or(0)),
+            position,
+        )
+    });
+
+    let moved = values
+        .iter()
+        .enumerate()
+        .filter(|&(new_position, &(position, _, _))| new_position != position)
+        .count();
+
+    let mut arg_section = ArgumentSection::with_capacity(values.len());
+
+    for (_, _, value) in values {
+        arg_section.add(value.clone());
+    }
+
+    let reordered = rewrite_ksm(file, Some(arg_section), |_| {});
+
+    report.bytes_after = reordered.bytes_after;
+
+    if moved > 0 {
+        report.add_rewrites("reordered-argument", moved);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::ResolvedCode;
+    use crate::ksm::sections::{CodeSection, CodeType, DebugEntry, DebugRange, DebugSection};
+    use crate::ksm::{CodeEmitter, IntSize};
+
+    #[test]
+    fn shrinks_index_bytes() {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        // Takes the section past 255 bytes, so every operand needs 2 bytes
+        arg_section.add(KOSValue::StringValue("x".repeat(250)));
+        arg_section.add(KOSValue::Int16(1));
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push(KOSValue::Int16(1));
+        emitter.push(KOSValue::Int16(2));
+        emitter.add();
+        emitter.eop();
+
+        assert_eq!(arg_section.num_index_bytes(), IntSize::Two);
+
+        // The two pushes are 3 bytes each, starting after the 2 byte section header
+        let debug_section = DebugSection::new(
+            DebugEntry::new(1).with_ranges(vec![DebugRange::new(2, 4), DebugRange::new(5, 7)]),
+        );
+
+        let mut file = KSMFile::new_from_parts(arg_section, vec![main_code], debug_section);
+
+        let report = compact_arguments(&mut file);
+
+        assert_eq!(report.rewrite_count("removed-argument"), 1);
+        assert!(report.bytes_saved() > 250);
+        assert_eq!(file.arg_section.num_index_bytes(), IntSize::One);
+
+        let main_code = file.code_sections().next().unwrap();
+        let code = ResolvedCode::from_code_section(main_code, &file.arg_section);
+
+        assert_eq!(code.get(0).unwrap().operand(0), Some(&KOSValue::Int16(1)));
+        assert_eq!(code.get(1).unwrap().operand(0), Some(&KOSValue::Int16(2)));
+
+        let entry = file.debug_section.debug_entries().next().unwrap();
+
+        assert_eq!(entry.get_range(0), Some(&DebugRange::new(2, 3)));
+        assert_eq!(entry.get_range(1), Some(&DebugRange::new(4, 5)));
+    }
+}
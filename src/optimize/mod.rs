@@ -0,0 +1,887 @@
+//! # Optimization
+//!
+//! A module for passes that rewrite the instructions of KO function sections and KSM code
+//! sections to make them smaller, which matters because space on a kOS volume is limited.
+//!
+//! Every pass works on an [InstrList], which is a section's instructions with their operands
+//! looked up, and with relative branch offsets turned into the indexes of the instructions they
+//! branch to. This means that a pass can freely remove or replace instructions, and the branch
+//! offsets are recalculated when the list is turned back into a section.
+//!
+//! [rewrite_ksm_file] and [rewrite_ko_file] run a pass over every section of a file, and keep the
+//! rest of the file up to date: the KSM debug section, and the KO relocation data sections and
+//! function symbol sizes.
+//!
+#[cfg(any(feature = "ko", feature = "ksm"))]
+use std::collections::HashSet;
+
+#[cfg(any(feature = "ko", feature = "ksm"))]
+use crate::analysis::ResolvedCode;
+use crate::{KOSValue, Opcode};
+
+#[cfg(feature = "ko")]
+use std::collections::HashMap;
+
+#[cfg(feature = "ko")]
+use crate::ko::sections::{DataSection, FuncSection, ReldSection};
+#[cfg(feature = "ko")]
+use crate::ko::symbols::{KOSymbol, ReldEntry, SymType};
+#[cfg(feature = "ko")]
+use crate::ko::{KOFile, SectionIdx};
+#[cfg(feature = "ksm")]
+use crate::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugEntry, DebugRange};
+#[cfg(feature = "ksm")]
+use crate::ksm::KSMFile;
+
+#[cfg(feature = "ksm")]
+pub mod args;
+pub mod peephole;
+
+/// The operand of an instruction in an [InstrList]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    /// A value, which is added to the data section or argument section when the list is turned
+    /// back into a section
+    Value(KOSValue),
+    /// The destination of a relative branch, as the index of an instruction in the list. This
+    /// can be the length of the list, which branches to the next section.
+    Target(usize),
+    /// A KO file operand that couldn't be looked up, such as a placeholder that is filled in by
+    /// the linker. The raw data section index is kept as it is.
+    Unresolved(u32),
+}
+
+/// An instruction in an [InstrList]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptInstr {
+    opcode: Opcode,
+    operands: Vec<Operand>,
+    origin: Option<usize>,
+    label: Option<String>,
+    pinned: bool,
+    relocated: bool,
+    // The branch offset operand of the original instruction, so that the new offset can be
+    // stored as the same type of value
+    offset: Option<KOSValue>,
+}
+
+impl OptInstr {
+    /// Creates a new instruction, which didn't come from the original section
+    pub fn new(opcode: Opcode, operands: Vec<Operand>) -> Self {
+        Self {
+            opcode,
+            operands,
+            origin: None,
+            label: None,
+            pinned: false,
+            relocated: false,
+            offset: None,
+        }
+    }
+
+    /// Returns a copy of this instruction with a different opcode. The copy keeps the operands
+    /// and the origin of this instruction.
+    pub fn with_opcode(&self, opcode: Opcode) -> Self {
+        Self {
+            opcode,
+            operands: self.operands.clone(),
+            origin: self.origin,
+            label: None,
+            pinned: false,
+            relocated: self.relocated,
+            offset: self.offset.clone(),
+        }
+    }
+
+    /// Returns a copy of this instruction with different operands. The copy keeps the opcode and
+    /// the origin of this instruction.
+    pub fn with_operands(&self, operands: Vec<Operand>) -> Self {
+        Self {
+            opcode: self.opcode,
+            operands,
+            origin: self.origin,
+            label: None,
+            pinned: false,
+            relocated: self.relocated,
+            offset: self.offset.clone(),
+        }
+    }
+
+    /// Returns the opcode of this instruction
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// Returns the operands of this instruction
+    pub fn operands(&self) -> &[Operand] {
+        &self.operands
+    }
+
+    /// Returns the value of the operand at the provided index, or None if it doesn't exist or
+    /// isn't a value
+    pub fn value(&self, index: usize) -> Option<&KOSValue> {
+        match self.operands.get(index) {
+            Some(Operand::Value(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the branch destination stored in the operand at the provided index, or None if it
+    /// doesn't exist or isn't a branch destination
+    pub fn target(&self, index: usize) -> Option<usize> {
+        match self.operands.get(index) {
+            Some(Operand::Target(target)) => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// Returns the index of the instruction in the original section that this instruction's
+    /// operands came from, or None if it was added by a pass
+    pub fn origin(&self) -> Option<usize> {
+        self.origin
+    }
+
+    /// Returns the kOS label this instruction had in the original section, if it had one
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Returns true if this instruction's label is referenced somewhere, so the instruction can't
+    /// be removed without changing the meaning of the code
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Returns true if a KO file relocation data entry refers to one of this instruction's
+    /// operands. The entry is moved along with the instruction's origin.
+    pub fn is_relocated(&self) -> bool {
+        self.relocated
+    }
+}
+
+/// The instructions of a single section, in a form that passes can rewrite
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrList {
+    instrs: Vec<OptInstr>,
+}
+
+/// Encodes a branch offset as the same type of value as the original offset if it fits, so that
+/// unchanged branches keep their argument. Otherwise it is an Int32, like the emitters use.
+#[cfg(any(feature = "ko", feature = "ksm"))]
+fn encode_offset(offset: i32, original: Option<&KOSValue>) -> KOSValue {
+    match original {
+        Some(KOSValue::Byte(_)) if i8::try_from(offset).is_ok() => KOSValue::Byte(offset as i8),
+        Some(KOSValue::Int16(_)) if i16::try_from(offset).is_ok() => KOSValue::Int16(offset as i16),
+        Some(KOSValue::ScalarInt(_)) => KOSValue::ScalarInt(offset),
+        _ => KOSValue::Int32(offset),
+    }
+}
+
+impl InstrList {
+    /// Lifts resolved code into an instruction list.
+    ///
+    /// `raw_operand` provides the raw index of an operand that couldn't be resolved, and
+    /// instructions that have a label in `referenced_labels` are pinned.
+    #[cfg(any(feature = "ko", feature = "ksm"))]
+    fn lift(
+        code: &ResolvedCode,
+        raw_operand: impl Fn(usize, usize) -> u32,
+        referenced_labels: &HashSet<&str>,
+    ) -> Self {
+        let instrs = code
+            .instructions()
+            .iter()
+            .enumerate()
+            .map(|(index, instr)| {
+                let operands = (0..instr.num_operands())
+                    .map(|operand_index| match instr.operand(operand_index) {
+                        Some(
+                            KOSValue::Byte(_)
+                            | KOSValue::Int16(_)
+                            | KOSValue::Int32(_)
+                            | KOSValue::ScalarInt(_),
+                        ) if operand_index == 0 && instr.opcode().is_branch() => {
+                            match code.branch_destination(index) {
+                                Some(destination) => Operand::Target(destination),
+                                None => Operand::Value(instr.operand(0).unwrap().clone()),
+                            }
+                        }
+                        Some(value) => Operand::Value(value.clone()),
+                        None => Operand::Unresolved(raw_operand(index, operand_index)),
+                    })
+                    .collect();
+
+                let label = code.label_of(index).map(String::from);
+                let pinned = label
+                    .as_deref()
+                    .is_some_and(|label| referenced_labels.contains(label));
+
+                let offset = instr
+                    .operand(0)
+                    .filter(|_| instr.opcode().is_branch())
+                    .cloned();
+
+                OptInstr {
+                    opcode: instr.opcode(),
+                    operands,
+                    origin: Some(index),
+                    label,
+                    pinned,
+                    relocated: false,
+                    offset,
+                }
+            })
+            .collect();
+
+        Self { instrs }
+    }
+
+    /// Lifts the instructions of a KSM code section.
+    ///
+    /// Any instruction whose label appears as a string in the argument section is pinned, because
+    /// something might call or branch to it using that label.
+    #[cfg(feature = "ksm")]
+    pub fn from_code_section(code_section: &CodeSection, arg_section: &ArgumentSection) -> Self {
+        let referenced_labels = arg_section
+            .arguments()
+            .filter_map(|value| match value {
+                KOSValue::String(s) | KOSValue::StringValue(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        Self::lift_code_section(code_section, arg_section, &referenced_labels)
+    }
+
+    #[cfg(feature = "ksm")]
+    fn lift_code_section(
+        code_section: &CodeSection,
+        arg_section: &ArgumentSection,
+        referenced_labels: &HashSet<&str>,
+    ) -> Self {
+        let code = ResolvedCode::from_code_section(code_section, arg_section);
+
+        Self::lift(
+            &code,
+            |index, operand_index| raw_operands_ksm(code_section, index)[operand_index],
+            referenced_labels,
+        )
+    }
+
+    /// Lifts the instructions of a KO function section
+    #[cfg(feature = "ko")]
+    pub fn from_func_section(func_section: &FuncSection, data_section: &DataSection) -> Self {
+        let code = ResolvedCode::from_func_section(func_section, data_section);
+
+        Self::lift(
+            &code,
+            |index, operand_index| raw_operands_ko(func_section, index)[operand_index],
+            &HashSet::new(),
+        )
+    }
+
+    /// Returns the instructions in this list
+    pub fn instructions(&self) -> &[OptInstr] {
+        &self.instrs
+    }
+
+    /// Returns the number of instructions in this list
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    /// Returns true if there are no instructions in this list
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+
+    /// Replaces runs of instructions in a single forward scan, and returns the number of runs
+    /// replaced.
+    ///
+    /// At each index, `rewriter` is given all of the instructions and the index, and can return
+    /// how many instructions starting at that index should be replaced, and what they should be
+    /// replaced with. Branch destinations in the replacement refer to indexes in the list before
+    /// the rewrite, and are updated along with every other branch.
+    ///
+    /// A replacement is skipped if it would change where a branch lands, so only the first
+    /// instruction of a run can be a branch destination, or be pinned. If the first instruction
+    /// is removed without a replacement, branches to it land on the instruction after the run.
+    /// A replacement is also skipped if it drops the origin of a relocated instruction, because
+    /// its relocation data entry would be lost.
+    pub fn rewrite(
+        &mut self,
+        mut rewriter: impl FnMut(&[OptInstr], usize) -> Option<(usize, Vec<OptInstr>)>,
+    ) -> usize {
+        let len = self.instrs.len();
+
+        let mut is_target = vec![false; len + 1];
+        for operand in self.instrs.iter().flat_map(|instr| instr.operands.iter()) {
+            if let Operand::Target(target) = *operand {
+                if let Some(is_target) = is_target.get_mut(target) {
+                    *is_target = true;
+                }
+            }
+        }
+
+        let mut instrs = Vec::with_capacity(len);
+        // The new index of the instruction at each old index, or of the next instruction if it
+        // was removed
+        let mut remap = vec![0; len + 1];
+        let mut replaced = 0;
+        let mut index = 0;
+
+        while index < len {
+            if let Some((matched, mut replacement)) = rewriter(&self.instrs, index) {
+                let end = index + matched;
+
+                let allowed = matched > 0
+                    && end <= len
+                    && (index + 1..end).all(|i| !is_target[i] && !self.instrs[i].pinned)
+                    && (!replacement.is_empty() || !self.instrs[index].pinned)
+                    && self.instrs[index..end]
+                        .iter()
+                        .filter(|instr| instr.relocated)
+                        .all(|instr| {
+                            replacement
+                                .iter()
+                                .any(|new_instr| new_instr.origin == instr.origin)
+                        });
+
+                if allowed {
+                    for old_index in remap.iter_mut().take(end).skip(index) {
+                        *old_index = instrs.len();
+                    }
+
+                    if let Some(first) = replacement.first_mut() {
+                        first.label = self.instrs[index].label.clone();
+                        first.pinned = self.instrs[index].pinned;
+                    }
+
+                    instrs.extend(replacement);
+                    replaced += 1;
+                    index = end;
+                    continue;
+                }
+            }
+
+            remap[index] = instrs.len();
+            instrs.push(self.instrs[index].clone());
+            index += 1;
+        }
+
+        remap[len] = instrs.len();
+
+        for operand in instrs
+            .iter_mut()
+            .flat_map(|instr| instr.operands.iter_mut())
+        {
+            if let Operand::Target(target) = operand {
+                *target = remap[(*target).min(len)];
+            }
+        }
+
+        self.instrs = instrs;
+
+        replaced
+    }
+
+    /// Inserts Lbrt instructions so that every pinned instruction keeps the label it had in the
+    /// original section.
+    ///
+    /// kOS numbers the labels of instructions by counting up from the last Lbrt, so removing an
+    /// instruction changes the labels of every instruction after it. An Lbrt with the original
+    /// label puts the numbering back on track.
+    pub fn restore_labels(&mut self) {
+        let mut next_label: Option<String> = None;
+        let mut counter: Option<u32> = None;
+        let mut index = 0;
+
+        while index < self.instrs.len() {
+            let instr = &self.instrs[index];
+
+            if instr.opcode == Opcode::Lbrt {
+                if let Some(KOSValue::String(label) | KOSValue::StringValue(label)) = instr.value(0)
+                {
+                    if let Some(number) = label.strip_prefix('@').and_then(|n| n.parse().ok()) {
+                        counter = Some(number);
+                    }
+
+                    next_label = Some(label.clone());
+                }
+
+                index += 1;
+                continue;
+            }
+
+            let label = next_label
+                .take()
+                .or_else(|| counter.map(|number| format!("@{:04}", number)));
+
+            if instr.pinned && instr.label != label {
+                let wanted = instr.label.clone().unwrap_or_default();
+
+                for operand in self.instrs.iter_mut().flat_map(|i| i.operands.iter_mut()) {
+                    if let Operand::Target(target) = operand {
+                        if *target >= index {
+                            *target += 1;
+                        }
+                    }
+                }
+
+                let lbrt =
+                    OptInstr::new(Opcode::Lbrt, vec![Operand::Value(KOSValue::String(wanted))]);
+                self.instrs.insert(index, lbrt);
+
+                // Go through the new Lbrt, and then this instruction again
+                continue;
+            }
+
+            counter = counter.map(|number| number + 1);
+            index += 1;
+        }
+    }
+
+    /// Turns this list back into opcodes and raw operand indexes, using `intern` to add values
+    #[cfg(any(feature = "ko", feature = "ksm"))]
+    fn lower(&self, mut intern: impl FnMut(KOSValue) -> u32) -> Vec<(Opcode, Vec<u32>)> {
+        self.instrs
+            .iter()
+            .enumerate()
+            .map(|(index, instr)| {
+                let operands = instr
+                    .operands
+                    .iter()
+                    .map(|operand| match operand {
+                        Operand::Value(value) => intern(value.clone()),
+                        Operand::Target(target) => intern(encode_offset(
+                            *target as i32 - index as i32,
+                            instr.offset.as_ref(),
+                        )),
+                        Operand::Unresolved(raw) => *raw,
+                    })
+                    .collect();
+
+                (instr.opcode, operands)
+            })
+            .collect()
+    }
+
+    /// Turns this list into a KSM code section, adding its operands to the argument section
+    #[cfg(feature = "ksm")]
+    pub fn to_code_section(
+        &self,
+        section_type: CodeType,
+        arg_section: &mut ArgumentSection,
+    ) -> CodeSection {
+        use crate::ksm::sections::ArgIndex;
+        use crate::ksm::Instr;
+
+        let instrs = self
+            .lower(|value| usize::from(arg_section.add_checked(value)) as u32)
+            .into_iter()
+            .map(|(opcode, operands)| match operands[..] {
+                [] => Instr::ZeroOp(opcode),
+                [op1] => Instr::OneOp(opcode, ArgIndex::from(op1)),
+                [op1, op2, ..] => Instr::TwoOp(opcode, ArgIndex::from(op1), ArgIndex::from(op2)),
+            });
+
+        CodeSection::new(section_type).with_instructions(instrs)
+    }
+
+    /// Turns this list into a KO function section, adding its operands to the data section
+    #[cfg(feature = "ko")]
+    pub fn to_func_section(
+        &self,
+        section_index: SectionIdx,
+        data_section: &mut DataSection,
+    ) -> FuncSection {
+        use crate::ko::sections::DataIdx;
+        use crate::ko::Instr;
+
+        let mut func_section = FuncSection::with_capacity(self.instrs.len(), section_index);
+
+        for (opcode, operands) in self.lower(|value| u32::from(data_section.add_checked(value))) {
+            func_section.add(match operands[..] {
+                [] => Instr::ZeroOp(opcode),
+                [op1] => Instr::OneOp(opcode, DataIdx::from(op1)),
+                [op1, op2, ..] => Instr::TwoOp(opcode, DataIdx::from(op1), DataIdx::from(op2)),
+            });
+        }
+
+        func_section
+    }
+
+    /// Returns a map from the index of each instruction in the original section to its index in
+    /// this list, for the instructions that are still here
+    #[cfg(feature = "ko")]
+    fn origin_map(&self) -> HashMap<usize, usize> {
+        self.instrs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, instr)| Some((instr.origin?, index)))
+            .collect()
+    }
+}
+
+/// Collects every string operand of every instruction in a KSM file, other than the labels that
+/// Lbrt instructions set, which are all of the labels that code might refer to
+#[cfg(feature = "ksm")]
+fn referenced_labels(file: &KSMFile) -> HashSet<&str> {
+    file.code_sections()
+        .flat_map(|code_section| {
+            ResolvedCode::from_code_section(code_section, &file.arg_section)
+                .instructions()
+                .to_vec()
+        })
+        .filter(|instr| instr.opcode() != Opcode::Lbrt)
+        .flat_map(|instr| (0..instr.num_operands()).filter_map(move |i| instr.operand(i)))
+        .filter_map(|value| match value {
+            KOSValue::String(s) | KOSValue::StringValue(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(feature = "ksm")]
+fn raw_operands_ksm(code_section: &CodeSection, index: usize) -> Vec<u32> {
+    use crate::ksm::Instr;
+
+    match code_section.instructions().nth(index) {
+        Some(Instr::OneOp(_, op1)) => vec![usize::from(*op1) as u32],
+        Some(Instr::TwoOp(_, op1, op2)) => vec![usize::from(*op1) as u32, usize::from(*op2) as u32],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(feature = "ko")]
+fn raw_operands_ko(func_section: &FuncSection, index: usize) -> Vec<u32> {
+    use crate::ko::Instr;
+
+    match func_section.instructions().nth(index) {
+        Some(Instr::OneOp(_, op1)) => vec![u32::from(*op1)],
+        Some(Instr::TwoOp(_, op1, op2)) => vec![u32::from(*op1), u32::from(*op2)],
+        _ => Vec::new(),
+    }
+}
+
+/// The results of running an optimization over a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    /// The size in bytes of the parts of the file that the optimization could change, before it
+    /// was run
+    pub bytes_before: usize,
+    /// The size in bytes of the same parts of the file, after the optimization was run
+    pub bytes_after: usize,
+    /// The name of each rewrite the optimization performed, along with how many times it did so
+    pub rewrites: Vec<(&'static str, usize)>,
+}
+
+impl OptimizeReport {
+    /// The number of bytes that the optimization saved, which is 0 if the file grew
+    pub fn bytes_saved(&self) -> usize {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+
+    /// The number of times the rewrite with the provided name was performed
+    pub fn rewrite_count(&self, name: &str) -> usize {
+        self.rewrites
+            .iter()
+            .filter(|(rewrite, _)| *rewrite == name)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Adds to the count of the rewrite with the provided name
+    pub fn add_rewrites(&mut self, name: &'static str, count: usize) {
+        match self
+            .rewrites
+            .iter_mut()
+            .find(|(rewrite, _)| *rewrite == name)
+        {
+            Some((_, total)) => *total += count,
+            None => self.rewrites.push((name, count)),
+        }
+    }
+}
+
+#[cfg(feature = "ksm")]
+fn ksm_size(file: &KSMFile) -> usize {
+    let index_bytes = file.arg_section.num_index_bytes();
+
+    file.arg_section.size_bytes()
+        + file
+            .code_sections()
+            .map(|code_section| code_section.size_bytes(index_bytes))
+            .sum::<usize>()
+        + file.debug_section.size_bytes()
+}
+
+/// Runs `pass` over every code section of a KSM file.
+///
+/// Values used by the new instructions are added to the argument section, but values that are no
+/// longer used are not removed. Debug ranges are moved along with the instructions they cover,
+/// and debug entries whose instructions were all removed are dropped.
+///
+/// The returned report has the sizes of the argument, code, and debug sections, and no rewrites.
+#[cfg(feature = "ksm")]
+pub fn rewrite_ksm_file(file: &mut KSMFile, pass: impl FnMut(&mut InstrList)) -> OptimizeReport {
+    rewrite_ksm(file, None, pass)
+}
+
+/// Runs `pass` over every code section of a KSM file like [rewrite_ksm_file], but if
+/// `new_arg_section` is provided, it replaces the file's argument section before the new
+/// instructions are added to it.
+#[cfg(feature = "ksm")]
+fn rewrite_ksm(
+    file: &mut KSMFile,
+    new_arg_section: Option<ArgumentSection>,
+    mut pass: impl FnMut(&mut InstrList),
+) -> OptimizeReport {
+    let bytes_before = ksm_size(file);
+    let old_index_bytes = file.arg_section.num_index_bytes();
+
+    // The start and end of every instruction, by section
+    let old_spans: Vec<Vec<(usize, usize)>> = file
+        .instruction_offsets()
+        .into_iter()
+        .zip(file.code_sections())
+        .map(|(offsets, code_section)| {
+            offsets
+                .into_iter()
+                .zip(code_section.instructions())
+                .map(|(start, instr)| (start, start + instr.size_bytes(old_index_bytes)))
+                .collect()
+        })
+        .collect();
+
+    let referenced_labels = referenced_labels(file);
+
+    let lists: Vec<(CodeType, InstrList)> = file
+        .code_sections()
+        .map(|code_section| {
+            let mut list =
+                InstrList::lift_code_section(code_section, &file.arg_section, &referenced_labels);
+            pass(&mut list);
+            list.restore_labels();
+            (code_section.section_type, list)
+        })
+        .collect();
+
+    if let Some(arg_section) = new_arg_section {
+        file.arg_section = arg_section;
+    }
+
+    let new_sections: Vec<CodeSection> = lists
+        .iter()
+        .map(|(section_type, list)| list.to_code_section(*section_type, &mut file.arg_section))
+        .collect();
+
+    for (code_section, new_section) in file.code_sections_mut().zip(new_sections) {
+        *code_section = new_section;
+    }
+
+    let new_index_bytes = file.arg_section.num_index_bytes();
+
+    // (old start, new start, new end) of every instruction that came from the original code
+    let mut moved: Vec<(usize, usize, usize)> = Vec::new();
+
+    for (((offsets, code_section), (_, list)), old_spans) in file
+        .instruction_offsets()
+        .into_iter()
+        .zip(file.code_sections())
+        .zip(lists.iter())
+        .zip(old_spans.iter())
+    {
+        for ((start, instr), opt_instr) in offsets
+            .into_iter()
+            .zip(code_section.instructions())
+            .zip(list.instructions())
+        {
+            if let Some(origin) = opt_instr.origin() {
+                let end = start + instr.size_bytes(new_index_bytes);
+                moved.push((old_spans[origin].0, start, end));
+            }
+        }
+    }
+
+    moved.sort_unstable();
+
+    let mut debug_section = crate::ksm::sections::DebugSection::new_empty();
+
+    for entry in file.debug_section.debug_entries() {
+        let mut new_entry = DebugEntry::new(entry.line_number);
+
+        for range in entry.ranges() {
+            let first = moved.partition_point(|&(old_start, _, _)| old_start < range.start);
+
+            let covered = moved[first..]
+                .iter()
+                .take_while(|&&(old_start, _, _)| old_start <= range.end);
+
+            let new_range = covered.fold(None, |acc: Option<(usize, usize)>, &(_, start, end)| {
+                Some(match acc {
+                    Some((min, max)) => (min.min(start), max.max(end - 1)),
+                    None => (start, end - 1),
+                })
+            });
+
+            if let Some((start, end)) = new_range {
+                new_entry.add(DebugRange::new(start, end));
+            }
+        }
+
+        if new_entry.number_ranges() > 0 {
+            debug_section.add(new_entry);
+        }
+    }
+
+    file.debug_section = debug_section;
+
+    OptimizeReport {
+        bytes_before,
+        bytes_after: ksm_size(file),
+        rewrites: Vec::new(),
+    }
+}
+
+#[cfg(feature = "ko")]
+fn ko_size(file: &KOFile) -> usize {
+    file.data_sections()
+        .map(|s| s.size() as usize)
+        .sum::<usize>()
+        + file
+            .func_sections()
+            .map(|s| s.size() as usize)
+            .sum::<usize>()
+        + file
+            .reld_sections()
+            .map(|s| s.size() as usize)
+            .sum::<usize>()
+}
+
+/// Runs `pass` over every function section of a KO file.
+///
+/// Values used by the new instructions are added to the .data section, but values that are no
+/// longer used are not removed. Operands with relocation entries are never looked up, relocation
+/// entries are moved along with their instructions, and the sizes of function symbols are
+/// updated. Nothing is changed if the file has no .data section.
+///
+/// The returned report has the sizes of the data, function, and relocation data sections, and
+/// no rewrites.
+#[cfg(feature = "ko")]
+pub fn rewrite_ko_file(file: &mut KOFile, mut pass: impl FnMut(&mut InstrList)) -> OptimizeReport {
+    let bytes_before = ko_size(file);
+
+    let lists: Vec<(SectionIdx, InstrList)> = {
+        let data_section = match file.data_section_by_name(".data") {
+            Some(data_section) => data_section,
+            None => {
+                return OptimizeReport {
+                    bytes_before,
+                    bytes_after: bytes_before,
+                    rewrites: Vec::new(),
+                }
+            }
+        };
+
+        file.func_sections()
+            .map(|func_section| {
+                let section_index = func_section.section_index();
+                let mut list = InstrList::from_func_section(func_section, data_section);
+
+                // Relocated operands are placeholders, so their values mean nothing
+                for entry in file
+                    .reld_sections()
+                    .flat_map(|reld_section| reld_section.entries())
+                    .filter(|entry| entry.section_index == section_index)
+                {
+                    let index = usize::from(entry.instr_index);
+                    let operand_index = u8::from(entry.operand_index) as usize - 1;
+
+                    if let Some(instr) = list.instrs.get_mut(index) {
+                        instr.relocated = true;
+
+                        if let Some(operand) = instr.operands.get_mut(operand_index) {
+                            *operand = Operand::Unresolved(
+                                raw_operands_ko(func_section, index)[operand_index],
+                            );
+                        }
+                    }
+                }
+
+                pass(&mut list);
+
+                (section_index, list)
+            })
+            .collect()
+    };
+
+    let new_sections: Vec<FuncSection> = {
+        let data_section = file
+            .data_section_by_name_mut(".data")
+            .expect("The .data section was found above");
+
+        lists
+            .iter()
+            .map(|(section_index, list)| list.to_func_section(*section_index, data_section))
+            .collect()
+    };
+
+    for (func_section, new_section) in file.func_sections_mut().zip(new_sections) {
+        *func_section = new_section;
+    }
+
+    let origin_maps: HashMap<SectionIdx, HashMap<usize, usize>> = lists
+        .iter()
+        .map(|(section_index, list)| (*section_index, list.origin_map()))
+        .collect();
+
+    for reld_section in file.reld_sections_mut() {
+        let mut new_reld = ReldSection::new(reld_section.section_index());
+
+        for entry in reld_section.entries() {
+            match origin_maps.get(&entry.section_index) {
+                Some(origin_map) => {
+                    if let Some(&new_index) = origin_map.get(&usize::from(entry.instr_index)) {
+                        new_reld.add(ReldEntry {
+                            instr_index: new_index.into(),
+                            ..*entry
+                        });
+                    }
+                }
+                None => {
+                    new_reld.add(*entry);
+                }
+            }
+        }
+
+        *reld_section = new_reld;
+    }
+
+    let sizes: HashMap<SectionIdx, u16> = file
+        .func_sections()
+        .map(|func_section| (func_section.section_index(), func_section.size() as u16))
+        .collect();
+
+    if let Some(symtab) = file.sym_tab_by_name_mut(".symtab") {
+        let updates: Vec<_> = symtab
+            .symbols()
+            .enumerate()
+            .filter(|(_, symbol)| symbol.sym_type == SymType::Func && !symbol.is_undefined())
+            .filter_map(|(index, symbol)| {
+                let size = *sizes.get(&symbol.sh_idx)?;
+                Some((index, KOSymbol { size, ..*symbol }))
+            })
+            .collect();
+
+        for (index, symbol) in updates {
+            symtab.set(index.into(), symbol);
+        }
+    }
+
+    OptimizeReport {
+        bytes_before,
+        bytes_after: ko_size(file),
+        rewrites: Vec::new(),
+    }
+}
@@ -0,0 +1,493 @@
+//! A peephole optimizer, which replaces short runs of instructions with shorter equivalents.
+//!
+//! Each [PeepholeRule] looks at the instructions starting at an index, and can replace some of
+//! them. The [Peephole] optimizer runs its rules over the code until none of them apply anymore.
+//!
+//! The default rules are:
+//!
+//! * [PushPop]: `push x` followed by `pop` is removed
+//! * [DupPop]: `dup` followed by `pop` is removed
+//! * [JumpToNext]: `jmp` to the next instruction is removed
+//! * [NotBranch]: `not` followed by `bfa` becomes `btr`, and `not` followed by `btr` becomes `bfa`
+//! * [EmptyScope]: `bscp` directly followed by `escp 1` is removed
+//! * [ConstantFold]: operations on values that were just pushed as constants are replaced by a
+//!   push of the result
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+//! use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+//! use kerbalobjects::optimize::peephole::Peephole;
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//!
+//! emitter.push(KOSValue::String("$x".into()));
+//! emitter.not();
+//! emitter.bfa(5);
+//! emitter.push(KOSValue::Int16(1));
+//! emitter.pop();
+//! emitter.push(KOSValue::Int16(2));
+//! emitter.pop();
+//! emitter.eop();
+//!
+//! let mut file = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+//!
+//! let report = Peephole::new().optimize_ksm(&mut file);
+//!
+//! assert_eq!(report.rewrite_count("not-branch"), 1);
+//! assert_eq!(report.rewrite_count("push-pop"), 2);
+//! assert!(report.bytes_saved() > 0);
+//! assert_eq!(file.code_sections().next().unwrap().instructions().len(), 3);
+//! # }
+//! ```
+//!
+use crate::optimize::{InstrList, Operand, OptInstr, OptimizeReport};
+use crate::{KOSValue, Opcode};
+
+#[cfg(feature = "ko")]
+use crate::ko::KOFile;
+#[cfg(feature = "ksm")]
+use crate::ksm::KSMFile;
+
+/// A single peephole rewrite
+pub trait PeepholeRule: std::fmt::Debug {
+    /// The name of this rule, which is used in an [OptimizeReport]
+    fn name(&self) -> &'static str;
+
+    /// Tries to match this rule against the instructions starting at the provided index.
+    ///
+    /// Returns how many instructions matched, and what they should be replaced with. See
+    /// [InstrList::rewrite] for when a replacement is allowed.
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)>;
+}
+
+/// Returns the opcodes of the two instructions starting at the provided index
+fn pair(instrs: &[OptInstr], index: usize) -> Option<(Opcode, Opcode)> {
+    Some((instrs.get(index)?.opcode(), instrs.get(index + 1)?.opcode()))
+}
+
+/// Removes a push that is immediately popped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PushPop;
+
+impl PeepholeRule for PushPop {
+    fn name(&self) -> &'static str {
+        "push-pop"
+    }
+
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)> {
+        match pair(instrs, index)? {
+            (Opcode::Push, Opcode::Pop) => Some((2, Vec::new())),
+            _ => None,
+        }
+    }
+}
+
+/// Removes a duplicate that is immediately popped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DupPop;
+
+impl PeepholeRule for DupPop {
+    fn name(&self) -> &'static str {
+        "dup-pop"
+    }
+
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)> {
+        match pair(instrs, index)? {
+            (Opcode::Dup, Opcode::Pop) => Some((2, Vec::new())),
+            _ => None,
+        }
+    }
+}
+
+/// Removes an unconditional jump to the instruction right after it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JumpToNext;
+
+impl PeepholeRule for JumpToNext {
+    fn name(&self) -> &'static str {
+        "jump-to-next"
+    }
+
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)> {
+        let instr = instrs.get(index)?;
+
+        if instr.opcode() == Opcode::Jmp && instr.target(0) == Some(index + 1) {
+            Some((1, Vec::new()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Folds a `not` into the conditional branch after it, by flipping the branch's condition
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NotBranch;
+
+impl PeepholeRule for NotBranch {
+    fn name(&self) -> &'static str {
+        "not-branch"
+    }
+
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)> {
+        let flipped = match pair(instrs, index)? {
+            (Opcode::Not, Opcode::Bfa) => Opcode::Btr,
+            (Opcode::Not, Opcode::Btr) => Opcode::Bfa,
+            _ => return None,
+        };
+
+        Some((2, vec![instrs[index + 1].with_opcode(flipped)]))
+    }
+}
+
+/// Removes a scope that is closed right after it is opened
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmptyScope;
+
+impl PeepholeRule for EmptyScope {
+    fn name(&self) -> &'static str {
+        "empty-scope"
+    }
+
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)> {
+        if pair(instrs, index)? != (Opcode::Bscp, Opcode::Escp) {
+            return None;
+        }
+
+        match instrs[index + 1].value(0)? {
+            KOSValue::Byte(1)
+            | KOSValue::Int16(1)
+            | KOSValue::Int32(1)
+            | KOSValue::ScalarInt(1) => Some((2, Vec::new())),
+            _ => None,
+        }
+    }
+}
+
+/// Computes operations whose operands are constants at compile time, using the same arithmetic
+/// as kOS. See [KOSValue::binary_op].
+///
+/// `push a`, `push b`, followed by an operation that consumes two values becomes `push c`, and
+/// `push a` followed by `neg`, `bool`, or `not` becomes `push b`. Pushes of identifiers like `$x`
+/// are variables, not constants, and operations that would fail at runtime are left alone so
+/// that they still fail.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantFold;
+
+impl ConstantFold {
+    /// Returns the value pushed by the instruction at the provided index, if it pushes a constant
+    fn constant(instrs: &[OptInstr], index: usize) -> Option<&KOSValue> {
+        let instr = instrs.get(index)?;
+
+        if instr.opcode() != Opcode::Push {
+            return None;
+        }
+
+        match instr.value(0)? {
+            KOSValue::Null | KOSValue::ArgMarker => None,
+            value if value.is_identifier() => None,
+            value => Some(value),
+        }
+    }
+}
+
+impl PeepholeRule for ConstantFold {
+    fn name(&self) -> &'static str {
+        "constant-fold"
+    }
+
+    fn apply(&self, instrs: &[OptInstr], index: usize) -> Option<(usize, Vec<OptInstr>)> {
+        let first = Self::constant(instrs, index)?;
+
+        let (count, result) = match instrs.get(index + 1)?.opcode() {
+            opcode @ (Opcode::Neg | Opcode::Bool | Opcode::Not) => (2, first.unary_op(opcode)),
+            _ => {
+                let second = Self::constant(instrs, index + 1)?;
+
+                match instrs.get(index + 2)?.opcode() {
+                    opcode @ (Opcode::Add
+                    | Opcode::Sub
+                    | Opcode::Mul
+                    | Opcode::Div
+                    | Opcode::Pow
+                    | Opcode::Cgt
+                    | Opcode::Clt
+                    | Opcode::Cge
+                    | Opcode::Cle
+                    | Opcode::Ceq
+                    | Opcode::Cne
+                    | Opcode::And
+                    | Opcode::Or) => (3, first.binary_op(opcode, second)),
+                    _ => return None,
+                }
+            }
+        };
+
+        let folded = instrs[index].with_operands(vec![Operand::Value(result.ok()?)]);
+
+        Some((count, vec![folded]))
+    }
+}
+
+/// A peephole optimizer, made up of a list of rules
+#[derive(Debug)]
+pub struct Peephole {
+    rules: Vec<Box<dyn PeepholeRule>>,
+}
+
+impl Peephole {
+    /// Creates a new peephole optimizer with all of the default rules
+    pub fn new() -> Self {
+        Self::empty()
+            .with_rule(PushPop)
+            .with_rule(DupPop)
+            .with_rule(JumpToNext)
+            .with_rule(NotBranch)
+            .with_rule(EmptyScope)
+            .with_rule(ConstantFold)
+    }
+
+    /// Creates a new peephole optimizer without any rules
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// A builder-style method that adds a rule to this optimizer
+    pub fn with_rule(mut self, rule: impl PeepholeRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Runs every rule over the instruction list until none of them apply, adding the number of
+    /// rewrites each rule made to the report
+    pub fn run(&self, list: &mut InstrList, report: &mut OptimizeReport) {
+        loop {
+            let mut changed = false;
+
+            for rule in self.rules.iter() {
+                let count = list.rewrite(|instrs, index| rule.apply(instrs, index));
+
+                if count > 0 {
+                    report.add_rewrites(rule.name(), count);
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Optimizes every code section of a KSM file. See [rewrite_ksm_file](super::rewrite_ksm_file).
+    ///
+    /// Removing instructions can require Lbrt instructions to be added to keep labels that are
+    /// referenced elsewhere, so if the optimized file would end up larger than the original, the
+    /// file is left unchanged and the report has no rewrites.
+    #[cfg(feature = "ksm")]
+    pub fn optimize_ksm(&self, file: &mut KSMFile) -> OptimizeReport {
+        let original = file.clone();
+        let mut rewrites = OptimizeReport::default();
+
+        let mut report = super::rewrite_ksm_file(file, |list| self.run(list, &mut rewrites));
+
+        if report.bytes_after > report.bytes_before {
+            *file = original;
+            report.bytes_after = report.bytes_before;
+        } else {
+            report.rewrites = rewrites.rewrites;
+        }
+
+        report
+    }
+
+    /// Optimizes every function section of a KO file. See [rewrite_ko_file](super::rewrite_ko_file).
+    #[cfg(feature = "ko")]
+    pub fn optimize_ko(&self, file: &mut KOFile) -> OptimizeReport {
+        let mut rewrites = OptimizeReport::default();
+
+        let mut report = super::rewrite_ko_file(file, |list| self.run(list, &mut rewrites));
+        report.rewrites = rewrites.rewrites;
+
+        report
+    }
+}
+
+impl Default for Peephole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "ksm")]
+mod tests {
+    use super::*;
+    use crate::analysis::ResolvedCode;
+    use crate::ksm::sections::{
+        ArgumentSection, CodeSection, CodeType, DebugEntry, DebugRange, DebugSection,
+    };
+    use crate::ksm::CodeEmitter;
+
+    fn build(f: impl FnOnce(&mut CodeEmitter), debug_section: DebugSection) -> KSMFile {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        f(&mut CodeEmitter::new(&mut main_code, &mut arg_section));
+
+        KSMFile::new_from_parts(
+            arg_section,
+            vec![
+                CodeSection::new(CodeType::Function),
+                CodeSection::new(CodeType::Initialization),
+                main_code,
+            ],
+            debug_section,
+        )
+    }
+
+    fn disassemble(file: &KSMFile) -> Vec<String> {
+        let main_code = file.code_sections().nth(2).unwrap();
+
+        ResolvedCode::from_code_section(main_code, &file.arg_section)
+            .instructions()
+            .iter()
+            .map(|instr| instr.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn fixes_branches() {
+        let mut file = build(
+            |emitter| {
+                emitter.push(KOSValue::String("$x".into()));
+                emitter.not();
+                emitter.bfa(4);
+                emitter.dup();
+                emitter.pop();
+                emitter.jmp(1);
+                emitter.bscp(1, 0);
+                emitter.escp(1);
+                emitter.eop();
+            },
+            DebugSection::new_empty(),
+        );
+
+        let report = Peephole::new().optimize_ksm(&mut file);
+
+        assert_eq!(
+            disassemble(&file),
+            vec!["push \"$x\"", "btr 1", "eop"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        );
+
+        // The branch to the empty scope now goes to the instruction after it
+        assert_eq!(report.rewrite_count("empty-scope"), 1);
+        assert_eq!(report.rewrite_count("dup-pop"), 1);
+        assert_eq!(report.rewrite_count("jump-to-next"), 1);
+        assert!(report.bytes_saved() > 0);
+    }
+
+    #[test]
+    fn folds_constants() {
+        let mut file = build(
+            |emitter| {
+                emitter.push(KOSValue::Int16(2));
+                emitter.push(KOSValue::Int16(3));
+                emitter.mul();
+                emitter.push(KOSValue::Int16(4));
+                emitter.add();
+                emitter.neg();
+                emitter.push(KOSValue::String("$x".into()));
+                emitter.push(KOSValue::Int16(1));
+                emitter.add();
+                emitter.push(KOSValue::Int16(1));
+                emitter.push(KOSValue::Int16(0));
+                emitter.div();
+                emitter.eop();
+            },
+            DebugSection::new_empty(),
+        );
+
+        let report = Peephole::new().optimize_ksm(&mut file);
+
+        // Variables aren't constants, and dividing by zero has to fail when the program runs
+        assert_eq!(
+            disassemble(&file),
+            vec![
+                "push -10",
+                "push \"$x\"",
+                "push 1",
+                "add",
+                "push 1",
+                "push 0",
+                "div",
+                "eop"
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+        );
+        assert_eq!(report.rewrite_count("constant-fold"), 3);
+    }
+
+    #[test]
+    fn keeps_referenced_labels() {
+        let mut file = build(
+            |emitter| {
+                emitter.lbrt("@0001");
+                emitter.push(KOSValue::Int16(1));
+                emitter.pop();
+                emitter.nop();
+                emitter.push_arg_marker();
+                emitter.call("", "@0004");
+                emitter.eop();
+            },
+            DebugSection::new_empty(),
+        );
+
+        Peephole::new().optimize_ksm(&mut file);
+
+        let main_code = file.code_sections().nth(2).unwrap();
+        let code = ResolvedCode::from_code_section(main_code, &file.arg_section);
+
+        assert_eq!(code.label("@0004"), Some(3));
+        assert_eq!(code.get(3).unwrap().opcode(), Opcode::Push);
+    }
+
+    #[test]
+    fn moves_debug_ranges() {
+        // The main section's instructions start at 6, after the headers of all three sections
+        let debug_section = DebugSection::new(DebugEntry::new(1).with_range(DebugRange::new(6, 8)))
+            .with_entries(vec![
+                DebugEntry::new(2).with_range(DebugRange::new(9, 10)),
+                DebugEntry::new(3).with_range(DebugRange::new(11, 12)),
+            ]);
+
+        let mut file = build(
+            |emitter| {
+                emitter.push(KOSValue::Int16(1));
+                emitter.pop();
+                emitter.push(KOSValue::Int16(2));
+                emitter.push(KOSValue::Int16(3));
+                emitter.eop();
+            },
+            debug_section,
+        );
+
+        Peephole::new().optimize_ksm(&mut file);
+
+        let entries: Vec<&DebugEntry> = file.debug_section.debug_entries().collect();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].line_number, 2);
+        assert_eq!(entries[0].get_range(0), Some(&DebugRange::new(6, 7)));
+        assert_eq!(entries[1].get_range(0), Some(&DebugRange::new(8, 9)));
+    }
+}
@@ -0,0 +1,243 @@
+//! Line coverage of programs run by the [Vm].
+//!
+//! [Coverage] counts how many times each instruction of a program is executed. Using the line
+//! numbers from the KSM file's debug section, this becomes line coverage of the source code that
+//! the file was compiled or assembled from, which can be written in the lcov tracefile format
+//! that most coverage tools read.
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::ksm::sections::{
+//!     ArgumentSection, CodeSection, CodeType, DebugEntry, DebugRange, DebugSection,
+//! };
+//! use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+//! use kerbalobjects::vm::coverage::Coverage;
+//! use kerbalobjects::vm::{OutputCapture, Program, Vm};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//! emitter.push(KOSValue::Bool(true));
+//! emitter.btr(2);
+//! emitter.nop();
+//! emitter.eop();
+//!
+//! // Each instruction is on its own line
+//! let debug_section = DebugSection::new(DebugEntry::new(1).with_range(DebugRange::new(2, 3)));
+//! let mut ksm = KSMFile::new_from_parts(arg_section, vec![main_code], debug_section);
+//! ksm.add_debug_entry(DebugEntry::new(2).with_range(DebugRange::new(4, 5)));
+//! ksm.add_debug_entry(DebugEntry::new(3).with_range(DebugRange::new(6, 6)));
+//! ksm.add_debug_entry(DebugEntry::new(4).with_range(DebugRange::new(7, 7)));
+//!
+//! let program = Program::from_ksm(&ksm).unwrap();
+//! let mut coverage = Coverage::new(&program);
+//! let mut vm = Vm::new(program);
+//!
+//! coverage.run(&mut vm, &mut OutputCapture::new()).unwrap();
+//!
+//! assert_eq!(coverage.lines_hit(), 3);
+//! assert_eq!(coverage.lines_found(), 4);
+//! assert!(coverage.to_lcov("program.ks").contains("DA:3,0\n"));
+//! # }
+//! ```
+//!
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+use crate::errors::VmError;
+use crate::vm::{Builtins, Program, Vm};
+
+/// The number of times each instruction of a [Program] was executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    hits: Vec<usize>,
+    lines: Vec<Option<isize>>,
+    ranges: Vec<Option<(usize, usize)>>,
+}
+
+impl Coverage {
+    /// Creates a new Coverage for the provided program, with no instructions executed
+    pub fn new(program: &Program) -> Self {
+        Self {
+            hits: vec![0; program.len()],
+            lines: (0..program.len())
+                .map(|index| program.line(index))
+                .collect(),
+            ranges: (0..program.len())
+                .map(|index| program.byte_range(index))
+                .collect(),
+        }
+    }
+
+    /// Records that the instruction at the provided index was executed
+    pub fn record(&mut self, index: usize) {
+        if let Some(hits) = self.hits.get_mut(index) {
+            *hits += 1;
+        }
+    }
+
+    /// Runs the VM until the program finishes or an error occurs, like [Vm::run], recording
+    /// every instruction that is executed.
+    ///
+    /// The VM must be running the program this Coverage was created for. Instructions that were
+    /// executed before an error are still recorded.
+    pub fn run(&mut self, vm: &mut Vm, builtins: &mut dyn Builtins) -> Result<(), VmError> {
+        vm.run_with(builtins, |_, index| {
+            self.record(index);
+
+            ControlFlow::Continue(())
+        })
+        .map(|_| ())
+    }
+
+    /// Returns the number of times the instruction at the provided index was executed
+    pub fn hits(&self, index: usize) -> usize {
+        self.hits.get(index).copied().unwrap_or(0)
+    }
+
+    /// Returns the byte ranges of the KSM file whose instructions were executed, with adjacent
+    /// ranges merged, sorted by their offsets. Ranges are inclusive, like debug ranges.
+    pub fn executed_ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .ranges
+            .iter()
+            .zip(&self.hits)
+            .filter(|&(_, &hits)| hits > 0)
+            .filter_map(|(range, _)| *range)
+            .collect();
+
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end + 1 => *last_end = end.max(*last_end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        merged
+    }
+
+    /// Returns the number of times each source line was executed, by line number.
+    ///
+    /// A line's count is the most times any of its instructions was executed. Only lines that
+    /// have instructions are included.
+    pub fn line_hits(&self) -> BTreeMap<isize, usize> {
+        let mut line_hits = BTreeMap::new();
+
+        for (line, &hits) in self.lines.iter().zip(&self.hits) {
+            if let Some(line) = *line {
+                let count = line_hits.entry(line).or_insert(0);
+                *count = hits.max(*count);
+            }
+        }
+
+        line_hits
+    }
+
+    /// Returns the number of source lines that have instructions
+    pub fn lines_found(&self) -> usize {
+        self.line_hits().len()
+    }
+
+    /// Returns the number of source lines that had at least one instruction executed
+    pub fn lines_hit(&self) -> usize {
+        self.line_hits().values().filter(|&&hits| hits > 0).count()
+    }
+
+    /// Writes the line coverage as an lcov tracefile record for the provided source file
+    pub fn to_lcov(&self, source_file: &str) -> String {
+        let line_hits = self.line_hits();
+        let mut lcov = String::new();
+
+        // Writing to a String can't fail
+        let _ = writeln!(lcov, "TN:");
+        let _ = writeln!(lcov, "SF:{}", source_file);
+
+        for (line, hits) in &line_hits {
+            let _ = writeln!(lcov, "DA:{},{}", line, hits);
+        }
+
+        let _ = writeln!(lcov, "LF:{}", line_hits.len());
+        let _ = writeln!(
+            lcov,
+            "LH:{}",
+            line_hits.values().filter(|&&hits| hits > 0).count()
+        );
+        let _ = writeln!(lcov, "end_of_record");
+
+        lcov
+    }
+}
+
+#[cfg(all(test, feature = "ksm"))]
+mod tests {
+    use super::*;
+    use crate::ksm::sections::{
+        ArgumentSection, CodeSection, CodeType, DebugEntry, DebugRange, DebugSection,
+    };
+    use crate::ksm::{CodeEmitter, KSMFile};
+    use crate::vm::OutputCapture;
+    use crate::KOSValue;
+
+    #[test]
+    fn counts_loop_iterations() {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        // Line 1: set i to 0.
+        // Line 2: until i = 3 {
+        // Line 3:     set i to i + 1.
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push(KOSValue::ScalarInt(0));
+        emitter.sto("$i");
+        emitter.push(KOSValue::String("$i".into()));
+        emitter.push(KOSValue::ScalarInt(3));
+        emitter.ceq();
+        emitter.btr(6);
+        emitter.push(KOSValue::String("$i".into()));
+        emitter.push(KOSValue::ScalarInt(1));
+        emitter.add();
+        emitter.sto("$i");
+        emitter.jmp(-8);
+        emitter.eop();
+
+        let mut file =
+            KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+
+        let offsets = file.instruction_offsets().remove(0);
+        let range =
+            |first: usize, last: usize| DebugRange::new(offsets[first], offsets[last + 1] - 1);
+
+        file.add_debug_entry(DebugEntry::new(1).with_range(range(0, 1)));
+        file.add_debug_entry(DebugEntry::new(2).with_range(range(2, 5)));
+        file.add_debug_entry(DebugEntry::new(3).with_range(range(6, 10)));
+
+        let program = Program::from_ksm(&file).unwrap();
+        let mut coverage = Coverage::new(&program);
+        let mut vm = Vm::new(program).with_step_limit(1000);
+
+        coverage.run(&mut vm, &mut OutputCapture::new()).unwrap();
+
+        assert_eq!(coverage.hits(0), 1);
+        assert_eq!(coverage.hits(2), 4);
+        assert_eq!(coverage.hits(6), 3);
+
+        assert_eq!(
+            coverage.to_lcov("loop.ks"),
+            "TN:\nSF:loop.ks\nDA:1,1\nDA:2,4\nDA:3,3\nLF:3\nLH:3\nend_of_record\n"
+        );
+
+        // Every instruction ran, so all of the code is one range
+        let last = offsets.len() - 1;
+        assert_eq!(
+            coverage.executed_ranges(),
+            vec![(offsets[0], offsets[last])]
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! A step debugger for programs run by the [Vm].
+//!
+//! The [Debugger] wraps a VM and runs it one instruction at a time, stopping at breakpoints,
+//! which can be set on source lines, on byte offsets into the KSM file the program was loaded
+//! from, or on instructions of the program itself. While stopped, the VM's stack and variables
+//! can be inspected, and the code around the current instruction can be disassembled.
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+//! use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+//! use kerbalobjects::vm::debugger::{Breakpoint, Debugger, StopReason};
+//! use kerbalobjects::vm::{OutputCapture, Program, StackValue, Vm};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//! emitter.push(KOSValue::ScalarInt(2));
+//! emitter.push(KOSValue::ScalarInt(3));
+//! emitter.mul();
+//! emitter.sto("$x");
+//! emitter.eop();
+//!
+//! let ksm = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+//!
+//! let mut debugger = Debugger::new(Vm::new(Program::from_ksm(&ksm).unwrap()));
+//! let mut builtins = OutputCapture::new();
+//!
+//! assert!(debugger.add_breakpoint(Breakpoint::Instruction(3)));
+//!
+//! let reason = debugger.resume(&mut builtins).unwrap();
+//!
+//! assert_eq!(reason, StopReason::Breakpoint(Breakpoint::Instruction(3)));
+//! assert_eq!(debugger.vm().stack(), &[StackValue::Value(KOSValue::ScalarInt(6))]);
+//!
+//! debugger.step(&mut builtins).unwrap();
+//!
+//! assert_eq!(
+//!     debugger.vm().variable("x"),
+//!     Some(&StackValue::Value(KOSValue::ScalarInt(6)))
+//! );
+//! # }
+//! ```
+//!
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+use crate::errors::VmError;
+use crate::vm::{Builtins, Program, ProgramInstr, Vm, VmState};
+
+/// A place in a program to stop at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Breakpoint {
+    /// Stops at the first instruction of a source line, using the line numbers from the KSM
+    /// file's debug section
+    Line(isize),
+    /// Stops at the instruction at a byte offset into the KSM file, measured the same way as the
+    /// debug section
+    Offset(usize),
+    /// Stops at the instruction at an index into the [Program]
+    Instruction(usize),
+}
+
+/// The reason the debugger stopped running the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested step was completed
+    Step,
+    /// The next instruction has a breakpoint
+    Breakpoint(Breakpoint),
+    /// The program finished
+    Finished,
+}
+
+/// Returns the breakpoint that the instruction at the provided index stops at, if any. Line
+/// breakpoints only stop at the first instruction of their line, not every instruction of it.
+fn breakpoint_at(
+    breakpoints: &[Breakpoint],
+    last_line: Option<isize>,
+    program: &Program,
+    index: usize,
+) -> Option<Breakpoint> {
+    breakpoints
+        .iter()
+        .copied()
+        .find(|&breakpoint| match breakpoint {
+            Breakpoint::Line(line) => program.line(index) == Some(line) && last_line != Some(line),
+            Breakpoint::Offset(offset) => program.offset(index) == Some(offset),
+            Breakpoint::Instruction(instr_index) => instr_index == index,
+        })
+}
+
+/// A debugger that controls a [Vm]. See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    vm: Vm,
+    breakpoints: Vec<Breakpoint>,
+    last_line: Option<isize>,
+}
+
+impl Debugger {
+    /// Creates a new debugger, which starts stopped at the VM's current instruction
+    pub fn new(vm: Vm) -> Self {
+        Self {
+            vm,
+            breakpoints: Vec::new(),
+            last_line: None,
+        }
+    }
+
+    /// Returns the VM that is being debugged, to inspect its stack and variables
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Consumes this debugger, returning the VM
+    pub fn into_vm(self) -> Vm {
+        self.vm
+    }
+
+    /// Adds a breakpoint.
+    ///
+    /// Returns false if the breakpoint doesn't match any instruction of the program, in which
+    /// case it isn't added.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let program = self.vm.program();
+
+        let valid = match breakpoint {
+            Breakpoint::Line(line) => {
+                (0..program.len()).any(|index| program.line(index) == Some(line))
+            }
+            Breakpoint::Offset(offset) => program.index_of_offset(offset).is_some(),
+            Breakpoint::Instruction(index) => index < program.len(),
+        };
+
+        if valid && !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+
+        valid
+    }
+
+    /// Removes a breakpoint. Returns true if it existed.
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&existing| existing != breakpoint);
+
+        self.breakpoints.len() != len
+    }
+
+    /// Returns all of the breakpoints, in the order they were added
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Returns the instruction that will be executed next
+    pub fn current_instruction(&self) -> Option<&ProgramInstr> {
+        self.vm.program().get(self.vm.ip())
+    }
+
+    /// Returns the source line of the instruction that will be executed next, if it is known
+    pub fn current_line(&self) -> Option<isize> {
+        self.vm.program().line(self.vm.ip())
+    }
+
+    /// Executes instructions until `keep_going` returns false, the program finishes, or a
+    /// breakpoint is reached. At least one instruction is always executed.
+    fn run_while(
+        &mut self,
+        builtins: &mut dyn Builtins,
+        mut keep_going: impl FnMut(&Vm) -> bool,
+    ) -> Result<StopReason, VmError> {
+        let breakpoints = &self.breakpoints;
+        let last_line = &mut self.last_line;
+        let mut reason = StopReason::Step;
+
+        let state = self.vm.run_with(builtins, |vm, index| {
+            *last_line = vm.program().line(index);
+
+            if !keep_going(vm) {
+                return ControlFlow::Break(());
+            }
+
+            match breakpoint_at(breakpoints, *last_line, vm.program(), vm.ip()) {
+                Some(breakpoint) => {
+                    reason = StopReason::Breakpoint(breakpoint);
+                    ControlFlow::Break(())
+                }
+                None => ControlFlow::Continue(()),
+            }
+        })?;
+
+        Ok(match state {
+            VmState::Finished => StopReason::Finished,
+            VmState::Running => reason,
+        })
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self, builtins: &mut dyn Builtins) -> Result<StopReason, VmError> {
+        self.run_while(builtins, |_| false)
+    }
+
+    /// Executes a single instruction, and if it calls a function, runs until that function
+    /// returns
+    pub fn step_over(&mut self, builtins: &mut dyn Builtins) -> Result<StopReason, VmError> {
+        let depth = self.vm.call_depth();
+
+        self.run_while(builtins, |vm| vm.call_depth() > depth)
+    }
+
+    /// Runs until the current function returns. Outside of a function, this runs until the
+    /// program finishes.
+    pub fn step_out(&mut self, builtins: &mut dyn Builtins) -> Result<StopReason, VmError> {
+        let depth = self.vm.call_depth();
+
+        self.run_while(builtins, |vm| depth == 0 || vm.call_depth() >= depth)
+    }
+
+    /// Runs until a breakpoint is reached or the program finishes
+    pub fn resume(&mut self, builtins: &mut dyn Builtins) -> Result<StopReason, VmError> {
+        self.run_while(builtins, |_| true)
+    }
+
+    /// Disassembles the instructions from `start` up to but not including `end`, one per line.
+    ///
+    /// Each line has the instruction's index, its offset into the KSM file and its label if they
+    /// are known, and the instruction itself. The current instruction is marked with `=>` and
+    /// instructions with breakpoints are marked with `*`.
+    pub fn disassemble(&self, start: usize, end: usize) -> String {
+        let program = self.vm.program();
+        let mut output = String::new();
+
+        for index in start..end.min(program.len()) {
+            let marker = if index == self.vm.ip() {
+                "=>"
+            } else if self.breakpoints.iter().any(|&breakpoint| match breakpoint {
+                Breakpoint::Line(line) => {
+                    program.line(index) == Some(line)
+                        && (index == 0 || program.line(index - 1) != Some(line))
+                }
+                Breakpoint::Offset(offset) => program.offset(index) == Some(offset),
+                Breakpoint::Instruction(instr_index) => instr_index == index,
+            }) {
+                " *"
+            } else {
+                "  "
+            };
+
+            let offset = program
+                .offset(index)
+                .map(|offset| format!("{:#06x}", offset))
+                .unwrap_or_default();
+
+            let label = program.label_of(index).unwrap_or_default();
+
+            if let Some(instr) = program.get(index) {
+                // Writing to a String can't fail
+                let _ = writeln!(
+                    output,
+                    "{} {:>5}  {:>6}  {:<10} {}",
+                    marker, index, offset, label, instr
+                );
+            }
+        }
+
+        output
+    }
+
+    /// Disassembles the instructions around the current instruction, with up to `context`
+    /// instructions before and after it
+    pub fn disassemble_current(&self, context: usize) -> String {
+        let ip = self.vm.ip();
+
+        self.disassemble(ip.saturating_sub(context), ip + context + 1)
+    }
+}
+
+#[cfg(all(test, feature = "ksm"))]
+mod tests {
+    use super::*;
+    use crate::ksm::sections::{
+        ArgumentSection, CodeSection, CodeType, DebugEntry, DebugRange, DebugSection,
+    };
+    use crate::ksm::{CodeEmitter, KSMFile};
+    use crate::vm::{OutputCapture, Program, StackValue};
+    use crate::KOSValue;
+
+    /// A program with a function that adds one to its argument, called twice
+    fn program() -> Program {
+        let mut arg_section = ArgumentSection::new();
+        let mut function_code = CodeSection::new(CodeType::Function);
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        let mut emitter = CodeEmitter::new(&mut function_code, &mut arg_section);
+        emitter.lbrt("add_one");
+        emitter.push(KOSValue::ScalarInt(1));
+        emitter.add();
+        emitter.ret(0);
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::ScalarInt(1));
+        emitter.call("add_one", "");
+        emitter.push_arg_marker();
+        emitter.swap();
+        emitter.call("add_one", "");
+        emitter.eop();
+
+        let mut file = KSMFile::new_from_parts(
+            arg_section,
+            vec![function_code, main_code],
+            DebugSection::new_empty(),
+        );
+
+        // Line 1 is the function, and each call in the main code is its own line
+        let offsets = file.instruction_offsets();
+        let range = |section: usize, first: usize, last: usize| {
+            let end = offsets[section]
+                .get(last + 1)
+                .copied()
+                .unwrap_or(offsets[section][last] + 1);
+            DebugRange::new(offsets[section][first], end - 1)
+        };
+
+        file.add_debug_entry(DebugEntry::new(1).with_range(range(0, 1, 3)));
+        file.add_debug_entry(DebugEntry::new(2).with_range(range(1, 0, 2)));
+        file.add_debug_entry(DebugEntry::new(3).with_range(range(1, 3, 5)));
+
+        Program::from_ksm(&file).unwrap()
+    }
+
+    #[test]
+    fn stops_at_line_breakpoints() {
+        let mut debugger = Debugger::new(Vm::new(program()));
+        let mut builtins = OutputCapture::new();
+
+        assert!(debugger.add_breakpoint(Breakpoint::Line(1)));
+        assert!(debugger.add_breakpoint(Breakpoint::Line(3)));
+        assert!(!debugger.add_breakpoint(Breakpoint::Line(4)));
+
+        assert_eq!(
+            debugger.resume(&mut builtins),
+            Ok(StopReason::Breakpoint(Breakpoint::Line(1)))
+        );
+        assert_eq!(debugger.vm().call_depth(), 1);
+
+        assert_eq!(debugger.step_out(&mut builtins), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().call_depth(), 0);
+        assert_eq!(
+            debugger.vm().stack().last(),
+            Some(&StackValue::Value(KOSValue::ScalarInt(2)))
+        );
+
+        // Returning from the function lands on the first instruction of line 3
+        assert_eq!(debugger.current_line(), Some(3));
+
+        assert_eq!(
+            debugger.resume(&mut builtins),
+            Ok(StopReason::Breakpoint(Breakpoint::Line(1)))
+        );
+        assert_eq!(debugger.vm().call_depth(), 1);
+
+        assert!(debugger.remove_breakpoint(Breakpoint::Line(1)));
+        assert_eq!(debugger.resume(&mut builtins), Ok(StopReason::Finished));
+    }
+
+    #[test]
+    fn steps_over_calls() {
+        let mut debugger = Debugger::new(Vm::new(program()));
+        let mut builtins = OutputCapture::new();
+
+        debugger.step(&mut builtins).unwrap();
+        debugger.step(&mut builtins).unwrap();
+
+        assert_eq!(
+            debugger
+                .current_instruction()
+                .map(|instr| instr.to_string()),
+            Some(String::from("call \"add_one\", \"\""))
+        );
+
+        assert_eq!(debugger.step_over(&mut builtins), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().call_depth(), 0);
+        assert_eq!(debugger.vm().ip(), 7);
+
+        let disassembly = debugger.disassemble_current(1);
+        let lines: Vec<&str> = disassembly.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("=>     7"));
+        assert!(lines[1].ends_with("push #"));
+
+        let offset = debugger.vm().program().offset(9).unwrap();
+        assert!(debugger.add_breakpoint(Breakpoint::Offset(offset)));
+
+        assert_eq!(
+            debugger.resume(&mut builtins),
+            Ok(StopReason::Breakpoint(Breakpoint::Offset(offset)))
+        );
+        assert_eq!(debugger.vm().ip(), 9);
+    }
+}
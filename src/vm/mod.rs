@@ -0,0 +1,1162 @@
+//! # Virtual machine
+//!
+//! A module for running kOS programs outside of the game, so that compiled or linked code can be
+//! tested headlessly.
+//!
+//! A [Program] is loaded from a KSM file, or from a set of KO files that are linked together in
+//! memory by the linker, and is then run by a [Vm]. The VM follows the stack machine semantics of the kOS CPU:
+//! scopes, variables, arithmetic and comparisons, calls, returns, and delegates. Everything that
+//! would need the rest of the game, such as `print()`, goes through the [Builtins] trait, so
+//! tests can provide their own implementation and assert on what happened. [OutputCapture]
+//! implements `print()` by recording each line that is printed.
+//!
+//! Suffixes, indexing, and triggers aren't supported, and executing them returns
+//! [VmError::Unsupported].
+//!
+//! ```
+//! # #[cfg(feature = "ksm")] {
+//! use kerbalobjects::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+//! use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+//! use kerbalobjects::vm::{OutputCapture, Program, Vm};
+//! use kerbalobjects::KOSValue;
+//!
+//! let mut arg_section = ArgumentSection::new();
+//! let mut main_code = CodeSection::new(CodeType::Main);
+//!
+//! let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+//! emitter.push(KOSValue::ScalarInt(2));
+//! emitter.push(KOSValue::ScalarInt(3));
+//! emitter.add();
+//! emitter.sto("$x");
+//! emitter.push_arg_marker();
+//! emitter.push(KOSValue::String("$x".into()));
+//! emitter.call("", "print()");
+//! emitter.pop();
+//! emitter.eop();
+//!
+//! let ksm = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+//!
+//! let mut vm = Vm::new(Program::from_ksm(&ksm).expect("Could not load program"));
+//! let mut output = OutputCapture::new();
+//!
+//! vm.run(&mut output).expect("Program failed");
+//!
+//! assert_eq!(output.lines(), &["5"]);
+//! # }
+//! ```
+//!
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+use crate::analysis::ResolvedInstr;
+use crate::errors::VmError;
+use crate::{KOSValue, Opcode};
+
+pub mod coverage;
+pub mod debugger;
+
+/// The name that a Call instruction uses to call the delegate below its arguments
+const INDIRECT_CALL: &str = "<indirect>";
+
+/// An instruction of a [Program], with its operands already looked up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramInstr {
+    opcode: Opcode,
+    operands: Vec<KOSValue>,
+}
+
+impl ProgramInstr {
+    /// Creates a new instruction
+    pub fn new(opcode: Opcode, operands: Vec<KOSValue>) -> Self {
+        Self { opcode, operands }
+    }
+
+    /// Returns this instruction's opcode
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// Returns this instruction's operands
+    pub fn operands(&self) -> &[KOSValue] {
+        &self.operands
+    }
+}
+
+impl std::fmt::Display for ProgramInstr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operands = self.operands.iter().map(Some).collect();
+
+        ResolvedInstr::new(self.opcode, operands).fmt(f)
+    }
+}
+
+/// A program that is ready to be run by a [Vm].
+///
+/// All of the program's code is stored as one list of instructions, with functions first, and
+/// the code that runs when the program starts after them.
+#[derive(Debug, Clone)]
+pub struct Program {
+    instrs: Vec<ProgramInstr>,
+    labels: HashMap<String, usize>,
+    lines: Vec<Option<isize>>,
+    ranges: Vec<Option<(usize, usize)>>,
+    entry: usize,
+}
+
+impl Program {
+    /// Creates a new program from a list of instructions, which starts running at `entry`.
+    ///
+    /// Labels are counted the way kOS counts them, the same as
+    /// [ResolvedCode](crate::analysis::ResolvedCode).
+    pub fn new(instrs: Vec<ProgramInstr>, entry: usize) -> Self {
+        let labels = {
+            let resolved = crate::analysis::ResolvedCode::new(
+                instrs
+                    .iter()
+                    .map(|instr| {
+                        ResolvedInstr::new(instr.opcode, instr.operands.iter().map(Some).collect())
+                    })
+                    .collect(),
+            );
+
+            (0..resolved.len())
+                .filter_map(|index| {
+                    resolved
+                        .label_of(index)
+                        .map(|label| (label.to_string(), index))
+                })
+                .collect()
+        };
+
+        let lines = vec![None; instrs.len()];
+        let ranges = vec![None; instrs.len()];
+
+        Self {
+            instrs,
+            labels,
+            lines,
+            ranges,
+            entry,
+        }
+    }
+
+    /// Loads a KSM file.
+    ///
+    /// The function code sections are placed first, followed by the initialization code sections,
+    /// and then the main code sections, which is the order kOS loads them in. The program starts
+    /// at the first instruction that isn't part of a function. Line numbers are taken from the
+    /// file's debug section.
+    #[cfg(feature = "ksm")]
+    pub fn from_ksm(file: &crate::ksm::KSMFile) -> Result<Self, VmError> {
+        use crate::analysis::ResolvedCode;
+        use crate::ksm::sections::CodeType;
+
+        let offsets = file.instruction_offsets();
+        let index_bytes = file.arg_section.num_index_bytes();
+        let sizes: Vec<Vec<usize>> = file
+            .code_sections()
+            .map(|code_section| {
+                code_section
+                    .instructions()
+                    .map(|instr| instr.size_bytes(index_bytes))
+                    .collect()
+            })
+            .collect();
+
+        // Labels are counted across the whole file, in the order the sections are stored
+        let mut resolved = Vec::new();
+        let mut positions = Vec::new();
+
+        for (section_index, code_section) in file.code_sections().enumerate() {
+            let code = ResolvedCode::from_code_section(code_section, &file.arg_section);
+
+            for (instr_index, instr) in code.instructions().iter().enumerate() {
+                if (0..instr.num_operands()).any(|i| instr.operand(i).is_none()) {
+                    return Err(VmError::InvalidOperand(resolved.len()));
+                }
+
+                resolved.push(instr.clone());
+                positions.push((section_index, instr_index));
+            }
+        }
+
+        let code = ResolvedCode::new(resolved);
+
+        let section_types: Vec<CodeType> = file
+            .code_sections()
+            .map(|code_section| code_section.section_type)
+            .collect();
+
+        let mut order: Vec<usize> = (0..code.len()).collect();
+        order.sort_by_key(|&index| match section_types[positions[index].0] {
+            CodeType::Function => 0,
+            CodeType::Initialization => 1,
+            CodeType::Main => 2,
+        });
+
+        let mut instrs = Vec::with_capacity(order.len());
+        let mut labels = HashMap::new();
+        let mut lines = Vec::with_capacity(order.len());
+        let mut ranges = Vec::with_capacity(order.len());
+
+        for (new_index, &index) in order.iter().enumerate() {
+            let instr = &code.instructions()[index];
+            let operands = (0..instr.num_operands())
+                .filter_map(|i| instr.operand(i).cloned())
+                .collect();
+
+            instrs.push(ProgramInstr::new(instr.opcode(), operands));
+
+            if let Some(label) = code.label_of(index) {
+                labels.insert(label.to_string(), new_index);
+            }
+
+            let (section_index, instr_index) = positions[index];
+            let offset = offsets[section_index][instr_index];
+            let size = sizes[section_index][instr_index];
+
+            lines.push(file.debug_section.line_for_offset(offset));
+            ranges.push(Some((offset, offset + size - 1)));
+        }
+
+        let entry = order
+            .iter()
+            .position(|&index| section_types[positions[index].0] != CodeType::Function)
+            .unwrap_or(order.len());
+
+        Ok(Self {
+            instrs,
+            labels,
+            lines,
+            ranges,
+            entry,
+        })
+    }
+
+    /// Loads a set of KO files, by [linking](crate::link::link) them together in memory with the
+    /// default options and loading the result.
+    ///
+    /// The program sets every variable to its initial value, then runs `_init` if it exists,
+    /// followed by `_start`.
+    #[cfg(all(feature = "ko", feature = "ksm"))]
+    pub fn from_ko_files(files: &[crate::ko::KOFile]) -> Result<Self, VmError> {
+        let linked = crate::link::link(files, &crate::link::LinkOptions::default())
+            .map_err(VmError::LinkError)?;
+
+        Self::from_ksm(linked.ksm_file())
+    }
+
+    /// Returns the instructions of this program
+    pub fn instructions(&self) -> &[ProgramInstr] {
+        &self.instrs
+    }
+
+    /// Returns the instruction at the provided index, or None if it doesn't exist
+    pub fn get(&self, index: usize) -> Option<&ProgramInstr> {
+        self.instrs.get(index)
+    }
+
+    /// Returns the number of instructions in this program
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    /// Returns true if this program has no instructions
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+
+    /// Returns the index of the instruction that the program starts at
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Returns the index of the instruction with the provided label
+    pub fn label(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).copied()
+    }
+
+    /// Returns the label of the instruction at the provided index, if it has one
+    pub fn label_of(&self, index: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, &label_index)| label_index == index)
+            .map(|(label, _)| label.as_str())
+    }
+
+    /// Returns the source line number of the instruction at the provided index, if it is known
+    pub fn line(&self, index: usize) -> Option<isize> {
+        self.lines.get(index).copied().flatten()
+    }
+
+    /// Returns the byte offset of the instruction at the provided index in the KSM file it was
+    /// loaded from, measured the same way as the file's debug section. Programs that weren't
+    /// loaded from a KSM file have no offsets.
+    pub fn offset(&self, index: usize) -> Option<usize> {
+        self.byte_range(index).map(|(start, _)| start)
+    }
+
+    /// Returns the offsets of the first and last bytes of the instruction at the provided index
+    /// in the KSM file it was loaded from, the same way the debug section stores ranges
+    pub fn byte_range(&self, index: usize) -> Option<(usize, usize)> {
+        self.ranges.get(index).copied().flatten()
+    }
+
+    /// Returns the index of the instruction at the provided byte offset in the KSM file it was
+    /// loaded from
+    pub fn index_of_offset(&self, offset: usize) -> Option<usize> {
+        self.ranges
+            .iter()
+            .position(|range| range.map(|(start, _)| start) == Some(offset))
+    }
+}
+
+/// A value on the stack of a [Vm], or stored in a variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackValue {
+    /// A regular value
+    Value(KOSValue),
+    /// A function delegate, which holds the index of the instruction the function starts at
+    Delegate(usize),
+}
+
+impl std::fmt::Display for StackValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackValue::Value(value) => value.fmt(f),
+            StackValue::Delegate(entry) => write!(f, "<delegate {}>", entry),
+        }
+    }
+}
+
+/// The functions that a [Vm] calls when a program calls something that isn't a function in the
+/// program, such as `print()`.
+pub trait Builtins {
+    /// Calls the builtin function with the provided name, as it is written in the Call
+    /// instruction, such as `print()`. The arguments are in the order they were pushed.
+    ///
+    /// Returns the value that the function returns, which should be [KOSValue::Null] for
+    /// functions that don't return anything.
+    fn call(&mut self, name: &str, args: &[KOSValue]) -> Result<KOSValue, VmError>;
+
+    /// Called by a Wait instruction. Does nothing by default.
+    fn wait(&mut self, _seconds: f64) -> Result<(), VmError> {
+        Ok(())
+    }
+}
+
+/// An implementation of [Builtins] that only provides `print()`, and records every line that
+/// is printed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputCapture {
+    lines: Vec<String>,
+}
+
+impl OutputCapture {
+    /// Creates a new OutputCapture with nothing printed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns every line that has been printed
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+impl Builtins for OutputCapture {
+    fn call(&mut self, name: &str, args: &[KOSValue]) -> Result<KOSValue, VmError> {
+        match (name, args) {
+            ("print()" | "print", [value]) => {
+                self.lines.push(value.to_kos_string());
+                Ok(KOSValue::Null)
+            }
+            ("print()" | "print", _) => Err(VmError::ArgumentMismatch),
+            _ => Err(VmError::UnknownFunction(name.to_string())),
+        }
+    }
+}
+
+/// The state of a [Vm] after executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmState {
+    /// The program has more instructions to run
+    Running,
+    /// The program has finished
+    Finished,
+}
+
+/// A function call that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    /// The instruction to continue at after the function returns
+    return_ip: usize,
+    /// The length of the stack below the function's argument marker
+    stack_base: usize,
+    /// The number of scopes that existed when the function was called
+    scope_base: usize,
+}
+
+/// A kOS virtual machine, which runs a [Program]
+#[derive(Debug, Clone)]
+pub struct Vm {
+    program: Program,
+    ip: usize,
+    stack: Vec<StackValue>,
+    scopes: Vec<HashMap<String, StackValue>>,
+    frames: Vec<Frame>,
+    steps: usize,
+    step_limit: Option<usize>,
+    finished: bool,
+}
+
+/// Converts a variable name from an instruction into the name the variable is stored under.
+/// kOS variable names aren't case sensitive, and start with a `$` when used as identifiers.
+fn variable_name(name: &str) -> String {
+    name.strip_prefix('$').unwrap_or(name).to_lowercase()
+}
+
+impl Vm {
+    /// Creates a new VM that is ready to run the provided program from its entry point
+    pub fn new(program: Program) -> Self {
+        let ip = program.entry();
+
+        Self {
+            program,
+            ip,
+            stack: Vec::new(),
+            scopes: vec![HashMap::new()],
+            frames: Vec::new(),
+            steps: 0,
+            step_limit: None,
+            finished: false,
+        }
+    }
+
+    /// Sets the maximum number of instructions [run](Self::run) will execute before returning
+    /// [VmError::StepLimit], to stop programs that never finish
+    pub fn with_step_limit(mut self, limit: usize) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Returns the maximum number of instructions [run](Self::run) will execute, if there is one
+    pub fn step_limit(&self) -> Option<usize> {
+        self.step_limit
+    }
+
+    /// Returns the program this VM is running
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Returns the index of the next instruction to execute
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Returns the number of instructions that have been executed
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns true if the program has finished
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the values on the stack, from the bottom up
+    pub fn stack(&self) -> &[StackValue] {
+        &self.stack
+    }
+
+    /// Returns the variables of each scope, from the global scope to the innermost scope
+    pub fn scopes(&self) -> &[HashMap<String, StackValue>] {
+        &self.scopes
+    }
+
+    /// Returns the number of function calls that haven't returned yet
+    pub fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the value of the variable with the provided name, as the current function sees it
+    pub fn variable(&self, name: &str) -> Option<&StackValue> {
+        let name = variable_name(name);
+
+        self.visible_scopes()
+            .find_map(|scope_index| self.scopes[scope_index].get(&name))
+    }
+
+    /// The indexes of the scopes that the current function can see, innermost first
+    fn visible_scopes(&self) -> impl Iterator<Item = usize> {
+        let scope_base = self
+            .frames
+            .last()
+            .map(|frame| frame.scope_base)
+            .unwrap_or(1);
+
+        (scope_base..self.scopes.len())
+            .rev()
+            .chain(std::iter::once(0))
+    }
+
+    fn find_scope(&self, name: &str) -> Option<usize> {
+        self.visible_scopes()
+            .find(|&scope_index| self.scopes[scope_index].contains_key(name))
+    }
+
+    fn pop_raw(&mut self) -> Result<StackValue, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow)
+    }
+
+    /// Pops a value, reading the variable it names if it is an identifier
+    fn pop(&mut self) -> Result<StackValue, VmError> {
+        let value = self.pop_raw()?;
+        self.deref(value)
+    }
+
+    fn deref(&self, value: StackValue) -> Result<StackValue, VmError> {
+        match value {
+            StackValue::Value(KOSValue::String(name)) if name.starts_with('$') => self
+                .variable(&name)
+                .cloned()
+                .ok_or(VmError::UnknownVariable(name)),
+            value => Ok(value),
+        }
+    }
+
+    fn pop_value(&mut self, opcode: Opcode) -> Result<KOSValue, VmError> {
+        match self.pop()? {
+            StackValue::Value(value) => Ok(value),
+            StackValue::Delegate(_) => Err(VmError::TypeError(opcode, String::from("Delegate"))),
+        }
+    }
+
+    fn push(&mut self, value: KOSValue) {
+        self.stack.push(StackValue::Value(value));
+    }
+
+    fn operand(&self, instr: &ProgramInstr, index: usize) -> Result<KOSValue, VmError> {
+        instr
+            .operands
+            .get(index)
+            .cloned()
+            .ok_or(VmError::InvalidOperand(self.ip))
+    }
+
+    fn int_operand(&self, instr: &ProgramInstr, index: usize) -> Result<i64, VmError> {
+        match self.operand(instr, index)? {
+            KOSValue::Byte(i) => Ok(i as i64),
+            KOSValue::Int16(i) => Ok(i as i64),
+            KOSValue::Int32(i) | KOSValue::ScalarInt(i) => Ok(i as i64),
+            _ => Err(VmError::InvalidOperand(self.ip)),
+        }
+    }
+
+    fn str_operand(&self, instr: &ProgramInstr, index: usize) -> Result<String, VmError> {
+        match self.operand(instr, index)? {
+            KOSValue::String(s) | KOSValue::StringValue(s) => Ok(s),
+            _ => Err(VmError::InvalidOperand(self.ip)),
+        }
+    }
+
+    /// Returns the index of the instruction that a branch instruction goes to
+    fn branch_destination(&self, instr: &ProgramInstr) -> Result<usize, VmError> {
+        match self.operand(instr, 0)? {
+            KOSValue::String(label) | KOSValue::StringValue(label) => self
+                .program
+                .label(&label)
+                .ok_or(VmError::UnknownLabel(label)),
+            _ => {
+                let destination = self.ip as i64 + self.int_operand(instr, 0)?;
+
+                usize::try_from(destination).map_err(|_| VmError::InvalidOperand(self.ip))
+            }
+        }
+    }
+
+    fn store(&mut self, opcode: Opcode, name: &str) -> Result<(), VmError> {
+        let value = self.pop()?;
+
+        if value == StackValue::Value(KOSValue::ArgMarker) {
+            // A function tried to read a parameter that wasn't passed
+            return Err(VmError::ArgumentMismatch);
+        }
+
+        let name = variable_name(name);
+
+        let scope_index = match opcode {
+            Opcode::Stol => self.scopes.len() - 1,
+            Opcode::Stog => 0,
+            Opcode::Stoe => self
+                .find_scope(&name)
+                .ok_or_else(|| VmError::UnknownVariable(name.clone()))?,
+            _ => self.find_scope(&name).unwrap_or(0),
+        };
+
+        self.scopes[scope_index].insert(name, value);
+
+        Ok(())
+    }
+
+    /// Returns the index of the argument marker closest to the top of the stack
+    fn arg_marker(&self) -> Result<usize, VmError> {
+        self.stack
+            .iter()
+            .rposition(|value| *value == StackValue::Value(KOSValue::ArgMarker))
+            .ok_or(VmError::ArgumentMismatch)
+    }
+
+    fn call(&mut self, instr: &ProgramInstr, builtins: &mut dyn Builtins) -> Result<(), VmError> {
+        let destination = self.str_operand(instr, 0)?;
+        let target = if destination.is_empty() {
+            self.str_operand(instr, 1)?
+        } else {
+            destination
+        };
+
+        let mut marker = self.arg_marker()?;
+
+        let entry = if target == INDIRECT_CALL {
+            // The delegate is pushed before the argument marker
+            let delegate = self
+                .stack
+                .get(marker.wrapping_sub(1))
+                .cloned()
+                .ok_or(VmError::StackUnderflow)?;
+
+            self.stack.remove(marker - 1);
+            marker -= 1;
+
+            match self.deref(delegate)? {
+                StackValue::Delegate(entry) => Some(entry),
+                StackValue::Value(value) => {
+                    return Err(VmError::TypeError(
+                        Opcode::Call,
+                        crate::operations::type_name(&value).to_string(),
+                    ))
+                }
+            }
+        } else if target.starts_with('$') {
+            match self.variable(&target) {
+                Some(StackValue::Delegate(entry)) => Some(*entry),
+                Some(StackValue::Value(value)) => {
+                    return Err(VmError::TypeError(
+                        Opcode::Call,
+                        crate::operations::type_name(value).to_string(),
+                    ))
+                }
+                None => return Err(VmError::UnknownFunction(target)),
+            }
+        } else {
+            self.program.label(&target)
+        };
+
+        match entry {
+            Some(entry) => {
+                self.frames.push(Frame {
+                    return_ip: self.ip + 1,
+                    stack_base: marker,
+                    scope_base: self.scopes.len(),
+                });
+
+                self.ip = entry;
+            }
+            None => {
+                let mut args = Vec::with_capacity(self.stack.len() - marker - 1);
+
+                for value in self.stack.split_off(marker + 1) {
+                    match self.deref(value)? {
+                        StackValue::Value(value) => args.push(value),
+                        StackValue::Delegate(_) => {
+                            return Err(VmError::TypeError(Opcode::Call, String::from("Delegate")))
+                        }
+                    }
+                }
+
+                self.stack.truncate(marker);
+
+                let result = builtins.call(&target, &args)?;
+                self.push(result);
+
+                self.ip += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn ret(&mut self, instr: &ProgramInstr) -> Result<(), VmError> {
+        let depth = self.int_operand(instr, 0)?.max(0) as usize;
+        let value = self.pop()?;
+
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => {
+                // Returning from the main program ends it
+                self.finished = true;
+                return Ok(());
+            }
+        };
+
+        let remaining = self
+            .scopes
+            .len()
+            .saturating_sub(depth)
+            .max(frame.scope_base);
+        self.scopes.truncate(remaining);
+
+        self.stack.truncate(frame.stack_base);
+        self.stack.push(value);
+
+        self.ip = frame.return_ip;
+
+        Ok(())
+    }
+
+    /// Executes the next instruction.
+    ///
+    /// If the instruction fails, the VM is left at that instruction, so it can be inspected.
+    pub fn step(&mut self, builtins: &mut dyn Builtins) -> Result<VmState, VmError> {
+        if self.finished {
+            return Ok(VmState::Finished);
+        }
+
+        let instr = match self.program.get(self.ip) {
+            Some(instr) => instr.clone(),
+            None => {
+                self.finished = true;
+                return Ok(VmState::Finished);
+            }
+        };
+
+        let opcode = instr.opcode;
+        let mut next_ip = self.ip + 1;
+
+        match opcode {
+            Opcode::Nop | Opcode::Lbrt => {}
+            // Eof stops the CPU and aborts the program's context, so nothing after it runs
+            Opcode::Eof | Opcode::Eop => {
+                self.finished = true;
+            }
+            Opcode::Push | Opcode::Pushv => {
+                let value = self.operand(&instr, 0)?;
+                self.push(value);
+            }
+            Opcode::Pop => {
+                self.pop_raw()?;
+            }
+            Opcode::Dup => {
+                let top = self.stack.last().cloned().ok_or(VmError::StackUnderflow)?;
+                self.stack.push(top);
+            }
+            Opcode::Swap => {
+                let first = self.pop_raw()?;
+                let second = self.pop_raw()?;
+                self.stack.push(first);
+                self.stack.push(second);
+            }
+            Opcode::Eval => {
+                let value = self.pop()?;
+                self.stack.push(value);
+            }
+            Opcode::Sto | Opcode::Stol | Opcode::Stog | Opcode::Stoe => {
+                let name = self.str_operand(&instr, 0)?;
+                self.store(opcode, &name)?;
+            }
+            Opcode::Uns => {
+                let name = match self.pop_raw()? {
+                    StackValue::Value(KOSValue::String(s) | KOSValue::StringValue(s)) => {
+                        variable_name(&s)
+                    }
+                    _ => return Err(VmError::TypeError(opcode, String::from("non-String"))),
+                };
+
+                if let Some(scope_index) = self.find_scope(&name) {
+                    self.scopes[scope_index].remove(&name);
+                }
+            }
+            Opcode::Exst => {
+                let exists = match self.pop_raw()? {
+                    StackValue::Value(KOSValue::String(s) | KOSValue::StringValue(s)) => {
+                        self.find_scope(&variable_name(&s)).is_some()
+                    }
+                    _ => false,
+                };
+
+                self.push(KOSValue::BoolValue(exists));
+            }
+            Opcode::Bscp => {
+                self.scopes.push(HashMap::new());
+            }
+            Opcode::Escp => {
+                let levels = self.int_operand(&instr, 0)?.max(0) as usize;
+                let scope_base = self
+                    .frames
+                    .last()
+                    .map(|frame| frame.scope_base)
+                    .unwrap_or(1);
+                let remaining = self.scopes.len().saturating_sub(levels).max(scope_base);
+
+                self.scopes.truncate(remaining);
+            }
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Pow
+            | Opcode::Cgt
+            | Opcode::Clt
+            | Opcode::Cge
+            | Opcode::Cle
+            | Opcode::Ceq
+            | Opcode::Cne
+            | Opcode::And
+            | Opcode::Or => {
+                let right = self.pop_value(opcode)?;
+                let left = self.pop_value(opcode)?;
+
+                let result = left.binary_op(opcode, &right)?;
+                self.push(result);
+            }
+            Opcode::Neg | Opcode::Bool | Opcode::Not => {
+                let value = self.pop_value(opcode)?;
+
+                let result = value.unary_op(opcode)?;
+                self.push(result);
+            }
+            Opcode::Jmp => {
+                next_ip = self.branch_destination(&instr)?;
+            }
+            Opcode::Bfa | Opcode::Btr => {
+                let value = self.pop_value(opcode)?;
+
+                if value.to_bool()? == (opcode == Opcode::Btr) {
+                    next_ip = self.branch_destination(&instr)?;
+                }
+            }
+            Opcode::Call => {
+                self.call(&instr, builtins)?;
+                next_ip = self.ip;
+            }
+            Opcode::Ret => {
+                self.ret(&instr)?;
+                next_ip = self.ip;
+            }
+            Opcode::Argb => match self.stack.last() {
+                Some(StackValue::Value(KOSValue::ArgMarker)) => {}
+                _ => return Err(VmError::ArgumentMismatch),
+            },
+            Opcode::Targ => {
+                let is_marker = matches!(
+                    self.stack.last(),
+                    Some(StackValue::Value(KOSValue::ArgMarker))
+                );
+
+                self.push(KOSValue::BoolValue(is_marker));
+            }
+            Opcode::Pdrl => {
+                let label = self.str_operand(&instr, 0)?;
+                let entry = self
+                    .program
+                    .label(&label)
+                    .ok_or(VmError::UnknownLabel(label))?;
+
+                self.stack.push(StackValue::Delegate(entry));
+            }
+            Opcode::Phdl => {
+                let entry = usize::try_from(self.int_operand(&instr, 0)?)
+                    .map_err(|_| VmError::InvalidOperand(self.ip))?;
+
+                self.stack.push(StackValue::Delegate(entry));
+            }
+            Opcode::Prl => {
+                let label = self.str_operand(&instr, 0)?;
+                let index = self
+                    .program
+                    .label(&label)
+                    .ok_or(VmError::UnknownLabel(label))?;
+
+                self.push(KOSValue::ScalarInt(index as i32));
+            }
+            Opcode::Wait => {
+                let value = self.pop_value(opcode)?;
+
+                let seconds = match value.binary_op(Opcode::Add, &KOSValue::ScalarDouble(0.0))? {
+                    KOSValue::ScalarDouble(seconds) => seconds,
+                    _ => {
+                        return Err(VmError::TypeError(
+                            opcode,
+                            crate::operations::type_name(&value).to_string(),
+                        ))
+                    }
+                };
+
+                builtins.wait(seconds)?;
+            }
+            Opcode::Tcan => {
+                // There are no triggers, so nothing is ever cancelled
+                self.push(KOSValue::BoolValue(false));
+            }
+            Opcode::Gmb
+            | Opcode::Smb
+            | Opcode::Gmet
+            | Opcode::Gidx
+            | Opcode::Sidx
+            | Opcode::Addt
+            | Opcode::Rmvt
+            | Opcode::Bogus => return Err(VmError::Unsupported(opcode)),
+        }
+
+        self.ip = next_ip;
+        self.steps += 1;
+
+        if self.ip >= self.program.len() {
+            self.finished = true;
+        }
+
+        Ok(if self.finished {
+            VmState::Finished
+        } else {
+            VmState::Running
+        })
+    }
+
+    /// Runs the program until it finishes, or an error occurs
+    pub fn run(&mut self, builtins: &mut dyn Builtins) -> Result<(), VmError> {
+        self.run_with(builtins, |_, _| ControlFlow::Continue(()))
+            .map(|_| ())
+    }
+
+    /// Runs the program like [Vm::run], calling `observer` with the VM and the index of the
+    /// instruction after every instruction that is executed.
+    ///
+    /// Returns [VmState::Running] if the observer returned [ControlFlow::Break] before the
+    /// program finished.
+    pub fn run_with(
+        &mut self,
+        builtins: &mut dyn Builtins,
+        mut observer: impl FnMut(&Vm, usize) -> ControlFlow<()>,
+    ) -> Result<VmState, VmError> {
+        let start = self.steps;
+
+        loop {
+            let index = self.ip;
+            let steps = self.steps;
+
+            let state = self.step(builtins)?;
+
+            let flow = if self.steps != steps {
+                observer(self, index)
+            } else {
+                ControlFlow::Continue(())
+            };
+
+            if state == VmState::Finished || flow.is_break() {
+                return Ok(state);
+            }
+
+            if let Some(limit) = self.step_limit {
+                if self.steps - start >= limit {
+                    return Err(VmError::StepLimit(limit));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ksm"))]
+mod tests {
+    use super::*;
+    use crate::ksm::sections::{ArgumentSection, CodeSection, CodeType, DebugSection};
+    use crate::ksm::{CodeEmitter, KSMFile};
+
+    fn run(
+        sections: Vec<CodeSection>,
+        arg_section: ArgumentSection,
+    ) -> (Vec<String>, Result<(), VmError>) {
+        let ksm = KSMFile::new_from_parts(arg_section, sections, DebugSection::new_empty());
+        let mut vm = Vm::new(Program::from_ksm(&ksm).unwrap()).with_step_limit(1000);
+        let mut output = OutputCapture::new();
+
+        let result = vm.run(&mut output);
+
+        (output.lines().to_vec(), result)
+    }
+
+    #[test]
+    fn calls_functions() {
+        let mut arg_section = ArgumentSection::new();
+        let mut function_code = CodeSection::new(CodeType::Function);
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        // function double(n) { return n * 2. }
+        let mut emitter = CodeEmitter::new(&mut function_code, &mut arg_section);
+        emitter.lbrt("double");
+        emitter.bscp(1, 0);
+        emitter.stol("$n");
+        emitter.argb();
+        emitter.push(KOSValue::String("$n".into()));
+        emitter.push(KOSValue::ScalarInt(2));
+        emitter.mul();
+        emitter.ret(1);
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push_arg_marker();
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::ScalarInt(21));
+        emitter.call("double", "");
+        emitter.call("", "print()");
+        emitter.pop();
+        emitter.eop();
+
+        assert_eq!(
+            run(vec![function_code, main_code], arg_section),
+            (vec![String::from("42")], Ok(()))
+        );
+    }
+
+    #[test]
+    fn loops_with_scopes() {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        // set i to 0. until i = 3 { local x is i. print x. set i to i + 1. }
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push(KOSValue::ScalarInt(0));
+        emitter.sto("$i");
+        emitter.push(KOSValue::String("$i".into()));
+        emitter.push(KOSValue::ScalarInt(3));
+        emitter.ceq();
+        emitter.btr(14);
+        emitter.bscp(1, 0);
+        emitter.push(KOSValue::String("$i".into()));
+        emitter.stol("$x");
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::String("$x".into()));
+        emitter.call("", "print()");
+        emitter.pop();
+        emitter.push(KOSValue::String("$i".into()));
+        emitter.push(KOSValue::ScalarInt(1));
+        emitter.add();
+        emitter.sto("$i");
+        emitter.escp(1);
+        emitter.jmp(-16);
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::String("$x".into()));
+        emitter.call("", "print()");
+        emitter.eop();
+
+        let (output, result) = run(vec![main_code], arg_section);
+
+        // The local variable doesn't exist outside of the loop's scope
+        assert_eq!(output, vec!["0", "1", "2"]);
+        assert_eq!(result, Err(VmError::UnknownVariable(String::from("$x"))));
+    }
+
+    #[test]
+    fn calls_delegates() {
+        let mut arg_section = ArgumentSection::new();
+        let mut function_code = CodeSection::new(CodeType::Function);
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        let mut emitter = CodeEmitter::new(&mut function_code, &mut arg_section);
+        emitter.lbrt("greeting");
+        emitter.argb();
+        emitter.push(KOSValue::StringValue("Hello".into()));
+        emitter.ret(0);
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.pdrl("greeting", false);
+        emitter.sto("$greet*");
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::String("$greet*".into()));
+        emitter.push_arg_marker();
+        emitter.call("", INDIRECT_CALL);
+        emitter.push(KOSValue::StringValue(", world".into()));
+        emitter.add();
+        emitter.call("", "print()");
+        emitter.pop();
+        emitter.eop();
+
+        assert_eq!(
+            run(vec![function_code, main_code], arg_section),
+            (vec![String::from("Hello, world")], Ok(()))
+        );
+    }
+
+    #[test]
+    fn stops_at_step_limit() {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.nop();
+        emitter.jmp(-1);
+
+        assert_eq!(
+            run(vec![main_code], arg_section).1,
+            Err(VmError::StepLimit(1000))
+        );
+    }
+
+    #[test]
+    fn stops_at_eof() {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::StringValue("before".into()));
+        emitter.call("", "print()");
+        emitter.pop();
+        emitter.eof();
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::StringValue("after".into()));
+        emitter.call("", "print()");
+        emitter.pop();
+        emitter.eop();
+
+        assert_eq!(
+            run(vec![main_code], arg_section),
+            (vec![String::from("before")], Ok(()))
+        );
+    }
+
+    #[test]
+    fn observes_every_instruction() {
+        let mut arg_section = ArgumentSection::new();
+        let mut main_code = CodeSection::new(CodeType::Main);
+
+        let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+        emitter.push(KOSValue::Bool(true));
+        emitter.btr(2);
+        emitter.nop();
+        emitter.nop();
+        emitter.eop();
+
+        let ksm = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+        let mut vm = Vm::new(Program::from_ksm(&ksm).unwrap());
+        let mut output = OutputCapture::new();
+        let mut executed = Vec::new();
+
+        let state = vm.run_with(&mut output, |_, index| {
+            executed.push(index);
+            ControlFlow::Continue(())
+        });
+
+        assert_eq!(state, Ok(VmState::Finished));
+        assert_eq!(executed, vec![0, 1, 3, 4]);
+
+        // Breaking stops the program after the instruction that was observed
+        let mut vm = Vm::new(Program::from_ksm(&ksm).unwrap());
+        let state = vm.run_with(&mut output, |vm, _| match vm.ip() {
+            3 => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        });
+
+        assert_eq!(state, Ok(VmState::Running));
+        assert_eq!(vm.ip(), 3);
+    }
+}
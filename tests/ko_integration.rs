@@ -241,3 +241,977 @@ fn read_kofile() {
 
     let _ko = KOFile::parse(&mut buffer_iter).expect("Error reading KO file");
 }
+
+#[test]
+fn symbol_lookup_by_name() {
+    use kerbalobjects::ko::symbols::{SymBind, SymType};
+
+    let mut ko = KOFile::new();
+
+    let mut start = ko.new_func_section("_start");
+    let mut helper = ko.new_func_section("helper");
+    let mut symtab = ko.new_symtab(".symtab");
+    let mut symstrtab = ko.new_strtab(".symstrtab");
+
+    start.add(Instr::ZeroOp(Opcode::Nop));
+    helper.add(Instr::ZeroOp(Opcode::Add));
+    helper.add(Instr::ZeroOp(Opcode::Pop));
+
+    symtab.add(KOSymbol::new(
+        symstrtab.add("_start"),
+        DataIdx::PLACEHOLDER,
+        start.size() as u16,
+        SymBind::Global,
+        SymType::Func,
+        start.section_index(),
+    ));
+    symtab.add(KOSymbol::new(
+        symstrtab.add("helper"),
+        DataIdx::PLACEHOLDER,
+        helper.size() as u16,
+        SymBind::Local,
+        SymType::Func,
+        helper.section_index(),
+    ));
+    symtab.add(KOSymbol::new(
+        symstrtab.add("print_it"),
+        DataIdx::PLACEHOLDER,
+        0,
+        SymBind::Extern,
+        SymType::Func,
+        SectionIdx::NULL,
+    ));
+
+    ko.add_func_section(start);
+    ko.add_func_section(helper);
+    ko.add_sym_tab(symtab);
+    ko.add_str_tab(symstrtab);
+
+    let helper_symbol = ko.symbol_by_name("helper").unwrap();
+    assert_eq!(helper_symbol.sym_bind, SymBind::Local);
+    assert_eq!(
+        ko.symbol_index_by_name("print_it"),
+        Some(kerbalobjects::ko::sections::SymbolIdx::from(2u32))
+    );
+    assert!(ko.symbol_by_name("missing").is_none());
+
+    let helper_section = ko.func_section_by_symbol_name("helper").unwrap();
+    assert_eq!(helper_section.instructions().count(), 2);
+    // Extern functions have no code in this file
+    assert!(ko.func_section_by_symbol_name("print_it").is_none());
+
+    let names: Vec<&str> = ko.named_symbols().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["_start", "helper", "print_it"]);
+}
+
+#[test]
+fn sections_by_index() {
+    use kerbalobjects::ko::errors::SectionAccessError;
+    use kerbalobjects::ko::sections::{Section, SectionKind, SectionMut};
+
+    let mut ko = KOFile::new();
+
+    let data_section = ko.new_data_section(".data");
+    let mut start = ko.new_func_section("_start");
+    let symstrtab = ko.new_strtab(".symstrtab");
+
+    start.add(Instr::ZeroOp(Opcode::Nop));
+
+    let data_index = data_section.section_index();
+    let start_index = start.section_index();
+
+    // Sections are added in a different order than their headers
+    ko.add_str_tab(symstrtab);
+    ko.add_func_section(start);
+    ko.add_data_section(data_section);
+
+    let kinds: Vec<SectionKind> = ko.sections().map(|section| section.kind()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            SectionKind::StrTab,
+            SectionKind::Data,
+            SectionKind::Func,
+            SectionKind::StrTab
+        ]
+    );
+
+    assert!(matches!(
+        ko.get_section(start_index),
+        Some(Section::Func(_))
+    ));
+    assert!(ko.get_section(SectionIdx::NULL).is_none());
+
+    assert_eq!(
+        ko.func_section_at(start_index)
+            .unwrap()
+            .instructions()
+            .count(),
+        1
+    );
+    assert_eq!(
+        ko.func_section_at(data_index).unwrap_err(),
+        SectionAccessError::SectionKindMismatchError(
+            u16::from(data_index),
+            SectionKind::Func,
+            SectionKind::Data
+        )
+    );
+    assert_eq!(
+        ko.data_section_at(SectionIdx::from(10u16)).unwrap_err(),
+        SectionAccessError::MissingSectionError(10)
+    );
+
+    if let Some(SectionMut::Data(data_section)) = ko.get_section_mut(data_index) {
+        data_section.add(KOSValue::Int16(1));
+    }
+
+    ko.data_section_at_mut(data_index)
+        .unwrap()
+        .add(KOSValue::Int16(2));
+
+    assert_eq!(ko.data_section_at(data_index).unwrap().data().count(), 2);
+}
+
+#[test]
+fn ko_call_graph() {
+    use kerbalobjects::analysis::dot::{CallEdge, CallGraph, CallKind};
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let greet = builder.reference_extern("greet");
+
+    let mut emitter = builder.emitter(start);
+    emitter.push_arg_marker();
+    emitter.call("", "print()");
+    emitter.push_arg_marker();
+    let call = emitter.call("", "");
+    builder.add_relocation(start, call, OperandIndex::One, greet);
+
+    let ko = builder.finish().unwrap().get();
+    let graph = CallGraph::from_ko_file(&ko);
+
+    assert_eq!(graph.functions(), &["_start".to_string()]);
+    assert_eq!(
+        graph.edges(),
+        &[
+            CallEdge {
+                caller: "_start".into(),
+                callee: "print()".into(),
+                kind: CallKind::Builtin
+            },
+            CallEdge {
+                caller: "_start".into(),
+                callee: "greet".into(),
+                kind: CallKind::Call
+            }
+        ]
+    );
+}
+
+#[test]
+fn ko_peephole() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::optimize::peephole::Peephole;
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let greet = builder.reference_extern("greet");
+
+    let mut emitter = builder.emitter(start);
+    emitter.push(KOSValue::Int16(1));
+    emitter.pop();
+    emitter.push_arg_marker();
+    let call = emitter.call("", "");
+    emitter.pop();
+    builder.add_relocation(start, call, OperandIndex::One, greet);
+
+    let mut ko = builder.finish().unwrap().get();
+
+    let report = Peephole::new().optimize_ko(&mut ko);
+
+    assert_eq!(report.rewrite_count("push-pop"), 1);
+    assert!(report.bytes_saved() > 0);
+
+    let func_section = ko.func_section_by_symbol_name("_start").unwrap();
+    assert_eq!(func_section.instructions().len(), 3);
+
+    let entry = ko
+        .reld_sections()
+        .next()
+        .unwrap()
+        .entries()
+        .next()
+        .copied()
+        .unwrap();
+    assert_eq!(usize::from(entry.instr_index), 1);
+
+    let symbol = ko.symbol_by_name("_start").unwrap();
+    assert_eq!(symbol.size as u32, func_section.size());
+
+    assert!(ko.validate().is_ok());
+}
+
+#[test]
+fn ko_peephole_keeps_relocations() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::optimize::peephole::Peephole;
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let value = builder.reference_extern("value");
+
+    // The push would be removed along with its relocation
+    let mut emitter = builder.emitter(start);
+    let push = emitter.push(KOSValue::Int16(0));
+    emitter.pop();
+    builder.add_relocation(start, push, OperandIndex::One, value);
+
+    let mut ko = builder.finish().unwrap().get();
+
+    let report = Peephole::new().optimize_ko(&mut ko);
+
+    assert_eq!(report.rewrite_count("push-pop"), 0);
+
+    let func_section = ko.func_section_by_symbol_name("_start").unwrap();
+    assert_eq!(func_section.instructions().len(), 2);
+    assert_eq!(ko.reld_sections().next().unwrap().entries().count(), 1);
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn ko_vm_links_files() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    let mut builder = KOFileBuilder::new();
+
+    let add_one = builder.define_function("add_one", SymBind::Global).unwrap();
+    builder
+        .define_value(
+            "greeting",
+            KOSValue::StringValue("The answer is ".into()),
+            SymBind::Global,
+        )
+        .unwrap();
+
+    let mut emitter = builder.emitter(add_one);
+    emitter.bscp(1, 0);
+    emitter.stol("$n");
+    emitter.argb();
+    emitter.push(KOSValue::String("$n".into()));
+    emitter.push(KOSValue::ScalarInt(1));
+    emitter.add();
+    emitter.ret(1);
+
+    let lib = builder.finish().unwrap().get();
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let add_one = builder.reference_extern("add_one");
+    let greeting = builder.reference_extern("greeting");
+
+    let mut emitter = builder.emitter(start);
+    emitter.push_arg_marker();
+    let push = emitter.push(KOSValue::Null);
+    emitter.push_arg_marker();
+    emitter.push(KOSValue::ScalarInt(41));
+    let call = emitter.call("", "");
+    emitter.add();
+    emitter.call("", "print()");
+    emitter.pop();
+    emitter.eop();
+    builder.add_relocation(start, push, OperandIndex::One, greeting);
+    builder.add_relocation(start, call, OperandIndex::One, add_one);
+
+    let main = builder.finish().unwrap().get();
+
+    let program = Program::from_ko_files(&[main, lib]).unwrap();
+    let mut vm = Vm::new(program).with_step_limit(100);
+    let mut output = OutputCapture::new();
+
+    vm.run(&mut output).unwrap();
+
+    assert_eq!(output.lines(), &["The answer is 42"]);
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn ko_vm_links_local_functions() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    // Each file calls its own local `helper`
+    let file = |entry: &str, text: &str| {
+        let mut builder = KOFileBuilder::new();
+
+        let entry = builder.define_function(entry, SymBind::Global).unwrap();
+        let helper = builder.define_function("helper", SymBind::Local).unwrap();
+
+        let mut emitter = builder.emitter(entry);
+        emitter.push_arg_marker();
+        emitter.push_arg_marker();
+        let call = emitter.call("", "");
+        emitter.call("", "print()");
+        emitter.pop();
+        builder.add_relocation(entry, call, OperandIndex::One, helper.symbol_index());
+
+        let mut emitter = builder.emitter(helper);
+        emitter.push(KOSValue::StringValue(text.into()));
+        emitter.ret(0);
+
+        builder.finish().unwrap().get()
+    };
+
+    let program = Program::from_ko_files(&[file("_init", "init"), file("_start", "start")])
+        .expect("Error loading files");
+    let mut vm = Vm::new(program).with_step_limit(100);
+    let mut output = OutputCapture::new();
+
+    vm.run(&mut output).unwrap();
+
+    assert_eq!(output.lines(), &["init", "start"]);
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_removes_unreachable_functions() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::link::{link, LinkOptions};
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    let mut builder = KOFileBuilder::new();
+    builder.set_source_file("lib.kasm");
+
+    let double = builder.define_function("double", SymBind::Global).unwrap();
+    let unused = builder.define_function("unused", SymBind::Global).unwrap();
+
+    let mut emitter = builder.emitter(double);
+    emitter.bscp(1, 0);
+    emitter.stol("$n");
+    emitter.argb();
+    emitter.push(KOSValue::String("$n".into()));
+    emitter.push(KOSValue::ScalarInt(2));
+    emitter.mul();
+    emitter.ret(1);
+
+    let mut emitter = builder.emitter(unused);
+    emitter.push(KOSValue::StringValue("unused".into()));
+    emitter.ret(0);
+
+    let lib = builder.finish().unwrap().get();
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let double = builder.reference_extern("double");
+
+    let mut emitter = builder.emitter(start);
+    emitter.push_arg_marker();
+    emitter.push_arg_marker();
+    emitter.push(KOSValue::ScalarInt(21));
+    let call = emitter.call("", "");
+    emitter.call("", "print()");
+    emitter.pop();
+    emitter.eop();
+    builder.add_relocation(start, call, OperandIndex::One, double);
+
+    let main = builder.finish().unwrap().get();
+
+    let linked = link(&[main, lib], &LinkOptions { gc_sections: true }).unwrap();
+    let removed = linked.gc_report().removed_functions();
+
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].name(), "unused");
+    assert_eq!(removed[0].source_file(), Some("lib.kasm"));
+    assert_eq!(linked.gc_report().removed_data(), 1);
+
+    let program = Program::from_ksm(linked.ksm_file()).unwrap();
+    let mut vm = Vm::new(program).with_step_limit(100);
+    let mut output = OutputCapture::new();
+
+    vm.run(&mut output).unwrap();
+
+    assert_eq!(output.lines(), &["42"]);
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_counts_every_data_section() {
+    use kerbalobjects::ko::symbols::{SymBind, SymType};
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::link::{link, LinkOptions};
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+
+    let mut emitter = builder.emitter(start);
+    emitter.push(KOSValue::StringValue("a".into()));
+    let push = emitter.push(KOSValue::StringValue("a".into()));
+    emitter.eop();
+
+    let mut ko = builder.finish().unwrap().get();
+
+    // The first value of a second data section has the same index as the first value of .data
+    let mut rodata = ko.new_data_section(".rodata");
+    let used = rodata.add(KOSValue::StringValue("c".into()));
+    rodata.add(KOSValue::StringValue("d".into()));
+    let rodata_index = rodata.section_index();
+    ko.add_data_section(rodata);
+
+    let symtab_index = ko.symtab().unwrap().section_index();
+    let symbol = ko.sym_tab_at_mut(symtab_index).unwrap().add(KOSymbol::new(
+        0usize.into(),
+        used,
+        2,
+        SymBind::Local,
+        SymType::NoType,
+        rodata_index,
+    ));
+
+    let mut reld_section = ko.new_reld_section(".reld");
+    reld_section.add(ReldEntry::new(
+        start.section_index(),
+        push,
+        OperandIndex::One,
+        symbol,
+    ));
+    ko.add_reld_section(reld_section);
+
+    let linked = link(&[ko], &LinkOptions::default()).unwrap();
+
+    // Only "d" is unused
+    assert_eq!(linked.gc_report().removed_data(), 1);
+
+    let source = linked
+        .map()
+        .arguments()
+        .iter()
+        .find(|argument| argument.value() == &KOSValue::StringValue("c".into()))
+        .and_then(|argument| argument.sources().first())
+        .unwrap();
+
+    assert_eq!(source.section_index(), rodata_index);
+    assert_eq!(source.data_index(), used);
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_map_lists_placement() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::ksm::sections::CodeType;
+    use kerbalobjects::link::{link, LinkOptions};
+
+    let mut builder = KOFileBuilder::new();
+    builder.set_source_file("lib.kasm");
+
+    let helper = builder.define_function("helper", SymBind::Global).unwrap();
+    let unused = builder.define_function("unused", SymBind::Global).unwrap();
+
+    let mut emitter = builder.emitter(helper);
+    emitter.push(KOSValue::ScalarInt(1));
+    emitter.ret(0);
+
+    builder.emitter(unused).ret(0);
+
+    let lib = builder.finish().unwrap().get();
+
+    let mut builder = KOFileBuilder::new();
+    builder.set_source_file("main.kasm");
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let helper = builder.reference_extern("helper");
+
+    let mut emitter = builder.emitter(start);
+    emitter.push(KOSValue::ScalarInt(1));
+    emitter.pop();
+    emitter.push_arg_marker();
+    let call = emitter.call("", "");
+    emitter.eop();
+    builder.add_relocation(start, call, OperandIndex::One, helper);
+
+    let main = builder.finish().unwrap().get();
+
+    let linked = link(&[main, lib], &LinkOptions { gc_sections: true }).unwrap();
+    let map = linked.map();
+
+    let offsets = linked.ksm_file().instruction_offsets();
+
+    let helper = &map.functions()[0];
+    assert_eq!(helper.name(), "helper");
+    assert_eq!(helper.source_file(), Some("lib.kasm"));
+    assert_eq!(helper.code_type(), CodeType::Function);
+    assert_eq!(helper.offset(), offsets[0][0]);
+    assert_eq!(helper.offset() + helper.size(), offsets[0][2] + 2);
+
+    let start = &map.functions()[1];
+    assert_eq!(start.name(), "_start");
+    assert_eq!(start.code_type(), CodeType::Main);
+    assert_eq!(start.offset(), offsets[2][0]);
+    assert_eq!(map.function_at(offsets[2][3]), Some(start));
+
+    // Both files push 1, so it comes from both data sections
+    let one = map
+        .arguments()
+        .iter()
+        .find(|argument| *argument.value() == KOSValue::ScalarInt(1))
+        .unwrap();
+    let sources: Vec<Option<&str>> = one.sources().iter().map(|s| s.source_file()).collect();
+    assert_eq!(sources, vec![Some("lib.kasm"), Some("main.kasm")]);
+
+    assert_eq!(map.discarded().len(), 1);
+    assert_eq!(map.discarded()[0].name(), "unused");
+
+    let text = map.to_string();
+    assert!(text.contains("helper (lib.kasm)"));
+    assert!(text.contains("Discarded:"));
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_relocation_kinds() {
+    use kerbalobjects::ko::symbols::{ReldKind, SymBind};
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::link::{link, LinkOptions};
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let skip = builder.define_function("skip", SymBind::Local).unwrap();
+    let answer = builder.define_function("answer", SymBind::Local).unwrap();
+    let delegated = builder
+        .define_function("delegated", SymBind::Local)
+        .unwrap();
+    let base = builder
+        .define_value("base", KOSValue::ScalarInt(40), SymBind::Local)
+        .unwrap();
+
+    let mut emitter = builder.emitter(start);
+    emitter.push_arg_marker();
+    emitter.push_arg_marker();
+    let call = emitter.call("", "");
+    emitter.call("", "print()");
+    emitter.pop();
+    emitter.push_arg_marker();
+    let push = emitter.push(KOSValue::Null);
+    emitter.call("", "print()");
+    emitter.pop();
+    let pdrl = emitter.pdrl("", true);
+    emitter.pop();
+    emitter.eop();
+    builder.add_relocation(start, call, OperandIndex::One, skip.symbol_index());
+    builder.add_relocation_of_kind(
+        start,
+        push,
+        OperandIndex::One,
+        base,
+        ReldKind::SymbolAddend,
+        2,
+    );
+    builder.add_relocation_of_kind(
+        start,
+        pdrl,
+        OperandIndex::One,
+        delegated.symbol_index(),
+        ReldKind::Delegate,
+        0,
+    );
+
+    // Jumps past the first instruction of `answer`
+    let jmp = builder.emitter(skip).jmp(0);
+    builder.add_relocation_of_kind(
+        skip,
+        jmp,
+        OperandIndex::One,
+        answer.symbol_index(),
+        ReldKind::Branch,
+        1,
+    );
+
+    let mut emitter = builder.emitter(answer);
+    emitter.push(KOSValue::StringValue("wrong".into()));
+    emitter.push(KOSValue::StringValue("right".into()));
+    emitter.ret(0);
+
+    builder.emitter(delegated).ret(0);
+
+    let ko = builder.finish().unwrap();
+
+    let mut buffer = Vec::new();
+    ko.write(&mut buffer);
+    let ko = KOFile::parse(&mut BufferIterator::new(&buffer)).unwrap();
+
+    let kinds: Vec<(ReldKind, i32)> = ko
+        .reld_sections()
+        .flat_map(|reld_section| reld_section.entries())
+        .map(|entry| (entry.kind, entry.addend))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (ReldKind::Symbol, 0),
+            (ReldKind::SymbolAddend, 2),
+            (ReldKind::Delegate, 0),
+            (ReldKind::Branch, 1)
+        ]
+    );
+
+    let files = [ko];
+    let linked = link(&files, &LinkOptions { gc_sections: true }).unwrap();
+
+    // Branches and delegates keep their functions
+    assert!(linked.gc_report().removed_functions().is_empty());
+
+    let programs = [
+        Program::from_ksm(linked.ksm_file()).unwrap(),
+        Program::from_ko_files(&files).unwrap(),
+    ];
+
+    for program in programs {
+        let mut vm = Vm::new(program).with_step_limit(100);
+        let mut output = OutputCapture::new();
+
+        vm.run(&mut output).unwrap();
+
+        assert_eq!(output.lines(), &["right", "42"]);
+    }
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_rejects_delegate_on_phdl() {
+    use kerbalobjects::errors::{LinkError, VmError};
+    use kerbalobjects::ko::symbols::{ReldKind, SymBind};
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::link::{link, LinkOptions};
+    use kerbalobjects::vm::Program;
+
+    let mut builder = KOFileBuilder::new();
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let delegated = builder
+        .define_function("delegated", SymBind::Local)
+        .unwrap();
+
+    let mut emitter = builder.emitter(start);
+    let phdl = emitter.phdl(0, true);
+    emitter.pop();
+    emitter.eop();
+    builder.add_relocation_of_kind(
+        start,
+        phdl,
+        OperandIndex::One,
+        delegated.symbol_index(),
+        ReldKind::Delegate,
+        0,
+    );
+
+    builder.emitter(delegated).ret(0);
+
+    let files = [builder.finish().unwrap().get()];
+
+    // Phdl takes an instruction index, not a label
+    assert!(matches!(
+        link(&files, &LinkOptions::default()),
+        Err(LinkError::InvalidRelocationError(name, 0)) if name == "_start"
+    ));
+    assert!(matches!(
+        Program::from_ko_files(&files),
+        Err(VmError::LinkError(LinkError::InvalidRelocationError(name, 0))) if name == "_start"
+    ));
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_rejects_ambiguous_local_entry() {
+    use kerbalobjects::errors::LinkError;
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::link::{link, LinkOptions};
+
+    let local_start = || {
+        let mut builder = KOFileBuilder::new();
+
+        let start = builder.define_function("_start", SymBind::Local).unwrap();
+        builder.emitter(start).eop();
+
+        builder.finish().unwrap().get()
+    };
+
+    assert!(link(&[local_start()], &LinkOptions::default()).is_ok());
+    assert!(matches!(
+        link(&[local_start(), local_start()], &LinkOptions::default()),
+        Err(LinkError::DuplicateSymbolError(name)) if name == "_start"
+    ));
+}
+
+#[test]
+fn ko_format_versions() {
+    use kerbalobjects::ko::errors::{HeaderParseError, KOParseError, WriteVersionError};
+    use kerbalobjects::ko::symbols::{ReldKind, SymBind};
+    use kerbalobjects::ko::{FormatFeature, KOFileBuilder, FILE_VERSION, MIN_FILE_VERSION};
+
+    let build = |kind: ReldKind| {
+        let mut builder = KOFileBuilder::new();
+
+        let start = builder.define_function("_start", SymBind::Global).unwrap();
+        let helper = builder.define_function("helper", SymBind::Local).unwrap();
+
+        let mut emitter = builder.emitter(start);
+        emitter.push_arg_marker();
+        let call = emitter.call("", "");
+        emitter.eop();
+        builder.add_relocation_of_kind(
+            start,
+            call,
+            OperandIndex::One,
+            helper.symbol_index(),
+            kind,
+            0,
+        );
+
+        builder.emitter(helper).ret(0);
+
+        builder.finish().unwrap()
+    };
+
+    // Files that don't use newer features can be written as older versions, and are upgraded
+    // when they are read
+    let ko = build(ReldKind::Symbol).get();
+    assert_eq!(ko.min_version(), MIN_FILE_VERSION);
+    let ko = ko.validate().unwrap();
+
+    let mut old = Vec::new();
+    ko.write_version(&mut old, MIN_FILE_VERSION).unwrap();
+    assert_eq!(old[4], MIN_FILE_VERSION);
+
+    let mut current = Vec::new();
+    ko.write(&mut current);
+    assert!(old.len() < current.len());
+
+    let upgraded = KOFile::parse(&mut BufferIterator::new(&old)).unwrap();
+    assert_eq!(upgraded.header().version, FILE_VERSION);
+
+    let mut rewritten = Vec::new();
+    upgraded.validate().unwrap().write(&mut rewritten);
+    assert_eq!(rewritten, current);
+
+    // Files from the future can't be read
+    current[4] = FILE_VERSION + 1;
+    assert!(matches!(
+        KOFile::parse(&mut BufferIterator::new(&current)),
+        Err(KOParseError::HeaderError(
+            HeaderParseError::VersionTooNewError(version, FILE_VERSION)
+        )) if version == FILE_VERSION + 1
+    ));
+
+    // Newer features can't be written as versions that don't have them
+    let ko = build(ReldKind::Delegate).get();
+    assert_eq!(
+        ko.min_version(),
+        FormatFeature::RelocationKinds.min_version()
+    );
+    let ko = ko.validate().unwrap();
+    assert_eq!(
+        ko.write_version(&mut Vec::new(), MIN_FILE_VERSION),
+        Err(WriteVersionError::UnavailableFeatureError(
+            FormatFeature::RelocationKinds,
+            MIN_FILE_VERSION
+        ))
+    );
+    assert_eq!(
+        ko.write_version(&mut Vec::new(), FILE_VERSION + 1),
+        Err(WriteVersionError::UnsupportedVersionError(
+            FILE_VERSION + 1,
+            MIN_FILE_VERSION,
+            FILE_VERSION
+        ))
+    );
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_weak_symbols() {
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ko::{FormatFeature, KOFileBuilder};
+    use kerbalobjects::link::{link, LinkOptions};
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    let printer = |builder: &mut KOFileBuilder, name: &str, bind: SymBind, text: &str| {
+        let func = builder.define_function(name, bind).unwrap();
+        let mut emitter = builder.emitter(func);
+        emitter.push_arg_marker();
+        emitter.push(KOSValue::StringValue(text.into()));
+        emitter.call("", "print()");
+        emitter.ret(0);
+    };
+
+    // The library provides defaults for both hooks
+    let mut builder = KOFileBuilder::new();
+    printer(&mut builder, "on_start", SymBind::Weak, "default start");
+    printer(&mut builder, "on_end", SymBind::Weak, "default end");
+    let lib = builder.finish().unwrap().get();
+
+    assert!(lib.uses_feature(FormatFeature::WeakSymbols));
+    assert_eq!(lib.min_version(), FormatFeature::WeakSymbols.min_version());
+
+    let mut builder = KOFileBuilder::new();
+    printer(&mut builder, "on_start", SymBind::Global, "custom start");
+
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+    let hooks = [
+        builder.reference_extern("on_start"),
+        builder.reference_extern("on_end"),
+    ];
+    let optional = builder.reference_weak("optional");
+
+    for hook in hooks {
+        let mut emitter = builder.emitter(start);
+        emitter.push_arg_marker();
+        let call = emitter.call("", "");
+        emitter.pop();
+        builder.add_relocation(start, call, OperandIndex::One, hook);
+    }
+
+    let mut emitter = builder.emitter(start);
+    emitter.push_arg_marker();
+    let push = emitter.push(KOSValue::StringValue("".into()));
+    emitter.call("", "print()");
+    emitter.pop();
+    emitter.eop();
+    builder.add_relocation(start, push, OperandIndex::One, optional);
+
+    let main = builder.finish().unwrap().get();
+    let files = [lib, main];
+
+    let linked = link(&files, &LinkOptions { gc_sections: true }).unwrap();
+    let removed = linked.gc_report().removed_functions();
+
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].name(), "on_start");
+
+    let programs = [
+        Program::from_ksm(linked.ksm_file()).unwrap(),
+        Program::from_ko_files(&files).unwrap(),
+    ];
+
+    for program in programs {
+        let mut vm = Vm::new(program).with_step_limit(100);
+        let mut output = OutputCapture::new();
+
+        vm.run(&mut output).unwrap();
+
+        assert_eq!(output.lines(), &["custom start", "default end", ""]);
+    }
+}
+
+#[test]
+#[cfg(feature = "ksm")]
+fn link_global_variables() {
+    use kerbalobjects::ko::symbols::{SymBind, SymType};
+    use kerbalobjects::ko::KOFileBuilder;
+    use kerbalobjects::link::{link, LinkOptions};
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    // The library adds its own local step to a global counter
+    let mut builder = KOFileBuilder::new();
+    let counter = builder
+        .define_variable("counter", KOSValue::ScalarInt(40), SymBind::Global)
+        .unwrap();
+    let step = builder
+        .define_variable("step", KOSValue::ScalarInt(2), SymBind::Local)
+        .unwrap();
+    let bump = builder.define_function("bump", SymBind::Global).unwrap();
+
+    let mut emitter = builder.emitter(bump);
+    let load = emitter.push(KOSValue::Null);
+    let add = emitter.push(KOSValue::Null);
+    emitter.add();
+    let store = emitter.sto("");
+    emitter.ret(0);
+
+    builder.add_relocation(bump, load, OperandIndex::One, counter);
+    builder.add_relocation(bump, add, OperandIndex::One, step);
+    builder.add_relocation(bump, store, OperandIndex::One, counter);
+
+    let lib = builder.finish().unwrap().get();
+
+    assert!(lib
+        .named_symbols()
+        .any(|(name, symbol)| name == "counter" && symbol.sym_type == SymType::Object));
+
+    // The main file has a local variable with the same name as the library's
+    let mut builder = KOFileBuilder::new();
+    let counter = builder.reference_extern("counter");
+    let step = builder
+        .define_variable(
+            "step",
+            KOSValue::StringValue("local".into()),
+            SymBind::Local,
+        )
+        .unwrap();
+    let bump = builder.reference_extern("bump");
+    let start = builder.define_function("_start", SymBind::Global).unwrap();
+
+    let mut emitter = builder.emitter(start);
+    emitter.push_arg_marker();
+    let call = emitter.call("", "");
+    emitter.pop();
+
+    builder.add_relocation(start, call, OperandIndex::One, bump);
+
+    for variable in [counter, step] {
+        let mut emitter = builder.emitter(start);
+        emitter.push_arg_marker();
+        let push = emitter.push(KOSValue::Null);
+        emitter.call("", "print()");
+        emitter.pop();
+
+        builder.add_relocation(start, push, OperandIndex::One, variable);
+    }
+
+    builder.emitter(start).eop();
+
+    let main = builder.finish().unwrap().get();
+    let files = [lib, main];
+
+    let linked = link(&files, &LinkOptions { gc_sections: true }).unwrap();
+    let arguments: Vec<&KOSValue> = linked
+        .map()
+        .arguments()
+        .iter()
+        .map(|argument| argument.value())
+        .collect();
+
+    for variable in ["$counter", "$step.0", "$step.1"] {
+        assert!(arguments.contains(&&KOSValue::String(variable.into())));
+    }
+
+    let programs = [
+        Program::from_ksm(linked.ksm_file()).unwrap(),
+        Program::from_ko_files(&files).unwrap(),
+    ];
+
+    for program in programs {
+        let mut vm = Vm::new(program).with_step_limit(100);
+        let mut output = OutputCapture::new();
+
+        vm.run(&mut output).unwrap();
+
+        assert_eq!(output.lines(), &["42", "local"]);
+    }
+}
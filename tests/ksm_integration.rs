@@ -186,3 +186,288 @@ fn read_ksm() {
 
     let _ksm = KSMFile::parse(&mut buffer_iter);
 }
+
+#[test]
+fn emit_and_read_ksm() {
+    use kerbalobjects::ksm::{CodeEmitter, KSMFile};
+
+    let mut arg_section = ArgumentSection::new();
+    let mut main_code = CodeSection::new(CodeType::Main);
+
+    let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+
+    emitter.push(KOSValue::String("@0001".into()));
+    emitter.bscp(1, 0);
+    emitter.argb();
+    emitter.push_arg_marker();
+    emitter.push(KOSValue::StringValue("Hello, world!".into()));
+    assert_eq!(emitter.call("", "print()"), 5);
+    emitter.pop();
+    emitter.escp(1);
+
+    let ksm_file = KSMFile::new_from_parts(
+        arg_section,
+        vec![
+            CodeSection::new(CodeType::Function),
+            CodeSection::new(CodeType::Initialization),
+            main_code,
+        ],
+        DebugSection::new(DebugEntry::new(1).with_range(DebugRange::new(0x06, 0x13))),
+    );
+
+    let mut file_buffer = Vec::with_capacity(2048);
+    ksm_file.write(&mut file_buffer);
+
+    let read = KSMFile::parse(&mut BufferIterator::new(&file_buffer)).expect("Error reading KSM");
+
+    let main = read.code_sections().nth(2).unwrap();
+    let instrs: Vec<&Instr> = main.instructions().collect();
+
+    assert_eq!(instrs.len(), 8);
+    // "@0001", 1, 0, ArgMarker, "Hello, world!", "", "print()"
+    assert_eq!(read.arg_section.arguments().count(), 7);
+    // The 1 used by bscp is reused by escp
+    assert_eq!(instrs[1].opcode(), Opcode::Bscp);
+    match (instrs[1], instrs[7]) {
+        (Instr::TwoOp(_, one, _), Instr::OneOp(Opcode::Escp, levels)) => assert_eq!(one, levels),
+        _ => panic!("Unexpected instructions"),
+    }
+}
+
+#[test]
+fn verify_kash_stack() {
+    use kerbalobjects::analysis::stack::verify_ksm_file;
+
+    let mut buffer = Vec::with_capacity(2048);
+    let file_path = PathBuf::from("tests").join("kash.ksm");
+    let mut file = std::fs::File::open(file_path).expect("Error opening KSM file");
+
+    file.read_to_end(&mut buffer)
+        .expect("Error reading kash.ksm");
+
+    let ksm = KSMFile::parse(&mut BufferIterator::new(&buffer)).expect("Error reading KSM file");
+
+    // The kOS compiler's output should always be balanced
+    assert_eq!(verify_ksm_file(&ksm), vec![]);
+}
+
+#[test]
+fn peephole_kash() {
+    use kerbalobjects::analysis::stack::verify_ksm_file;
+    use kerbalobjects::optimize::peephole::Peephole;
+    use kerbalobjects::optimize::{rewrite_ksm_file, OptimizeReport};
+
+    let mut buffer = Vec::with_capacity(2048);
+    let file_path = PathBuf::from("tests").join("kash.ksm");
+    let mut file = std::fs::File::open(file_path).expect("Error opening KSM file");
+
+    file.read_to_end(&mut buffer)
+        .expect("Error reading kash.ksm");
+
+    let mut ksm =
+        KSMFile::parse(&mut BufferIterator::new(&buffer)).expect("Error reading KSM file");
+
+    let peephole = Peephole::new();
+    let mut rewrites = OptimizeReport::default();
+
+    // Always rewrite, even if the file grows, so that the branch fixups are checked
+    rewrite_ksm_file(&mut ksm, |list| peephole.run(list, &mut rewrites));
+
+    assert!(rewrites.rewrite_count("not-branch") > 0);
+
+    let mut written = Vec::new();
+    ksm.write(&mut written);
+
+    let reread =
+        KSMFile::parse(&mut BufferIterator::new(&written)).expect("Error reading optimized file");
+
+    assert_eq!(verify_ksm_file(&reread), vec![]);
+}
+
+#[test]
+fn compact_kash_arguments() {
+    use kerbalobjects::analysis::stack::verify_ksm_file;
+    use kerbalobjects::optimize::args::{compact_arguments, order_arguments};
+    use kerbalobjects::optimize::peephole::Peephole;
+
+    let mut buffer = Vec::with_capacity(2048);
+    let file_path = PathBuf::from("tests").join("kash.ksm");
+    let mut file = std::fs::File::open(file_path).expect("Error opening KSM file");
+
+    file.read_to_end(&mut buffer)
+        .expect("Error reading kash.ksm");
+
+    let mut ksm =
+        KSMFile::parse(&mut BufferIterator::new(&buffer)).expect("Error reading KSM file");
+
+    Peephole::new().optimize_ksm(&mut ksm);
+    let report = compact_arguments(&mut ksm);
+
+    assert!(report.bytes_after <= report.bytes_before);
+
+    let report = order_arguments(&mut ksm);
+
+    assert!(report.bytes_after <= report.bytes_before);
+
+    let mut written = Vec::new();
+    ksm.write(&mut written);
+
+    let reread =
+        KSMFile::parse(&mut BufferIterator::new(&written)).expect("Error reading compacted file");
+
+    assert_eq!(verify_ksm_file(&reread), vec![]);
+}
+
+#[test]
+#[cfg(feature = "ko")]
+fn delink_kash() {
+    use kerbalobjects::delink::delink;
+    use kerbalobjects::ko::KOFile;
+
+    let mut buffer = Vec::with_capacity(2048);
+    let file_path = PathBuf::from("tests").join("kash.ksm");
+    let mut file = std::fs::File::open(file_path).expect("Error opening KSM file");
+
+    file.read_to_end(&mut buffer)
+        .expect("Error reading kash.ksm");
+
+    let ksm = KSMFile::parse(&mut BufferIterator::new(&buffer)).expect("Error reading KSM file");
+
+    let delinked = delink(&ksm, "kash.ksm").expect("Error delinking kash.ksm");
+
+    let ksm_instrs: usize = ksm.code_sections().map(|s| s.instructions().len()).sum();
+    let line_count: usize = delinked.functions().iter().map(|f| f.lines().len()).sum();
+
+    assert_eq!(line_count, ksm_instrs);
+    assert!(delinked
+        .functions()
+        .iter()
+        .any(|f| f.lines().iter().any(|line| line.is_some())));
+    assert_eq!(delinked.functions().last().unwrap().name(), "_start");
+
+    let mut ko_buffer = Vec::new();
+    delinked.ko_file().write(&mut ko_buffer);
+
+    let ko = KOFile::parse(&mut BufferIterator::new(&ko_buffer)).expect("Error reading KO file");
+
+    let ko_instrs: usize = ko.func_sections().map(|s| s.instructions().len()).sum();
+
+    assert_eq!(ko_instrs, ksm_instrs);
+    assert!(ko.func_section_by_symbol_name("_start").is_some());
+}
+
+#[test]
+#[cfg(feature = "ko")]
+fn delink_refuses_phdl() {
+    use kerbalobjects::delink::delink;
+    use kerbalobjects::errors::DelinkError;
+    use kerbalobjects::ksm::CodeEmitter;
+
+    let mut arg_section = ArgumentSection::new();
+    let mut main_code = CodeSection::new(CodeType::Main);
+
+    let mut emitter = CodeEmitter::new(&mut main_code, &mut arg_section);
+    emitter.push(KOSValue::Int16(0));
+    emitter.phdl(0, false);
+    emitter.eop();
+
+    let ksm = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+
+    assert!(matches!(
+        delink(&ksm, "phdl.ksm"),
+        Err(DelinkError::AbsoluteIndexError(0, 1))
+    ));
+}
+
+#[test]
+fn unchanged_rewrite_keeps_arguments() {
+    use kerbalobjects::optimize::rewrite_ksm_file;
+
+    let mut arg_section = ArgumentSection::new();
+    let mut main_code = CodeSection::new(CodeType::Main);
+
+    // Branch offsets that fit in a smaller type are stored that way by some compilers
+    let condition = arg_section.add_checked(KOSValue::Bool(true));
+    let offset = arg_section.add_checked(KOSValue::Int16(2));
+
+    main_code.add(Instr::OneOp(Opcode::Push, condition));
+    main_code.add(Instr::OneOp(Opcode::Btr, offset));
+    main_code.add(Instr::ZeroOp(Opcode::Nop));
+    main_code.add(Instr::ZeroOp(Opcode::Eop));
+
+    let mut ksm = KSMFile::new_from_parts(arg_section, vec![main_code], DebugSection::new_empty());
+    let before: Vec<KOSValue> = ksm.arg_section.arguments().cloned().collect();
+
+    rewrite_ksm_file(&mut ksm, |_| {});
+
+    let after: Vec<KOSValue> = ksm.arg_section.arguments().cloned().collect();
+
+    assert_eq!(before, after);
+}
+
+#[test]
+#[cfg(feature = "ko")]
+fn link_delinked_files() {
+    use kerbalobjects::delink::delink;
+    use kerbalobjects::ko::symbols::SymBind;
+    use kerbalobjects::ksm::CodeEmitter;
+    use kerbalobjects::link::{link, LinkOptions};
+    use kerbalobjects::vm::{OutputCapture, Program, Vm};
+
+    // Both files are compiled on their own, so their functions have the same label
+    let compile = |code_type: CodeType, text: &str| {
+        let mut arg_section = ArgumentSection::new();
+        let mut function_code = CodeSection::new(CodeType::Function);
+        let mut code = CodeSection::new(code_type);
+
+        let mut emitter = CodeEmitter::new(&mut function_code, &mut arg_section);
+        emitter.lbrt("@0001");
+        emitter.push(KOSValue::StringValue(text.into()));
+        emitter.ret(0);
+
+        let mut emitter = CodeEmitter::new(&mut code, &mut arg_section);
+        emitter.push_arg_marker();
+        emitter.push_arg_marker();
+        emitter.call("@0001", "");
+        emitter.call("", "print()");
+        emitter.pop();
+
+        if code_type == CodeType::Main {
+            emitter.eop();
+        }
+
+        KSMFile::new_from_parts(
+            arg_section,
+            vec![function_code, code],
+            DebugSection::new_empty(),
+        )
+    };
+
+    let init = delink(&compile(CodeType::Initialization, "init"), "init.ksm")
+        .unwrap()
+        .into_ko_file()
+        .get();
+    let main = delink(&compile(CodeType::Main, "main"), "main.ksm")
+        .unwrap()
+        .into_ko_file()
+        .get();
+
+    assert_eq!(
+        main.symbol_by_name("@0001").unwrap().sym_bind,
+        SymBind::Local
+    );
+    assert_eq!(
+        main.symbol_by_name("_start").unwrap().sym_bind,
+        SymBind::Global
+    );
+
+    let linked = link(&[init, main], &LinkOptions::default()).expect("Error linking files");
+
+    let program = Program::from_ksm(linked.ksm_file()).unwrap();
+    let mut vm = Vm::new(program).with_step_limit(100);
+    let mut output = OutputCapture::new();
+
+    vm.run(&mut output).unwrap();
+
+    assert_eq!(output.lines(), &["init", "main"]);
+}